@@ -1,28 +1,331 @@
 use ic_cdk_macros::*;
+use crate::exchange::to_platform_currency;
+use crate::fees::total_fees_collected;
+use crate::holdings::{holders_of, lots_for};
+use crate::market_data::get_candles;
+use crate::money::platform_currency;
 use crate::storage::*;
-use crate::types::PlatformStats;
+use crate::types::*;
+use crate::utils::{get_current_time, NANOS_PER_DAY};
+
+/// Running totals kept up to date by the modules that change them.
+pub enum Metric {
+    /// Capital raised through primary issuance.
+    ValueLocked,
+    /// Payment value of all filled orders.
+    TradingVolume,
+    /// Orders in the `Active` state. Orders past their expiry still count until
+    /// something marks them expired.
+    ActiveOrders,
+    /// Dividends paid out to holders, net of fees.
+    DividendsPaid,
+}
+
+const MAX_SNAPSHOTS_PER_QUERY: u64 = 366;
+const TRAILING_WINDOW: u64 = 365 * NANOS_PER_DAY;
 
 #[query]
 pub fn get_platform_analytics() -> PlatformStats {
-    let total_properties = PROPERTY_STORAGE.with(|storage| storage.borrow().len());
-    let total_investments = INVESTMENT_STORAGE.with(|storage| storage.borrow().len());
-    let total_users = USER_STORAGE.with(|storage| storage.borrow().len());
-    
-    let total_value_locked = PROPERTY_STORAGE.with(|storage| {
+    let platform = |amount| Money::new(amount, platform_currency());
+    PlatformStats {
+        total_properties: PROPERTY_STORAGE.with(|storage| storage.borrow().len()),
+        total_investments: INVESTMENT_STORAGE.with(|storage| storage.borrow().len()),
+        total_users: USER_STORAGE.with(|storage| storage.borrow().len()),
+        total_value_locked: platform(Amount::from_minor(metric(Metric::ValueLocked))),
+        total_trading_volume: platform(Amount::from_minor(metric(Metric::TradingVolume))),
+        active_orders: metric(Metric::ActiveOrders),
+        total_dividends_paid: platform(Amount::from_minor(metric(Metric::DividendsPaid))),
+        platform_fee_collected: platform(total_fees_collected()),
+    }
+}
+
+/// Daily snapshots for days `from_day..=to_day` (days since the Unix epoch), at
+/// most a year's worth. Days without any activity have no snapshot.
+#[query]
+pub fn get_platform_snapshots(from_day: u64, to_day: u64) -> Vec<PlatformSnapshot> {
+    let to_day = to_day.min(from_day.saturating_add(MAX_SNAPSHOTS_PER_QUERY - 1));
+    PLATFORM_SNAPSHOT_STORAGE.with(|storage| {
         storage.borrow()
-            .iter()
-            .map(|(_, property)| property.total_value)
+            .range(from_day..=to_day)
+            .map(|(_, snapshot)| snapshot)
+            .collect()
+    })
+}
+
+#[query]
+pub fn get_property_analytics(property_id: u64) -> Result<PropertyAnalytics, String> {
+    let property = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().get(&property_id)
+            .ok_or_else(|| "Property not found".to_string())
+    })?;
+
+    let now = get_current_time();
+    let since = now.saturating_sub(TRAILING_WINDOW);
+
+    let mut balances: Vec<u64> = holders_of(property_id).into_iter().map(|(_, balance)| balance).collect();
+    balances.sort_unstable();
+    let circulating: u64 = balances.iter().sum();
+    let share = |tokens: u64| Rate::ratio(tokens as i128, circulating as i128, Rounding::HalfUp);
+
+    let tokens_traded = TRADE_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, since, 0)..=(property_id, now, u64::MAX))
+            .map(|(_, trade)| trade.token_amount)
             .sum::<u64>()
     });
 
-    PlatformStats {
-        total_properties,
-        total_investments,
-        total_users,
-        total_value_locked,
-        total_trading_volume: 0,
-        active_orders: 0,
-        total_dividends_paid: 0,
-        platform_fee_collected: 0,
+    let dividends: Vec<DividendDistribution> = distributions_by_property(property_id)
+        .into_iter()
+        .filter(|distribution| matches!(distribution.kind, DistributionKind::Dividend))
+        .collect();
+    let trailing_dividends: Amount = dividends.iter()
+        .filter(|distribution| distribution.distribution_date >= since)
+        .flat_map(|distribution| distribution.recipients.iter())
+        .map(|recipient| recipient.dividend_amount)
+        .sum();
+
+    Ok(PropertyAnalytics {
+        property_id,
+        holder_count: balances.len() as u64,
+        circulating_supply: circulating,
+        largest_holder_share: share(balances.last().copied().unwrap_or(0)),
+        top_10_holder_share: share(balances.iter().rev().take(10).sum()),
+        herfindahl_index: herfindahl_index(&balances, circulating),
+        gini_coefficient: gini_coefficient(&balances, circulating),
+        tokens_traded_trailing: tokens_traded,
+        annual_turnover: share(tokens_traded),
+        average_holding_period_days: average_holding_period_days(property_id, now),
+        advertised_yield: property.rental_yield,
+        realized_yield: Rate::ratio(
+            trailing_dividends.minor_units() as i128,
+            property.total_value.minor_units() as i128,
+            Rounding::HalfUp,
+        ),
+        dividend_history: dividends.iter()
+            .map(|distribution| DividendPoint {
+                distribution_id: distribution.id,
+                distribution_date: distribution.distribution_date,
+                total_amount: distribution.total_amount,
+                per_token_amount: distribution.per_token_amount,
+            })
+            .collect(),
+        price_history: get_candles(property_id, CandleInterval::Day, since, now),
+    })
+}
+
+pub fn increment_metric(metric: Metric, amount: u64) {
+    update_metric(metric, |value| value.saturating_add(amount));
+}
+
+pub fn decrement_metric(metric: Metric, amount: u64) {
+    update_metric(metric, |value| value.saturating_sub(amount));
+}
+
+/// Overwrites today's snapshot with the current totals, so each day's snapshot
+/// ends up holding that day's closing values.
+pub fn record_snapshot() {
+    let now = get_current_time();
+    let day = now / NANOS_PER_DAY;
+    let stats = get_platform_analytics();
+
+    let volume_before_today = PLATFORM_SNAPSHOT_STORAGE.with(|storage| {
+        storage.borrow()
+            .range(..day)
+            .next_back()
+            .map(|(_, snapshot)| snapshot.total_trading_volume.amount)
+            .unwrap_or(Amount::ZERO)
+    });
+
+    let snapshot = PlatformSnapshot {
+        day,
+        total_properties: stats.total_properties,
+        total_investments: stats.total_investments,
+        total_users: stats.total_users,
+        trading_volume: Money::new(
+            stats.total_trading_volume.amount.saturating_sub(volume_before_today),
+            platform_currency(),
+        ),
+        total_value_locked: stats.total_value_locked,
+        total_trading_volume: stats.total_trading_volume,
+        active_orders: stats.active_orders,
+        total_dividends_paid: stats.total_dividends_paid,
+        platform_fee_collected: stats.platform_fee_collected,
+        recorded_at: now,
+    };
+
+    PLATFORM_SNAPSHOT_STORAGE.with(|storage| storage.borrow_mut().insert(day, snapshot));
+}
+
+/// Seeds the counters from stored records the first time this version runs, at
+/// current exchange rates. Later upgrades keep the incrementally maintained values.
+pub fn backfill_metrics() {
+    if PLATFORM_METRIC_STORAGE.with(|storage| !storage.borrow().is_empty()) {
+        return;
+    }
+
+    let value_locked: Amount = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow()
+            .iter()
+            .filter(|(_, property)| !matches!(property.status, PropertyStatus::Sold))
+            .map(|(_, property)| {
+                let raised = property.price_per_token
+                    .times(property.total_tokens - property.available_tokens)
+                    .expect("Amount overflow");
+                to_platform_currency(property.settlement_asset, raised).unwrap_or(Amount::ZERO)
+            })
+            .sum()
+    });
+    let trading_volume: Amount = TRADE_STORAGE.with(|storage| {
+        storage.borrow()
+            .iter()
+            .map(|(_, trade)| to_platform_currency(trade.quote_asset, trade.total_price).unwrap_or(Amount::ZERO))
+            .sum()
+    });
+    let now = get_current_time();
+    let active_orders = ORDER_STORAGE.with(|storage| {
+        storage.borrow()
+            .iter()
+            .filter(|(_, order)| matches!(order.status, OrderStatus::Active) && order.expires_at > now)
+            .count() as u64
+    });
+    let dividends_paid: Amount = DIVIDEND_STORAGE.with(|storage| {
+        storage.borrow()
+            .iter()
+            .filter(|(_, distribution)| matches!(distribution.kind, DistributionKind::Dividend))
+            .map(|(_, distribution)| {
+                let paid: Amount = distribution.recipients.iter()
+                    .filter(|recipient| recipient.paid)
+                    .map(|recipient| recipient.dividend_amount)
+                    .sum();
+                PROPERTY_STORAGE.with(|storage| storage.borrow().get(&distribution.property_id))
+                    .and_then(|property| to_platform_currency(property.settlement_asset, paid).ok())
+                    .unwrap_or(Amount::ZERO)
+            })
+            .sum()
+    });
+
+    increment_metric(Metric::ValueLocked, value_locked.minor_units());
+    increment_metric(Metric::TradingVolume, trading_volume.minor_units());
+    increment_metric(Metric::ActiveOrders, active_orders);
+    increment_metric(Metric::DividendsPaid, dividends_paid.minor_units());
+}
+
+/// Sum of squared percentage shares: 10000 for a single holder. Shares are taken
+/// in basis points, so the result is exact to within rounding of each share.
+fn herfindahl_index(balances: &[u64], circulating: u64) -> u64 {
+    if circulating == 0 {
+        return 0;
+    }
+    balances.iter()
+        .map(|balance| {
+            let share = Rate::ratio(*balance as i128, circulating as i128, Rounding::HalfUp).bps() as u64;
+            share * share / Rate::ONE_HUNDRED_PERCENT.bps() as u64
+        })
+        .sum()
+}
+
+/// Expects `balances` sorted ascending.
+fn gini_coefficient(balances: &[u64], circulating: u64) -> Rate {
+    let n = balances.len() as i128;
+    if circulating == 0 || balances.len() < 2 {
+        return Rate::ZERO;
+    }
+    let weighted: i128 = balances.iter()
+        .enumerate()
+        .map(|(i, balance)| (i as i128 + 1) * *balance as i128)
+        .sum();
+    let circulating = circulating as i128;
+    Rate::ratio(2 * weighted - (n + 1) * circulating, n * circulating, Rounding::HalfUp)
+}
+
+/// How long the currently outstanding tokens have been held, weighted by the
+/// remaining quantity of each open lot.
+fn average_holding_period_days(property_id: u64, now: u64) -> u64 {
+    let (tokens, token_nanos) = holders_of(property_id)
+        .into_iter()
+        .flat_map(|(owner, _)| lots_for(owner, property_id))
+        .fold((0u128, 0u128), |(tokens, token_nanos), lot| {
+            let held = now.saturating_sub(lot.acquired_at) as u128;
+            (tokens + lot.remaining_quantity as u128, token_nanos + held * lot.remaining_quantity as u128)
+        });
+    if tokens == 0 {
+        return 0;
+    }
+    (token_nanos / tokens / NANOS_PER_DAY as u128) as u64
+}
+
+fn metric(metric: Metric) -> u64 {
+    PLATFORM_METRIC_STORAGE.with(|storage| storage.borrow().get(&metric_key(&metric)).unwrap_or(0))
+}
+
+fn update_metric(metric: Metric, update: impl FnOnce(u64) -> u64) {
+    let key = metric_key(&metric);
+    PLATFORM_METRIC_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let value = storage.get(&key).unwrap_or(0);
+        storage.insert(key, update(value))
+    });
+    record_snapshot();
+}
+
+fn metric_key(metric: &Metric) -> u8 {
+    match metric {
+        Metric::ValueLocked => 0,
+        Metric::TradingVolume => 1,
+        Metric::ActiveOrders => 2,
+        Metric::DividendsPaid => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hhi_is_full_for_a_single_holder_and_falls_with_dispersion() {
+        assert_eq!(herfindahl_index(&[100], 100), 10_000);
+        assert_eq!(herfindahl_index(&[50, 50], 100), 5_000);
+        assert_eq!(herfindahl_index(&[25, 75], 100), 6_250);
+    }
+
+    #[test]
+    fn hhi_rounds_each_share_to_the_nearest_basis_point() {
+        // Each third rounds to 3 333 bps, whose square rounds down to 1 110.
+        assert_eq!(herfindahl_index(&[1, 1, 1], 3), 3_330);
+        // 2/3 rounds up to 6 667 bps, whose square rounds down to 4 444.
+        assert_eq!(herfindahl_index(&[1, 2], 3), 1_110 + 4_444);
+    }
+
+    #[test]
+    fn hhi_handles_no_supply_and_large_balances() {
+        assert_eq!(herfindahl_index(&[], 0), 0);
+        assert_eq!(herfindahl_index(&[u64::MAX], u64::MAX), 10_000);
+    }
+
+    #[test]
+    fn gini_is_zero_for_equal_holdings() {
+        assert_eq!(gini_coefficient(&[5, 5], 10), Rate::ZERO);
+        assert_eq!(gini_coefficient(&[7, 7, 7, 7], 28), Rate::ZERO);
+    }
+
+    #[test]
+    fn gini_grows_with_concentration() {
+        assert_eq!(gini_coefficient(&[1, 3], 4), Rate::from_bps(2_500));
+        assert_eq!(gini_coefficient(&[0, 0, 0, 100], 100), Rate::from_bps(7_500));
+    }
+
+    #[test]
+    fn gini_rounds_to_the_nearest_basis_point() {
+        // (2 * 14 - 4 * 6) / (3 * 6) = 2/9, i.e. 2 222.2 bps.
+        assert_eq!(gini_coefficient(&[1, 2, 3], 6), Rate::from_bps(2_222));
+        // (2 * 5 - 3 * 3) / (2 * 3) = 1/6, i.e. 1 666.7 bps.
+        assert_eq!(gini_coefficient(&[1, 2], 3), Rate::from_bps(1_667));
+    }
+
+    #[test]
+    fn gini_handles_degenerate_and_large_inputs() {
+        assert_eq!(gini_coefficient(&[], 0), Rate::ZERO);
+        assert_eq!(gini_coefficient(&[100], 100), Rate::ZERO);
+        assert_eq!(gini_coefficient(&[0, u64::MAX], u64::MAX), Rate::from_bps(5_000));
     }
 }
@@ -0,0 +1,366 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::exchange::settlement_asset;
+use crate::ledger::{prepare_transfer, send_transfer, transfer_from, TransferFailure};
+use crate::storage::{
+    get_next_id, insert_treasury_withdrawal, remove_treasury_withdrawal, CASH_BALANCE_STORAGE,
+    CASH_TRANSACTION_STORAGE, PENDING_DEPOSIT_STORAGE, PENDING_WITHDRAWAL_STORAGE, TREASURY_WITHDRAWAL_STORAGE,
+};
+use crate::types::*;
+use crate::utils::{get_current_time, is_admin, is_authenticated, paginate, Paginated};
+
+/// Moves `amount` of the asset into the caller's cash balance. The caller must
+/// first approve the canister to spend it (ICRC-2) on the asset's ledger. If the
+/// pull ends without a definite answer the deposit is left pending for
+/// `reconcile_deposit`.
+#[update]
+pub async fn deposit(asset: Principal, amount: Amount) -> Result<CashBalance, String> {
+    let caller = is_authenticated()?;
+    settlement_asset(asset)?;
+    if amount.is_zero() {
+        return Err("Deposit amount must be greater than zero".to_string());
+    }
+
+    // Recorded before the call, and so committed with it, so that no outcome of
+    // the pull leaves the funds untracked.
+    let now = get_current_time();
+    let pending = PendingDeposit {
+        id: get_next_id(),
+        owner: caller,
+        asset,
+        amount,
+        created_at_time: now,
+        last_error: String::new(),
+        last_attempt_at: now,
+    };
+    PENDING_DEPOSIT_STORAGE.with(|storage| storage.borrow_mut().insert(pending.id, pending.clone()));
+
+    let outcome = transfer_from(asset, caller, amount, pending.id, pending.created_at_time).await;
+    take_pending_deposit(pending.id)?;
+    settle_deposit(pending, outcome)?;
+
+    Ok(cash_balance(caller, asset))
+}
+
+/// Retries a pending deposit's pull with its original memo and
+/// `created_at_time`. The ledger deduplicates them, so a pull that already went
+/// through is found rather than taken twice. Owners reconcile their own
+/// deposits, admins any of them.
+#[update]
+pub async fn reconcile_deposit(id: u64) -> Result<u64, String> {
+    let caller = is_authenticated()?;
+    let pending = PENDING_DEPOSIT_STORAGE.with(|storage| storage.borrow().get(&id))
+        .ok_or_else(|| "Pending deposit not found".to_string())?;
+    if pending.owner != caller {
+        is_admin()?;
+    }
+
+    let outcome = transfer_from(pending.asset, pending.owner, pending.amount, pending.id, pending.created_at_time).await;
+    take_pending_deposit(id)?;
+    settle_deposit(pending, outcome)
+}
+
+/// Settles a pending deposit an admin has looked up on the ledger, for when
+/// retrying can no longer tell: `Some(block)` credits the owner, `None` records
+/// that the pull never happened.
+#[update]
+pub fn resolve_pending_deposit(id: u64, block_index: Option<u64>) -> Result<(), String> {
+    is_admin()?;
+    let pending = take_pending_deposit(id)?;
+    if let Some(block) = block_index {
+        credit_cash(pending.owner, pending.asset, pending.amount, CashTransactionKind::Deposit, block)
+            .unwrap_or_else(|error| ic_cdk::trap(&error));
+    }
+    Ok(())
+}
+
+#[query]
+pub fn get_pending_deposits() -> Vec<PendingDeposit> {
+    PENDING_DEPOSIT_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, pending)| pending).collect()
+    })
+}
+
+/// Sends `amount` of the caller's available balance to `to`. The ledger fee is
+/// paid out of `amount`. If the ledger rejects the transfer the balance is
+/// credited back; if the call ends without a definite answer the withdrawal is
+/// left pending for `reconcile_withdrawal`.
+#[update]
+pub async fn withdraw(asset: Principal, amount: Amount, to: Account) -> Result<CashBalance, String> {
+    let caller = is_authenticated()?;
+    settlement_asset(asset)?;
+    if amount.is_zero() {
+        return Err("Withdrawal amount must be greater than zero".to_string());
+    }
+
+    // Debit before the call so the funds can't be spent twice while it's in flight.
+    let debit = debit_cash(caller, asset, amount, CashTransactionKind::Withdrawal, 0)?;
+    let transfer = match prepare_transfer(asset, to, amount, debit.id).await {
+        Ok(transfer) => transfer,
+        Err(error) => {
+            credit_cash(caller, asset, amount, CashTransactionKind::WithdrawalRefund, debit.id)?;
+            return Err(error);
+        }
+    };
+
+    let pending = PendingWithdrawal {
+        id: debit.id,
+        owner: Some(caller),
+        debited: amount,
+        transfer,
+        last_error: String::new(),
+        last_attempt_at: get_current_time(),
+    };
+    let outcome = send_transfer(&pending.transfer).await;
+    settle_withdrawal(pending, outcome)?;
+
+    Ok(cash_balance(caller, asset))
+}
+
+/// Resends a pending withdrawal with its original ledger arguments. The ledger
+/// deduplicates them, so a transfer that already went through is found rather
+/// than paid twice. Owners reconcile their own withdrawals, admins any of them.
+#[update]
+pub async fn reconcile_withdrawal(id: u64) -> Result<u64, String> {
+    let caller = is_authenticated()?;
+    let pending = PENDING_WITHDRAWAL_STORAGE.with(|storage| storage.borrow().get(&id))
+        .ok_or_else(|| "Pending withdrawal not found".to_string())?;
+    if pending.owner != Some(caller) {
+        is_admin()?;
+    }
+
+    let outcome = send_transfer(&pending.transfer).await;
+
+    // Only the first retry to come back settles it.
+    let pending = PENDING_WITHDRAWAL_STORAGE.with(|storage| storage.borrow_mut().remove(&id))
+        .ok_or_else(|| "Withdrawal was already reconciled".to_string())?;
+    settle_withdrawal(pending, outcome)
+}
+
+/// Settles a pending withdrawal an admin has looked up on the ledger, for when
+/// retrying can no longer tell (past the ledger's deduplication window):
+/// `Some(block)` records the payout, `None` releases the funds again.
+#[update]
+pub fn resolve_pending_withdrawal(id: u64, block_index: Option<u64>) -> Result<(), String> {
+    is_admin()?;
+    let pending = PENDING_WITHDRAWAL_STORAGE.with(|storage| storage.borrow_mut().remove(&id))
+        .ok_or_else(|| "Pending withdrawal not found".to_string())?;
+    match block_index {
+        Some(block) => record_withdrawal_block(&pending, block),
+        None => release_withdrawal(&pending).unwrap_or_else(|error| ic_cdk::trap(&error)),
+    }
+    Ok(())
+}
+
+#[query]
+pub fn get_pending_withdrawals() -> Vec<PendingWithdrawal> {
+    PENDING_WITHDRAWAL_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, pending)| pending).collect()
+    })
+}
+
+/// Applies the ledger's answer to a cash or treasury withdrawal: records the
+/// block, releases the funds again on a definite rejection, or keeps the
+/// withdrawal pending when the outcome is unknown.
+pub fn settle_withdrawal(mut pending: PendingWithdrawal, outcome: Result<u64, TransferFailure>) -> Result<u64, String> {
+    match outcome {
+        Ok(block) => {
+            record_withdrawal_block(&pending, block);
+            Ok(block)
+        }
+        Err(TransferFailure::Rejected(error)) => {
+            release_withdrawal(&pending).unwrap_or_else(|error| ic_cdk::trap(&error));
+            Err(error)
+        }
+        Err(TransferFailure::Unknown(error)) => {
+            let id = pending.id;
+            pending.last_error = error.clone();
+            pending.last_attempt_at = get_current_time();
+            PENDING_WITHDRAWAL_STORAGE.with(|storage| storage.borrow_mut().insert(id, pending));
+            Err(format!("Withdrawal {} is pending reconciliation: {}", id, error))
+        }
+    }
+}
+
+fn record_withdrawal_block(pending: &PendingWithdrawal, block: u64) {
+    match pending.owner {
+        Some(owner) => {
+            let debit = CASH_TRANSACTION_STORAGE.with(|storage| storage.borrow().get(&(owner, pending.id)));
+            if let Some(debit) = debit {
+                set_reference(debit, block);
+            }
+        }
+        None => {
+            let withdrawal = TREASURY_WITHDRAWAL_STORAGE.with(|storage| storage.borrow().get(&pending.id));
+            if let Some(mut withdrawal) = withdrawal {
+                withdrawal.block_index = Some(block);
+                insert_treasury_withdrawal(withdrawal);
+            }
+        }
+    }
+}
+
+/// Gives a withdrawal that never left back to its owner, or the treasury.
+fn release_withdrawal(pending: &PendingWithdrawal) -> Result<(), String> {
+    match pending.owner {
+        Some(owner) => {
+            credit_cash(owner, pending.transfer.ledger, pending.debited, CashTransactionKind::WithdrawalRefund, pending.id)?;
+        }
+        None => {
+            remove_treasury_withdrawal(pending.id);
+        }
+    }
+    Ok(())
+}
+
+/// Applies the ledger's answer to a deposit taken out of the pending store:
+/// credits the owner once the block is known, trapping rather than keeping the
+/// funds uncredited, drops it on a definite rejection, or puts it back when the
+/// outcome is unknown.
+fn settle_deposit(mut pending: PendingDeposit, outcome: Result<u64, TransferFailure>) -> Result<u64, String> {
+    match outcome {
+        Ok(block) => {
+            credit_cash(pending.owner, pending.asset, pending.amount, CashTransactionKind::Deposit, block)
+                .unwrap_or_else(|error| ic_cdk::trap(&error));
+            Ok(block)
+        }
+        Err(TransferFailure::Rejected(error)) => Err(error),
+        Err(TransferFailure::Unknown(error)) => {
+            let id = pending.id;
+            pending.last_error = error.clone();
+            pending.last_attempt_at = get_current_time();
+            PENDING_DEPOSIT_STORAGE.with(|storage| storage.borrow_mut().insert(id, pending));
+            Err(format!("Deposit {} is pending reconciliation: {}", id, error))
+        }
+    }
+}
+
+/// Only the first caller to take a pending deposit settles it.
+fn take_pending_deposit(id: u64) -> Result<PendingDeposit, String> {
+    PENDING_DEPOSIT_STORAGE.with(|storage| storage.borrow_mut().remove(&id))
+        .ok_or_else(|| "Deposit was already reconciled".to_string())
+}
+
+/// The owner's balance in every asset they have held funds in.
+#[query]
+pub fn get_cash_balances(owner: Principal) -> Vec<CashBalance> {
+    CASH_BALANCE_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((owner, Principal::from_slice(&[]))..)
+            .take_while(|((holder, _), _)| *holder == owner)
+            .map(|(_, balance)| balance)
+            .collect()
+    })
+}
+
+#[query]
+pub fn get_cash_transactions(owner: Principal, asset: Option<Principal>, page: PageRequest) -> Page<CashTransaction> {
+    let transactions = CASH_TRANSACTION_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((owner, 0)..=(owner, u64::MAX))
+            .map(|(_, transaction)| transaction)
+            .filter(|transaction| asset.is_none_or(|asset| transaction.asset == asset))
+            .collect()
+    });
+    paginate(transactions, &page)
+}
+
+pub fn cash_balance(owner: Principal, asset: Principal) -> CashBalance {
+    CASH_BALANCE_STORAGE.with(|storage| storage.borrow().get(&(owner, asset)))
+        .unwrap_or(CashBalance {
+            owner,
+            asset,
+            available: Amount::ZERO,
+            locked: Amount::ZERO,
+        })
+}
+
+pub fn credit_cash(owner: Principal, asset: Principal, amount: Amount, kind: CashTransactionKind, reference_id: u64) -> Result<CashTransaction, String> {
+    let mut balance = cash_balance(owner, asset);
+    balance.available = balance.available.checked_add(amount)?;
+    store(balance);
+    Ok(record(owner, asset, kind, amount, reference_id))
+}
+
+pub fn debit_cash(owner: Principal, asset: Principal, amount: Amount, kind: CashTransactionKind, reference_id: u64) -> Result<CashTransaction, String> {
+    let mut balance = cash_balance(owner, asset);
+    balance.available = balance.available.checked_sub(amount)
+        .map_err(|_| "Insufficient available balance".to_string())?;
+    store(balance);
+    Ok(record(owner, asset, kind, amount, reference_id))
+}
+
+/// Moves funds from available into escrow.
+pub fn lock_cash(owner: Principal, asset: Principal, amount: Amount, reference_id: u64) -> Result<(), String> {
+    let mut balance = cash_balance(owner, asset);
+    balance.available = balance.available.checked_sub(amount)
+        .map_err(|_| "Insufficient available balance".to_string())?;
+    balance.locked = balance.locked.checked_add(amount)?;
+    store(balance);
+    record(owner, asset, CashTransactionKind::EscrowLocked, amount, reference_id);
+    Ok(())
+}
+
+/// Returns escrowed funds to available.
+pub fn unlock_cash(owner: Principal, asset: Principal, amount: Amount, reference_id: u64) -> Result<(), String> {
+    let mut balance = cash_balance(owner, asset);
+    balance.locked = balance.locked.checked_sub(amount)
+        .map_err(|_| "Insufficient locked balance".to_string())?;
+    balance.available = balance.available.checked_add(amount)?;
+    store(balance);
+    record(owner, asset, CashTransactionKind::EscrowReleased, amount, reference_id);
+    Ok(())
+}
+
+/// Pays escrowed funds away; the counterparty is credited separately.
+pub fn spend_locked_cash(owner: Principal, asset: Principal, amount: Amount, kind: CashTransactionKind, reference_id: u64) -> Result<(), String> {
+    let mut balance = cash_balance(owner, asset);
+    balance.locked = balance.locked.checked_sub(amount)
+        .map_err(|_| "Insufficient locked balance".to_string())?;
+    store(balance);
+    record(owner, asset, kind, amount, reference_id);
+    Ok(())
+}
+
+impl Paginated for CashTransaction {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn sort_key(&self, field: &SortField) -> u64 {
+        match field {
+            SortField::CreatedAt | SortField::Yield => self.timestamp,
+            SortField::Price | SortField::Value => self.amount.minor_units(),
+        }
+    }
+}
+
+fn store(balance: CashBalance) {
+    CASH_BALANCE_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((balance.owner, balance.asset), balance)
+    });
+}
+
+fn record(owner: Principal, asset: Principal, kind: CashTransactionKind, amount: Amount, reference_id: u64) -> CashTransaction {
+    let transaction = CashTransaction {
+        id: get_next_id(),
+        owner,
+        asset,
+        kind,
+        amount,
+        reference_id,
+        timestamp: get_current_time(),
+    };
+    CASH_TRANSACTION_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((owner, transaction.id), transaction.clone())
+    });
+    transaction
+}
+
+/// Points a withdrawal at its ledger block once the transfer has gone through.
+fn set_reference(mut transaction: CashTransaction, reference_id: u64) {
+    transaction.reference_id = reference_id;
+    CASH_TRANSACTION_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((transaction.owner, transaction.id), transaction)
+    });
+}
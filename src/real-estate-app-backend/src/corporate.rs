@@ -0,0 +1,167 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::holdings::{check_rescale, holders_of, rescale_holdings};
+use crate::market_data::{refresh_nav, restate_price};
+use crate::marketplace::{rescaled_orders, restate_orders};
+use crate::offering::open_offering_round;
+use crate::portfolio::{current_price, refresh_portfolio};
+use crate::presale::grant_preemptive_rights;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::{get_current_time, is_authenticated, mul_div, mul_div_exact};
+
+const NANOS_PER_HOUR: u64 = 3600 * 1_000_000_000;
+
+/// Applies a corporate action on the owner's authority. Holders can approve the
+/// same actions through a `CorporateAction` proposal instead.
+#[update]
+pub fn apply_corporate_action(property_id: u64, kind: CorporateActionKind) -> Result<CorporateAction, String> {
+    let caller = is_authenticated()?;
+    let property = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().get(&property_id)
+            .ok_or_else(|| "Property not found".to_string())
+    })?;
+
+    if caller != property.owner {
+        return Err("Only the property owner can apply corporate actions".to_string());
+    }
+    validate_corporate_action(&kind)?;
+
+    execute_corporate_action(property, kind, caller, None)
+}
+
+/// The property's corporate actions, oldest first.
+#[query]
+pub fn get_corporate_actions(property_id: u64) -> Vec<CorporateAction> {
+    CORPORATE_ACTION_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|(_, action)| action)
+            .collect()
+    })
+}
+
+pub fn validate_corporate_action(kind: &CorporateActionKind) -> Result<(), String> {
+    match kind {
+        CorporateActionKind::Split { numerator, denominator }
+            if *numerator == 0 || *denominator == 0 || numerator == denominator =>
+        {
+            Err("Split ratio must be two different non-zero numbers".to_string())
+        }
+        CorporateActionKind::Issuance { token_amount: 0, .. } => {
+            Err("Issuance must add at least one token".to_string())
+        }
+        CorporateActionKind::Issuance { rights_period_hours: Some(0), .. } => {
+            Err("Rights period must be at least an hour".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Applies the action in full or not at all: every check and restated value is
+/// worked out before anything is written, and an audit record is kept.
+pub fn execute_corporate_action(
+    mut property: Property,
+    kind: CorporateActionKind,
+    applied_by: Principal,
+    proposal_id: Option<u64>,
+) -> Result<CorporateAction, String> {
+    if !matches!(property.status, PropertyStatus::Active) {
+        return Err("Corporate actions need an active property".to_string());
+    }
+    if open_offering_round(&mut property).is_some() {
+        return Err("Finish or cancel the open offering round first".to_string());
+    }
+
+    let now = get_current_time();
+    let total_tokens_before = property.total_tokens;
+    let price_per_token_before = property.price_per_token;
+    let holders_affected;
+    let mut orders_restated = 0;
+
+    match &kind {
+        CorporateActionKind::Split { numerator, denominator } => {
+            let (numerator, denominator) = (*numerator, *denominator);
+            // Votes are weighed on balances at a snapshot, which a split would skew.
+            if proposals_by_property(property.id).iter().any(|proposal| matches!(proposal.status, ProposalStatus::Active)) {
+                return Err("Active proposals must be finalized before a split".to_string());
+            }
+
+            let scale = |quantity: u64| {
+                mul_div_exact(quantity, numerator, denominator)
+                    .ok_or_else(|| "The ratio would leave a fraction of a token".to_string())
+            };
+            let total_tokens = scale(property.total_tokens)?;
+            let available_tokens = scale(property.available_tokens)?;
+            let price_per_token = mul_div_exact(property.price_per_token.minor_units(), denominator, numerator)
+                .map(Amount::from_minor)
+                .ok_or_else(|| "The token price doesn't divide evenly at this ratio".to_string())?;
+            check_rescale(property.id, numerator, denominator)?;
+            let orders = rescaled_orders(property.id, numerator, denominator)?;
+            let market_price = current_price(property.id).share(denominator, numerator, Rounding::HalfUp)?;
+
+            holders_affected = rescale_holdings(property.id, numerator, denominator);
+            orders_restated = orders.len() as u32;
+            restate_orders(property.id, orders);
+
+            property.total_tokens = total_tokens;
+            property.available_tokens = available_tokens;
+            property.price_per_token = price_per_token;
+            property.updated_at = now;
+            insert_property(property.clone());
+            restate_price(property.id, market_price);
+        }
+        CorporateActionKind::Issuance { token_amount, rights_period_hours } => {
+            let total_tokens = property.total_tokens.checked_add(*token_amount)
+                .ok_or_else(|| "Token supply overflow".to_string())?;
+            let total_value = property.total_value.checked_add(property.price_per_token.times(*token_amount)?)?;
+            let holders = holders_of(property.id);
+
+            if let Some(hours) = rights_period_hours {
+                let circulating: u64 = holders.iter().map(|(_, balance)| balance).sum();
+                let entitlements = holders
+                    .iter()
+                    .map(|(investor, balance)| (*investor, mul_div(*balance, *token_amount, circulating)))
+                    .filter(|(_, tokens)| *tokens > 0)
+                    .map(|(investor, tokens)| Ok((investor, property.price_per_token.times(tokens)?)))
+                    .collect::<Result<Vec<(Principal, Amount)>, String>>()?;
+                let rights_end = now.saturating_add(hours.saturating_mul(NANOS_PER_HOUR));
+                grant_preemptive_rights(property.id, entitlements, rights_end, applied_by)?;
+            }
+            holders_affected = holders.len() as u32;
+
+            property.total_tokens = total_tokens;
+            property.available_tokens += token_amount;
+            property.total_value = total_value;
+            property.updated_at = now;
+            insert_property(property.clone());
+            refresh_nav(property.id);
+        }
+    }
+
+    let action = CorporateAction {
+        id: get_next_id(),
+        property_id: property.id,
+        kind,
+        proposal_id,
+        applied_by,
+        total_tokens_before,
+        total_tokens_after: property.total_tokens,
+        price_per_token_before,
+        price_per_token_after: property.price_per_token,
+        holders_affected,
+        orders_restated,
+        applied_at: now,
+    };
+    CORPORATE_ACTION_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((action.property_id, action.id), action.clone())
+    });
+
+    if matches!(action.kind, CorporateActionKind::Split { .. }) {
+        for (holder, _) in holders_of(property.id) {
+            refresh_portfolio(holder);
+        }
+    }
+
+    Ok(action)
+}
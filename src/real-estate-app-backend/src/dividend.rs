@@ -0,0 +1,142 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::analytics::{increment_metric, Metric};
+use crate::cash::{credit_cash, debit_cash};
+use crate::exchange::to_platform_currency;
+use crate::fees::{collect_fee, fee_for};
+use crate::holdings::holders_of;
+use crate::portfolio::refresh_portfolio;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::{get_current_time, is_authenticated};
+
+/// Pays `total_amount` out to current holders pro rata. Only the property's owner
+/// or manager can distribute outside of governance.
+#[update]
+pub fn distribute_dividends(property_id: u64, total_amount: Amount) -> Result<DividendDistribution, String> {
+    let caller = is_authenticated()?;
+
+    let property = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().get(&property_id)
+            .ok_or_else(|| "Property not found".to_string())
+    })?;
+
+    if caller != property.owner && caller != property.manager {
+        return Err("Only the property owner or manager can distribute dividends".to_string());
+    }
+
+    run_distribution(property_id, total_amount, DistributionKind::Dividend, None)
+}
+
+#[query]
+pub fn get_property_distributions(property_id: u64) -> Vec<DividendDistribution> {
+    distributions_by_property(property_id)
+}
+
+/// Splits `total_amount` across holders by balance, after the management fee on
+/// regular dividends. Sale proceeds are split over every token instead, so the
+/// unsold tokens' share stays with the owner. The owner's cash balance funds the
+/// payout. Each share rounds down, so a few units of dust may stay with the owner.
+pub fn run_distribution(
+    property_id: u64,
+    total_amount: Amount,
+    kind: DistributionKind,
+    proposal_id: Option<u64>,
+) -> Result<DividendDistribution, String> {
+    if total_amount.is_zero() {
+        return Err("Distribution amount must be greater than zero".to_string());
+    }
+
+    let holders = holders_of(property_id);
+    let circulating: u64 = holders.iter().map(|(_, balance)| balance).sum();
+    if circulating == 0 {
+        return Err("Property has no token holders".to_string());
+    }
+
+    let management_fee = match kind {
+        DistributionKind::Dividend => fee_for(&FeeSource::DividendManagement, property_id, total_amount)?,
+        DistributionKind::SaleProceeds => Amount::ZERO,
+    };
+    let distributable = total_amount.checked_sub(management_fee)?;
+    let property = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .ok_or_else(|| "Property not found".to_string())?;
+    let tokens = match kind {
+        DistributionKind::Dividend => circulating,
+        DistributionKind::SaleProceeds => property.total_tokens.max(circulating),
+    };
+
+    let recipients = holders
+        .iter()
+        .map(|(investor, balance)| {
+            Ok(DividendRecipient {
+                investor: *investor,
+                token_amount: *balance,
+                dividend_amount: distributable.share(*balance, tokens, Rounding::Down)?,
+                paid: false,
+            })
+        })
+        .collect::<Result<Vec<DividendRecipient>, String>>()?;
+    let paid: Amount = recipients.iter().map(|recipient| recipient.dividend_amount).sum();
+    let paid_in_platform_currency = to_platform_currency(property.settlement_asset, paid)?;
+    let distribution_id = get_next_id();
+
+    let mut distribution = DividendDistribution {
+        id: distribution_id,
+        property_id,
+        kind,
+        proposal_id,
+        total_amount,
+        management_fee,
+        per_token_amount: distributable.per_unit(tokens, Rounding::Down)?,
+        distribution_date: get_current_time(),
+        payment_status: PaymentStatus::Pending,
+        recipients,
+    };
+
+    // A recipient is marked paid only once their share is in their cash account.
+    // The owner's debit is the only step that can fail cleanly; once it has gone
+    // through, a failed credit traps so the whole payout rolls back.
+    debit_cash(
+        property.owner,
+        property.settlement_asset,
+        paid.checked_add(management_fee)?,
+        CashTransactionKind::DistributionFunding,
+        distribution_id,
+    )?;
+    for recipient in distribution.recipients.iter_mut() {
+        if !recipient.dividend_amount.is_zero() {
+            credit_cash(
+                recipient.investor,
+                property.settlement_asset,
+                recipient.dividend_amount,
+                CashTransactionKind::DistributionReceived,
+                distribution_id,
+            )
+            .unwrap_or_else(|error| ic_cdk::trap(&error));
+        }
+        recipient.paid = true;
+    }
+    distribution.payment_status = PaymentStatus::Completed;
+
+    insert_distribution(distribution.clone());
+    if matches!(distribution.kind, DistributionKind::Dividend) {
+        increment_metric(Metric::DividendsPaid, paid_in_platform_currency.minor_units());
+    }
+
+    collect_fee(
+        FeeSource::DividendManagement,
+        property_id,
+        property.settlement_asset,
+        property.owner,
+        total_amount,
+        management_fee,
+        distribution.id,
+    );
+
+    let investors: Vec<Principal> = holders.into_iter().map(|(investor, _)| investor).collect();
+    for investor in investors {
+        refresh_portfolio(investor);
+    }
+
+    Ok(distribution)
+}
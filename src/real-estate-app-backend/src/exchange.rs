@@ -0,0 +1,194 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::money::{mul_div_rounded, platform_currency, PLATFORM_CURRENCY_CODE};
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::{get_current_time, is_admin};
+
+/// `ExchangeRate::rate` has eight decimal places.
+const RATE_SCALE: u64 = 100_000_000;
+const MAX_DECIMALS: u8 = 18;
+const MAX_CODE_CHARS: usize = 10;
+
+/// Makes an asset available for pricing properties and quoting orders. `rate` is
+/// its price in the platform currency; every asset needs one so platform totals
+/// can be reported in a single currency.
+#[update]
+pub fn register_settlement_asset(asset: SettlementAsset, rate: u64) -> Result<SettlementAsset, String> {
+    let caller = is_admin()?;
+    add_settlement_asset(asset, rate, caller)
+}
+
+/// `register_settlement_asset` without the caller check, for upgrades.
+pub fn add_settlement_asset(asset: SettlementAsset, rate: u64, caller: Principal) -> Result<SettlementAsset, String> {
+    validate_currency(&asset.currency())?;
+
+    if SETTLEMENT_ASSET_STORAGE.with(|storage| storage.borrow().contains_key(&asset.ledger)) {
+        return Err("Settlement asset already registered".to_string());
+    }
+    if code_in_use(&asset.symbol) {
+        return Err("An asset or currency with this code already exists".to_string());
+    }
+
+    store_rate(&asset.symbol, rate, caller)?;
+    SETTLEMENT_ASSET_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(asset.ledger, asset.clone())
+    });
+
+    Ok(asset)
+}
+
+#[query]
+pub fn get_settlement_assets() -> Vec<SettlementAsset> {
+    SETTLEMENT_ASSET_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, asset)| asset).collect()
+    })
+}
+
+/// Adds a currency investors can value their portfolio in. `rate` is its price
+/// in the platform currency.
+#[update]
+pub fn register_display_currency(currency: Currency, rate: u64) -> Result<Currency, String> {
+    let caller = is_admin()?;
+    validate_currency(&currency)?;
+
+    if code_in_use(&currency.code) {
+        return Err("An asset or currency with this code already exists".to_string());
+    }
+
+    store_rate(&currency.code, rate, caller)?;
+    CURRENCY_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(currency.code.clone(), currency.clone())
+    });
+
+    Ok(currency)
+}
+
+/// The platform currency first, then every registered display currency.
+#[query]
+pub fn get_display_currencies() -> Vec<Currency> {
+    let mut currencies = vec![platform_currency()];
+    CURRENCY_STORAGE.with(|storage| {
+        currencies.extend(storage.borrow().iter().map(|(_, currency)| currency))
+    });
+    currencies
+}
+
+/// Updates the rate of a registered asset or display currency. The platform
+/// currency is always worth exactly one.
+#[update]
+pub fn set_exchange_rate(code: String, rate: u64) -> Result<ExchangeRate, String> {
+    let caller = is_admin()?;
+
+    if code == PLATFORM_CURRENCY_CODE {
+        return Err("The platform currency rate is fixed".to_string());
+    }
+    if !code_in_use(&code) {
+        return Err("Unknown asset or currency".to_string());
+    }
+
+    store_rate(&code, rate, caller)
+}
+
+#[query]
+pub fn get_exchange_rates() -> Vec<ExchangeRate> {
+    EXCHANGE_RATE_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, rate)| rate).collect()
+    })
+}
+
+pub fn settlement_asset(ledger: Principal) -> Result<SettlementAsset, String> {
+    SETTLEMENT_ASSET_STORAGE.with(|storage| storage.borrow().get(&ledger))
+        .ok_or_else(|| "Unknown settlement asset".to_string())
+}
+
+/// A registered display currency, or the platform currency.
+pub fn display_currency(code: &str) -> Result<Currency, String> {
+    if code == PLATFORM_CURRENCY_CODE {
+        return Ok(platform_currency());
+    }
+    CURRENCY_STORAGE.with(|storage| storage.borrow().get(&code.to_string()))
+        .ok_or_else(|| "Unknown display currency".to_string())
+}
+
+/// Converts through the platform currency at the current rates, rounding to the
+/// nearest minor unit of `to`.
+pub fn convert(amount: Amount, from: &Currency, to: &Currency) -> Result<Amount, String> {
+    if from.code == to.code {
+        return Ok(amount);
+    }
+
+    let from_rate = rate_of(&from.code)? as u128;
+    let to_rate = rate_of(&to.code)? as u128;
+    let minor = amount.minor_units() as u128;
+    let converted = if to.decimals >= from.decimals {
+        let scale = 10u128.pow((to.decimals - from.decimals) as u32);
+        minor.checked_mul(scale)
+            .and_then(|scaled| mul_div_rounded(scaled, from_rate, to_rate, Rounding::HalfUp))
+    } else {
+        let scale = 10u128.pow((from.decimals - to.decimals) as u32);
+        to_rate.checked_mul(scale)
+            .and_then(|denominator| mul_div_rounded(minor, from_rate, denominator, Rounding::HalfUp))
+    };
+
+    converted
+        .and_then(|value| u64::try_from(value).ok())
+        .map(Amount::from_minor)
+        .ok_or_else(|| "Amount overflow".to_string())
+}
+
+/// An amount of the asset at `ledger`, in the platform currency.
+pub fn to_platform_currency(ledger: Principal, amount: Amount) -> Result<Amount, String> {
+    convert(amount, &settlement_asset(ledger)?.currency(), &platform_currency())
+}
+
+/// A platform currency amount, in the asset at `ledger`.
+pub fn from_platform_currency(ledger: Principal, amount: Amount) -> Result<Amount, String> {
+    convert(amount, &platform_currency(), &settlement_asset(ledger)?.currency())
+}
+
+fn rate_of(code: &str) -> Result<u64, String> {
+    if code == PLATFORM_CURRENCY_CODE {
+        return Ok(RATE_SCALE);
+    }
+    EXCHANGE_RATE_STORAGE.with(|storage| storage.borrow().get(&code.to_string()))
+        .map(|rate| rate.rate)
+        .ok_or_else(|| format!("No exchange rate for {}", code))
+}
+
+fn store_rate(code: &str, rate: u64, caller: Principal) -> Result<ExchangeRate, String> {
+    if rate == 0 {
+        return Err("Exchange rate must be greater than zero".to_string());
+    }
+
+    let rate = ExchangeRate {
+        code: code.to_string(),
+        rate,
+        updated_by: caller,
+        updated_at: get_current_time(),
+    };
+    EXCHANGE_RATE_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(rate.code.clone(), rate.clone())
+    });
+
+    Ok(rate)
+}
+
+/// Asset symbols and currency codes share one namespace, since rates are keyed by code.
+fn code_in_use(code: &str) -> bool {
+    code == PLATFORM_CURRENCY_CODE
+        || EXCHANGE_RATE_STORAGE.with(|storage| storage.borrow().contains_key(&code.to_string()))
+}
+
+fn validate_currency(currency: &Currency) -> Result<(), String> {
+    if currency.code.is_empty()
+        || currency.code.len() > MAX_CODE_CHARS
+        || !currency.code.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(format!("Code must be 1-{} letters or digits", MAX_CODE_CHARS));
+    }
+    if currency.decimals > MAX_DECIMALS {
+        return Err(format!("Decimals must be at most {}", MAX_DECIMALS));
+    }
+    Ok(())
+}
@@ -0,0 +1,270 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::cash::settle_withdrawal;
+use crate::exchange::{from_platform_currency, get_settlement_assets, to_platform_currency};
+use crate::ledger::{prepare_transfer, send_transfer};
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+
+const PLATFORM_FEE_KEY: u64 = 0;
+const FEE_SOURCES: [FeeSource; 5] = [
+    FeeSource::Issuance,
+    FeeSource::Maker,
+    FeeSource::Taker,
+    FeeSource::DividendManagement,
+    FeeSource::Listing,
+];
+
+#[update]
+pub fn set_fee_config(config: FeeConfig) -> Result<(), String> {
+    is_admin()?;
+    validate_fee_config(&config)?;
+    FEE_CONFIG_STORAGE.with(|storage| storage.borrow_mut().insert(PLATFORM_FEE_KEY, config));
+    Ok(())
+}
+
+/// Sets fees for one property, replacing the platform defaults entirely. `None`
+/// removes the override.
+#[update]
+pub fn set_property_fee_override(property_id: u64, config: Option<FeeConfig>) -> Result<(), String> {
+    is_admin()?;
+
+    if PROPERTY_STORAGE.with(|storage| !storage.borrow().contains_key(&property_id)) {
+        return Err("Property not found".to_string());
+    }
+
+    match config {
+        Some(config) => {
+            validate_fee_config(&config)?;
+            FEE_CONFIG_STORAGE.with(|storage| storage.borrow_mut().insert(property_id, config));
+        }
+        None => {
+            FEE_CONFIG_STORAGE.with(|storage| storage.borrow_mut().remove(&property_id));
+        }
+    }
+
+    Ok(())
+}
+
+/// The fees that apply to a property, or the platform defaults when `property_id` is `None`.
+#[query]
+pub fn get_fee_config(property_id: Option<u64>) -> FeeConfig {
+    fee_config(property_id.unwrap_or(PLATFORM_FEE_KEY))
+}
+
+/// Fees collected, optionally narrowed to one property and/or source.
+#[query]
+pub fn get_fee_ledger(property_id: Option<u64>, source: Option<FeeSource>, page: PageRequest) -> Page<FeeEntry> {
+    fee_entries_page(property_id, source.as_ref(), &page)
+}
+
+/// One treasury per settlement asset; fees are kept in the asset they were paid in.
+#[query]
+pub fn get_fee_summary() -> Vec<FeeSummary> {
+    get_settlement_assets()
+        .into_iter()
+        .map(|asset| fee_summary(asset.ledger))
+        .collect()
+}
+
+/// Transfers `amount` of the asset's treasury to `recipient` on its ledger. The
+/// ledger fee is paid out of `amount`. A transfer with an unknown outcome stays
+/// reserved and pending for `reconcile_withdrawal`.
+#[update]
+pub async fn withdraw_fees(asset: Principal, amount: Amount, recipient: Principal) -> Result<TreasuryWithdrawal, String> {
+    let caller = is_admin()?;
+
+    if amount.is_zero() {
+        return Err("Withdrawal amount must be greater than zero".to_string());
+    }
+    if amount > fee_summary(asset).treasury_balance {
+        return Err("Insufficient treasury balance".to_string());
+    }
+
+    let mut withdrawal = TreasuryWithdrawal {
+        id: get_next_id(),
+        asset,
+        amount,
+        recipient,
+        withdrawn_by: caller,
+        timestamp: get_current_time(),
+        block_index: None,
+    };
+
+    // Recorded before the call so the balance stays reserved while it's in flight.
+    insert_treasury_withdrawal(withdrawal.clone());
+
+    let to = Account { owner: recipient, subaccount: None };
+    let transfer = match prepare_transfer(asset, to, amount, withdrawal.id).await {
+        Ok(transfer) => transfer,
+        Err(error) => {
+            remove_treasury_withdrawal(withdrawal.id);
+            return Err(error);
+        }
+    };
+
+    let pending = PendingWithdrawal {
+        id: withdrawal.id,
+        owner: None,
+        debited: amount,
+        transfer,
+        last_error: String::new(),
+        last_attempt_at: get_current_time(),
+    };
+    let outcome = send_transfer(&pending.transfer).await;
+    withdrawal.block_index = Some(settle_withdrawal(pending, outcome)?);
+
+    Ok(withdrawal)
+}
+
+#[query]
+pub fn get_treasury_withdrawals(asset: Option<Principal>, page: PageRequest) -> Page<TreasuryWithdrawal> {
+    treasury_withdrawals_page(asset, &page)
+}
+
+/// The fee `source` would charge on `base_amount` for this property. Rounds down,
+/// in the payer's favour. The flat listing fee is converted into the property's
+/// settlement asset.
+pub fn fee_for(source: &FeeSource, property_id: u64, base_amount: Amount) -> Result<Amount, String> {
+    let config = fee_config(property_id);
+    let rate = match source {
+        FeeSource::Issuance => config.issuance_fee,
+        FeeSource::Maker => config.maker_fee,
+        FeeSource::Taker => config.taker_fee,
+        FeeSource::DividendManagement => config.dividend_management_fee,
+        FeeSource::Listing => {
+            let asset = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id))
+                .map(|property| property.settlement_asset)
+                .ok_or_else(|| "Property not found".to_string())?;
+            return listing_fee(property_id, asset);
+        }
+    };
+    base_amount.apply_rate(rate, Rounding::Down)
+}
+
+/// The flat listing fee in `asset`, for properties not stored yet.
+pub fn listing_fee(property_id: u64, asset: Principal) -> Result<Amount, String> {
+    from_platform_currency(asset, fee_config(property_id).listing_fee)
+}
+
+/// Books a fee already deducted at settlement into the ledger and the treasury
+/// totals of `asset`.
+pub fn collect_fee(
+    source: FeeSource,
+    property_id: u64,
+    asset: Principal,
+    payer: Principal,
+    base_amount: Amount,
+    amount: Amount,
+    reference_id: u64,
+) {
+    if amount.is_zero() {
+        return;
+    }
+
+    let key = (asset, fee_source_key(&source));
+    let entry = FeeEntry {
+        id: get_next_id(),
+        source,
+        property_id,
+        asset,
+        payer,
+        base_amount,
+        amount,
+        reference_id,
+        timestamp: get_current_time(),
+    };
+
+    insert_fee_entry(entry);
+    FEE_TOTAL_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let collected = storage.get(&key).unwrap_or(Amount::ZERO);
+        storage.insert(key, collected.checked_add(amount).expect("Fee total overflow"))
+    });
+}
+
+/// Fees collected across every asset, in the platform currency at current rates.
+pub fn total_fees_collected() -> Amount {
+    FEE_TOTAL_STORAGE.with(|storage| {
+        storage.borrow()
+            .iter()
+            .map(|((asset, _), amount)| to_platform_currency(asset, amount).unwrap_or(Amount::ZERO))
+            .sum()
+    })
+}
+
+fn fee_summary(asset: Principal) -> FeeSummary {
+    let by_source: Vec<FeeSourceTotal> = FEE_SOURCES
+        .iter()
+        .map(|source| FeeSourceTotal {
+            source: source.clone(),
+            amount: FEE_TOTAL_STORAGE.with(|storage| {
+                storage.borrow().get(&(asset, fee_source_key(source))).unwrap_or(Amount::ZERO)
+            }),
+        })
+        .collect();
+    let total_collected: Amount = by_source.iter().map(|total| total.amount).sum();
+    let total_withdrawn = treasury_withdrawn(asset);
+
+    FeeSummary {
+        asset,
+        total_collected,
+        total_withdrawn,
+        treasury_balance: total_collected.saturating_sub(total_withdrawn),
+        by_source,
+    }
+}
+
+fn fee_config(key: u64) -> FeeConfig {
+    FEE_CONFIG_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        storage.get(&key).or_else(|| storage.get(&PLATFORM_FEE_KEY))
+    })
+    .unwrap_or(FeeConfig {
+        issuance_fee: Rate::from_bps(100),
+        maker_fee: Rate::from_bps(10),
+        taker_fee: Rate::from_bps(20),
+        dividend_management_fee: Rate::from_bps(100),
+        listing_fee: Amount::ZERO,
+    })
+}
+
+fn validate_fee_config(config: &FeeConfig) -> Result<(), String> {
+    let rates = [
+        config.issuance_fee,
+        config.maker_fee,
+        config.taker_fee,
+        config.dividend_management_fee,
+    ];
+    if rates.iter().any(|rate| *rate < Rate::ZERO || *rate >= Rate::ONE_HUNDRED_PERCENT) {
+        return Err("Fee rates must be between 0 and 10000 basis points".to_string());
+    }
+    Ok(())
+}
+
+impl Paginated for FeeEntry {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn sort_key(&self, field: &SortField) -> u64 {
+        match field {
+            SortField::CreatedAt | SortField::Yield => self.timestamp,
+            SortField::Price | SortField::Value => self.amount.minor_units(),
+        }
+    }
+}
+
+impl Paginated for TreasuryWithdrawal {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn sort_key(&self, field: &SortField) -> u64 {
+        match field {
+            SortField::CreatedAt | SortField::Yield => self.timestamp,
+            SortField::Price | SortField::Value => self.amount.minor_units(),
+        }
+    }
+}
@@ -1,3 +1,8 @@
+use crate::corporate::{execute_corporate_action, validate_corporate_action};
+use crate::dividend::run_distribution;
+use crate::redemption::redeem_property;
+use crate::holdings::{balance_at, lock_tokens, record_disposal, token_balance, unlock_tokens};
+use crate::portfolio::refresh_portfolio;
 use crate::storage::*;
 use crate::types::*;
 use crate::utils::*;
@@ -5,46 +10,146 @@ use candid::Principal;
 use ic_cdk::api::time;
 use ic_cdk_macros::*;
 
+/// Delay between a proposal passing and its action becoming executable, giving
+/// holders who voted against time to exit.
+const EXECUTION_TIMELOCK_NANOS: u64 = 48 * NANOS_PER_HOUR;
+
+const NANOS_PER_HOUR: u64 = 3600 * 1_000_000_000;
+
+/// Bounds on how long a proposal stays open for voting, so holders always get a
+/// full day to vote and deposits aren't locked for more than a month.
+const MIN_VOTING_HOURS: u64 = 24;
+const MAX_VOTING_HOURS: u64 = 30 * 24;
+
+const MAX_LINK_LENGTH: usize = 512;
+
+/// Opens a proposal. The proposer needs a minimum stake and locks a deposit that
+/// is slashed if turnout misses quorum. Voting opens after the review period, during
+/// which the proposer may amend the proposal.
 #[update]
-pub fn create_proposal(
-    property_id: u64,
-    title: String,
-    description: String,
-    proposal_type: ProposalType,
-    voting_duration_hours: u64,
-) -> Result<GovernanceProposal, String> {
+pub fn create_proposal(payload: CreateProposalPayload) -> Result<GovernanceProposal, String> {
     let caller = is_authenticated()?;
     validate_kyc(caller)?;
 
-    // Verify caller has tokens in the property
-    let user_tokens = get_user_token_balance(caller, property_id)?;
-    if user_tokens == 0 {
-        return Err("Must own tokens to create proposals".to_string());
+    let current_time = time();
+    // Voting power is frozen at creation, so nobody can acquire tokens after seeing
+    // the proposal, and the proposer can't pick a past moment that favours them.
+    let snapshot_at = current_time;
+
+    let property = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().get(&payload.property_id)
+            .ok_or_else(|| "Property not found".to_string())
+    })?;
+    if matches!(property.status, PropertyStatus::Sold) {
+        return Err("Property has been sold".to_string());
+    }
+
+    let config = governance_config();
+    validate_content(&config, &payload.title, &payload.description, &payload.discussion_links, &payload.document_hashes)?;
+    validate_action(&payload.action)?;
+    let proposal_type = action_type(&payload.action);
+    let (voting_starts_at, voting_ends_at) =
+        voting_window(current_time, config.review_period_hours, payload.voting_duration_hours)?;
+
+    let eligible_supply = property.total_tokens - property.available_tokens;
+    let holding = token_balance(caller, payload.property_id);
+    if holding == 0 || (holding as u128) * (BASIS_POINTS as u128) < eligible_supply as u128 * config.min_proposer_bps as u128 {
+        return Err("Not enough tokens to create proposals for this property".to_string());
     }
 
+    // Proposals whose vote has ended but nobody has finalized yet don't hold a slot.
+    let active = proposals_by_property(payload.property_id)
+        .iter()
+        .filter(|proposal| matches!(proposal.status, ProposalStatus::Active) && current_time <= proposal.voting_ends_at)
+        .count();
+    if active >= config.max_active_proposals as usize {
+        return Err("Too many active proposals for this property".to_string());
+    }
+
+    let deposit = if config.deposit_bps == 0 {
+        0
+    } else {
+        mul_div(eligible_supply, config.deposit_bps as u64, BASIS_POINTS).max(1)
+    };
+    lock_tokens(caller, payload.property_id, deposit)?;
+
+    // Thresholds are fixed when the proposal opens so later config changes can't
+    // move the goalposts mid-vote.
+    let thresholds = proposal_thresholds(&proposal_type);
+
     let proposal_id = get_next_id();
-    let current_time = time();
-    let voting_ends_at = current_time + (voting_duration_hours * 3600 * 1_000_000_000);
 
     let proposal = GovernanceProposal {
         id: proposal_id,
-        property_id,
+        property_id: payload.property_id,
         proposer: caller,
-        title,
-        description,
+        title: payload.title,
+        description: payload.description,
         proposal_type,
-        voting_power_required: calculate_required_voting_power(property_id)?,
+        action: payload.action,
+        voting_power_required: mul_div(eligible_supply, thresholds.quorum_bps as u64, BASIS_POINTS),
+        approval_threshold_bps: thresholds.approval_bps,
+        eligible_supply,
         votes_for: 0,
         votes_against: 0,
         status: ProposalStatus::Active,
+        outcome: None,
         created_at: current_time,
+        snapshot_at,
         voting_ends_at,
+        finalized_at: None,
+        executable_at: None,
+        executed_at: None,
+        deposit,
+        deposit_status: DepositStatus::Locked,
+        discussion_links: payload.discussion_links,
+        document_hashes: payload.document_hashes,
+        amendments: Vec::new(),
+        voting_starts_at,
     };
 
-    PROPOSAL_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(proposal_id, proposal.clone())
+    insert_proposal(proposal.clone());
+
+    Ok(proposal)
+}
+
+/// Replaces a proposal's content while it is still in review. The superseded
+/// version is kept in `amendments`.
+#[update]
+pub fn amend_proposal(proposal_id: u64, payload: AmendProposalPayload) -> Result<GovernanceProposal, String> {
+    let caller = is_authenticated()?;
+
+    let mut proposal = PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow().get(&proposal_id)
+            .ok_or_else(|| "Proposal not found".to_string())
+    })?;
+
+    if proposal.proposer != caller {
+        return Err("Only the proposer can amend a proposal".to_string());
+    }
+    if !matches!(proposal.status, ProposalStatus::Active) || time() >= proposal.voting_starts_at {
+        return Err("Proposals can only be amended before voting opens".to_string());
+    }
+
+    let config = governance_config();
+    validate_content(&config, &payload.title, &payload.description, &payload.discussion_links, &payload.document_hashes)?;
+    validate_action(&payload.action)?;
+    // The type fixed the thresholds and the deposit, so it can't change.
+    if proposal_type_key(&action_type(&payload.action)) != proposal_type_key(&proposal.proposal_type) {
+        return Err("An amendment cannot change the proposal type".to_string());
+    }
+
+    proposal.amendments.push(ProposalAmendment {
+        title: std::mem::replace(&mut proposal.title, payload.title),
+        description: std::mem::replace(&mut proposal.description, payload.description),
+        action: std::mem::replace(&mut proposal.action, payload.action),
+        discussion_links: std::mem::replace(&mut proposal.discussion_links, payload.discussion_links),
+        document_hashes: std::mem::replace(&mut proposal.document_hashes, payload.document_hashes),
+        amended_at: time(),
     });
 
+    insert_proposal(proposal.clone());
+
     Ok(proposal)
 }
 
@@ -53,74 +158,572 @@ pub fn vote_on_proposal(proposal_id: u64, vote_for: bool) -> Result<(), String>
     let caller = is_authenticated()?;
     validate_kyc(caller)?;
 
+    let proposal = load_open_proposal(proposal_id)?;
+
+    if get_vote(proposal_id, caller).is_some() {
+        return Err("Already voted on this proposal; use change_vote to switch sides".to_string());
+    }
+
+    cast_vote(proposal, caller, vote_for)
+}
+
+/// Switches an existing vote: the weight recorded with the old vote is taken off
+/// its side before the caller's current weight is added to the new one.
+#[update]
+pub fn change_vote(proposal_id: u64, vote_for: bool) -> Result<(), String> {
+    let caller = is_authenticated()?;
+    validate_kyc(caller)?;
+
+    let mut proposal = load_open_proposal(proposal_id)?;
+
+    let previous = get_vote(proposal_id, caller)
+        .ok_or_else(|| "No existing vote to change".to_string())?;
+
+    if previous.vote_for {
+        proposal.votes_for -= previous.weight;
+    } else {
+        proposal.votes_against -= previous.weight;
+    }
+
+    cast_vote(proposal, caller, vote_for)
+}
+
+/// Closes voting once `voting_ends_at` has passed. Anyone may call it; the outcome
+/// depends only on the recorded tallies:
+/// - turnout below quorum rejects, whatever the split, and slashes the deposit;
+/// - a tie rejects;
+/// - otherwise the votes for must exceed the approval threshold of votes cast.
+#[update]
+pub fn finalize_proposal(proposal_id: u64) -> Result<GovernanceProposal, String> {
+    is_authenticated()?;
+
     let mut proposal = PROPOSAL_STORAGE.with(|storage| {
         storage.borrow().get(&proposal_id)
             .ok_or_else(|| "Proposal not found".to_string())
     })?;
 
+    if !matches!(proposal.status, ProposalStatus::Active) {
+        return Err("Proposal is already finalized".to_string());
+    }
+
+    let current_time = time();
+    if current_time <= proposal.voting_ends_at {
+        return Err("Voting period has not ended".to_string());
+    }
+
+    let outcome = tally_outcome(&proposal);
+    proposal.status = match outcome {
+        ProposalOutcome::Approved => ProposalStatus::Passed,
+        _ => ProposalStatus::Rejected,
+    };
+    if matches!(proposal.status, ProposalStatus::Passed) {
+        proposal.executable_at = Some(current_time + EXECUTION_TIMELOCK_NANOS);
+    }
+    settle_deposit(&mut proposal, &outcome)?;
+    proposal.outcome = Some(outcome);
+    proposal.finalized_at = Some(current_time);
+
+    insert_proposal(proposal.clone());
+
+    Ok(proposal)
+}
+
+/// Carries out a passed proposal's action once its timelock has elapsed. Anyone
+/// may call it; a failing action leaves the proposal `Passed` so it can be retried.
+#[update]
+pub fn execute_proposal(proposal_id: u64) -> Result<GovernanceProposal, String> {
+    is_authenticated()?;
+
+    let mut proposal = PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow().get(&proposal_id)
+            .ok_or_else(|| "Proposal not found".to_string())
+    })?;
+
+    if !matches!(proposal.status, ProposalStatus::Passed) {
+        return Err("Only passed proposals can be executed".to_string());
+    }
+
+    let current_time = time();
+    match proposal.executable_at {
+        Some(executable_at) if current_time >= executable_at => {}
+        _ => return Err("Proposal is still timelocked".to_string()),
+    }
+
+    apply_action(&proposal)?;
+
+    proposal.status = ProposalStatus::Executed;
+    proposal.executed_at = Some(current_time);
+    insert_proposal(proposal.clone());
+
+    Ok(proposal)
+}
+
+#[update]
+pub fn set_proposal_thresholds(proposal_type: ProposalType, thresholds: ProposalThresholds) -> Result<(), String> {
+    is_admin()?;
+
+    if thresholds.quorum_bps as u64 > BASIS_POINTS || thresholds.approval_bps as u64 >= BASIS_POINTS {
+        return Err("Quorum cannot exceed 10000 and approval must be below 10000 basis points".to_string());
+    }
+
+    PROPOSAL_THRESHOLD_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(proposal_type_key(&proposal_type), thresholds)
+    });
+
+    Ok(())
+}
+
+#[update]
+pub fn set_governance_config(config: GovernanceConfig) -> Result<(), String> {
+    is_admin()?;
+
+    if config.min_proposer_bps as u64 > BASIS_POINTS || config.deposit_bps as u64 > BASIS_POINTS {
+        return Err("Token thresholds cannot exceed 10000 basis points".to_string());
+    }
+    if config.max_active_proposals == 0 || config.max_title_chars == 0 {
+        return Err("Active proposal and title limits must be greater than zero".to_string());
+    }
+    if config.review_period_hours > MAX_VOTING_HOURS {
+        return Err(format!("Review period cannot exceed {} hours", MAX_VOTING_HOURS));
+    }
+
+    GOVERNANCE_CONFIG_STORAGE.with(|storage| storage.borrow_mut().insert(0, config));
+
+    Ok(())
+}
+
+#[query]
+pub fn get_governance_config() -> GovernanceConfig {
+    governance_config()
+}
+
+#[query]
+pub fn get_proposal_thresholds(proposal_type: ProposalType) -> ProposalThresholds {
+    proposal_thresholds(&proposal_type)
+}
+
+#[query]
+pub fn get_proposal_votes(proposal_id: u64) -> Vec<Vote> {
+    votes_by_proposal(proposal_id)
+}
+
+#[query]
+pub fn get_voter_votes(voter: Principal) -> Vec<Vote> {
+    votes_by_voter(voter)
+}
+
+fn load_open_proposal(proposal_id: u64) -> Result<GovernanceProposal, String> {
+    let proposal = PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow().get(&proposal_id)
+            .ok_or_else(|| "Proposal not found".to_string())
+    })?;
+
     if !matches!(proposal.status, ProposalStatus::Active) {
         return Err("Proposal is not active".to_string());
     }
 
-    if proposal.voting_ends_at < time() {
-        proposal.status = ProposalStatus::Rejected;
-        PROPOSAL_STORAGE.with(|storage| {
-            storage.borrow_mut().insert(proposal_id, proposal)
-        });
+    let current_time = time();
+    if current_time < proposal.voting_starts_at {
+        return Err("Voting has not opened yet".to_string());
+    }
+    if proposal.voting_ends_at < current_time {
         return Err("Voting period has ended".to_string());
     }
 
-    let voting_power = get_user_token_balance(caller, proposal.property_id)?;
+    Ok(proposal)
+}
+
+fn get_vote(proposal_id: u64, voter: Principal) -> Option<Vote> {
+    VOTE_STORAGE.with(|storage| storage.borrow().get(&(proposal_id, voter)))
+}
+
+fn cast_vote(mut proposal: GovernanceProposal, voter: Principal, vote_for: bool) -> Result<(), String> {
+    let own_weight = balance_at(voter, proposal.property_id, proposal.snapshot_at);
+    let delegated = delegated_weights(&proposal, voter);
+    let voting_power = own_weight + delegated.iter().map(|d| d.weight).sum::<u64>();
     if voting_power == 0 {
         return Err("No voting power for this property".to_string());
     }
 
+    // Voting directly overrides a delegate who already voted with this weight.
+    withdraw_delegated_weight(&mut proposal, voter);
+
     if vote_for {
         proposal.votes_for += voting_power;
     } else {
         proposal.votes_against += voting_power;
     }
 
-    // Check if proposal passes
-    if proposal.votes_for >= proposal.voting_power_required {
-        proposal.status = ProposalStatus::Passed;
-    }
-
-    PROPOSAL_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(proposal_id, proposal)
+    insert_vote(Vote {
+        proposal_id: proposal.id,
+        voter,
+        weight: voting_power,
+        delegated,
+        vote_for,
+        timestamp: time(),
     });
+    insert_proposal(proposal);
 
     Ok(())
 }
 
+/// Snapshot balances of everyone whose delegation for this property currently
+/// points at `delegate`. A property-specific delegation takes precedence over a
+/// global one, delegation is not transitive, and holders whose weight is already
+/// in the tally are skipped.
+fn delegated_weights(proposal: &GovernanceProposal, delegate: Principal) -> Vec<DelegatedWeight> {
+    let property_id = proposal.property_id;
+    let global = delegators_of(delegate, GLOBAL_DELEGATION_SCOPE)
+        .into_iter()
+        .filter(|delegator| get_delegation(*delegator, property_id).is_none());
+    let candidates: Vec<Principal> = delegators_of(delegate, property_id).into_iter().chain(global).collect();
+
+    uncounted_delegators(candidates, &votes_by_proposal(proposal.id), delegate)
+        .into_iter()
+        .map(|delegator| DelegatedWeight {
+            delegator,
+            weight: balance_at(delegator, property_id, proposal.snapshot_at),
+        })
+        .filter(|delegated| delegated.weight > 0)
+        .collect()
+}
+
+/// Drops delegators who voted themselves or whose weight another voter already
+/// cast, e.g. a delegate they've since left or revoked. Only `voter`'s own vote is
+/// ignored, since it's being replaced.
+fn uncounted_delegators(candidates: Vec<Principal>, votes: &[Vote], voter: Principal) -> Vec<Principal> {
+    candidates
+        .into_iter()
+        .filter(|delegator| {
+            !votes.iter().any(|vote| {
+                vote.voter == *delegator
+                    || (vote.voter != voter && vote.delegated.iter().any(|d| d.delegator == *delegator))
+            })
+        })
+        .collect()
+}
+
+fn withdraw_delegated_weight(proposal: &mut GovernanceProposal, delegator: Principal) {
+    for mut vote in votes_by_proposal(proposal.id) {
+        let Some(position) = vote.delegated.iter().position(|d| d.delegator == delegator) else {
+            continue;
+        };
+        let withdrawn = vote.delegated.remove(position).weight;
+        vote.weight -= withdrawn;
+        if vote.vote_for {
+            proposal.votes_for -= withdrawn;
+        } else {
+            proposal.votes_against -= withdrawn;
+        }
+        insert_vote(vote);
+    }
+}
+
+/// Delegates the caller's voting power for one property, or for all properties when
+/// `property_id` is `None`. Replaces any existing delegation for the same scope.
+/// Votes already cast by the delegate keep the weight; the caller can still
+/// override them by voting directly.
+#[update]
+pub fn delegate_votes(delegate: Principal, property_id: Option<u64>) -> Result<Delegation, String> {
+    let caller = is_authenticated()?;
+
+    if delegate == caller {
+        return Err("Cannot delegate to yourself".to_string());
+    }
+    if delegate == Principal::anonymous() {
+        return Err("Cannot delegate to the anonymous principal".to_string());
+    }
+    if let Some(property_id) = property_id {
+        if PROPERTY_STORAGE.with(|storage| !storage.borrow().contains_key(&property_id)) {
+            return Err("Property not found".to_string());
+        }
+    }
+
+    let delegation = Delegation {
+        delegator: caller,
+        delegate,
+        property_id,
+        created_at: time(),
+    };
+    insert_delegation(delegation.clone());
+
+    Ok(delegation)
+}
+
+#[update]
+pub fn revoke_delegation(property_id: Option<u64>) -> Result<(), String> {
+    let caller = is_authenticated()?;
+    remove_delegation(caller, delegation_scope(property_id))
+        .map(|_| ())
+        .ok_or_else(|| "No delegation to revoke".to_string())
+}
+
 #[query]
-pub fn get_property_proposals(property_id: u64) -> Vec<GovernanceProposal> {
-    PROPOSAL_STORAGE.with(|storage| {
-        storage.borrow()
-            .iter()
-            .filter(|(_, proposal)| proposal.property_id == property_id)
-            .map(|(_, proposal)| proposal)
-            .collect()
-    })
+pub fn get_delegations(delegator: Principal) -> Vec<Delegation> {
+    delegations_by_delegator(delegator)
 }
 
-fn calculate_required_voting_power(property_id: u64) -> Result<u64, String> {
-    let property = PROPERTY_STORAGE.with(|storage| {
-        storage.borrow().get(&property_id)
+/// Delegations pointing at `delegate` for the given scope.
+#[query]
+pub fn get_delegators(delegate: Principal, property_id: Option<u64>) -> Vec<Delegation> {
+    let scope = delegation_scope(property_id);
+    delegators_of(delegate, scope)
+        .into_iter()
+        .filter_map(|delegator| get_delegation(delegator, scope))
+        .collect()
+}
+
+#[query]
+pub fn get_property_proposals(property_id: u64, page: PageRequest) -> Page<GovernanceProposal> {
+    paginate(proposals_by_property(property_id), &page)
+}
+
+impl Paginated for GovernanceProposal {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    // Proposals have no price, yield or value; every field sorts by creation time.
+    fn sort_key(&self, _field: &SortField) -> u64 {
+        self.created_at
+    }
+}
+
+fn apply_action(proposal: &GovernanceProposal) -> Result<(), String> {
+    let mut property = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().get(&proposal.property_id)
             .ok_or_else(|| "Property not found".to_string())
     })?;
 
-    // Require majority (51%) of total tokens
-    Ok((property.total_tokens * 51) / 100)
+    match &proposal.action {
+        ProposalAction::PropertySale { sale_price } => {
+            redeem_property(property, *sale_price, Some(proposal.id))?;
+        }
+        ProposalAction::DividendDistribution { total_amount } => {
+            run_distribution(property.id, *total_amount, DistributionKind::Dividend, Some(proposal.id))?;
+        }
+        ProposalAction::ManagementChange { new_manager } => {
+            property.manager = *new_manager;
+            insert_property(property);
+        }
+        ProposalAction::CorporateAction(kind) => {
+            execute_corporate_action(property, kind.clone(), ic_cdk::caller(), Some(proposal.id))?;
+        }
+        // Maintenance and free-form proposals record the holders' decision; the
+        // work itself happens off-chain.
+        ProposalAction::PropertyMaintenance { .. } | ProposalAction::Other => {}
+    }
+
+    Ok(())
 }
 
-fn get_user_token_balance(user: Principal, property_id: u64) -> Result<u64, String> {
-    INVESTMENT_STORAGE.with(|storage| {
-        let balance = storage.borrow()
-            .iter()
-            .filter(|(_, investment)| investment.investor == user && investment.property_id == property_id)
-            .map(|(_, investment)| investment.token_amount)
-            .sum();
-        Ok(balance)
-    })
+/// Refunds the proposer's deposit, or when quorum was missed slashes it back
+/// into the property's unsold supply.
+fn settle_deposit(proposal: &mut GovernanceProposal, outcome: &ProposalOutcome) -> Result<(), String> {
+    // Load what slashing needs before anything is written.
+    let slashed_from = if matches!(outcome, ProposalOutcome::QuorumNotMet) && proposal.deposit > 0 {
+        Some(PROPERTY_STORAGE.with(|storage| {
+            storage.borrow().get(&proposal.property_id)
+                .ok_or_else(|| "Property not found".to_string())
+        })?)
+    } else {
+        None
+    };
+
+    unlock_tokens(proposal.proposer, proposal.property_id, proposal.deposit);
+
+    // A sold property's tokens have been redeemed, so there's nothing left to slash.
+    let Some(mut property) = slashed_from.filter(|property| !matches!(property.status, PropertyStatus::Sold)) else {
+        proposal.deposit_status = DepositStatus::Refunded;
+        return Ok(());
+    };
+    // The disposal is booked against the proposal id; the forfeited tokens realize a
+    // loss. The deposit was locked, so it's still there once unlocked; if not, trap
+    // rather than leave it unlocked and unslashed.
+    record_disposal(proposal.proposer, proposal.property_id, proposal.id, proposal.deposit, Amount::ZERO)
+        .unwrap_or_else(|error| ic_cdk::trap(&error));
+    property.available_tokens += proposal.deposit;
+    insert_property(property);
+    refresh_portfolio(proposal.proposer);
+
+    proposal.deposit_status = DepositStatus::Slashed;
+    Ok(())
+}
+
+fn validate_content(
+    config: &GovernanceConfig,
+    title: &str,
+    description: &str,
+    discussion_links: &[String],
+    document_hashes: &[String],
+) -> Result<(), String> {
+    if title.trim().is_empty() {
+        return Err("Title cannot be empty".to_string());
+    }
+    if title.chars().count() > config.max_title_chars as usize {
+        return Err(format!("Title cannot exceed {} characters", config.max_title_chars));
+    }
+    if description.chars().count() > config.max_description_chars as usize {
+        return Err(format!("Description cannot exceed {} characters", config.max_description_chars));
+    }
+    if discussion_links.len() + document_hashes.len() > config.max_attachments as usize {
+        return Err(format!("At most {} links and document hashes can be attached", config.max_attachments));
+    }
+    if discussion_links.iter().any(|link| !link.starts_with("https://")) {
+        return Err("Discussion links must be https URLs".to_string());
+    }
+    if discussion_links.iter().any(|link| link.len() > MAX_LINK_LENGTH) {
+        return Err(format!("Discussion links cannot exceed {} characters", MAX_LINK_LENGTH));
+    }
+    if document_hashes.iter().any(|hash| !is_sha256_hex(hash)) {
+        return Err("Document hashes must be hex-encoded SHA-256 digests".to_string());
+    }
+    Ok(())
+}
+
+/// When voting opens and closes for a proposal created at `now`: after the review
+/// period, for `voting_duration_hours`.
+fn voting_window(now: u64, review_period_hours: u64, voting_duration_hours: u64) -> Result<(u64, u64), String> {
+    if !(MIN_VOTING_HOURS..=MAX_VOTING_HOURS).contains(&voting_duration_hours) {
+        return Err(format!("Voting must last between {} and {} hours", MIN_VOTING_HOURS, MAX_VOTING_HOURS));
+    }
+    let hours_to_nanos = |hours: u64| hours.checked_mul(NANOS_PER_HOUR);
+    let voting_starts_at = hours_to_nanos(review_period_hours).and_then(|review| now.checked_add(review));
+    let voting_ends_at = voting_starts_at
+        .zip(hours_to_nanos(voting_duration_hours))
+        .and_then(|(starts_at, duration)| starts_at.checked_add(duration));
+    match (voting_starts_at, voting_ends_at) {
+        (Some(starts_at), Some(ends_at)) => Ok((starts_at, ends_at)),
+        _ => Err("Review period is too long".to_string()),
+    }
+}
+
+fn governance_config() -> GovernanceConfig {
+    GOVERNANCE_CONFIG_STORAGE.with(|storage| storage.borrow().get(&0))
+        .unwrap_or(GovernanceConfig {
+            min_proposer_bps: 50,
+            deposit_bps: 10,
+            max_active_proposals: 5,
+            max_title_chars: 200,
+            max_description_chars: 10_000,
+            max_attachments: 10,
+            review_period_hours: 24,
+        })
+}
+
+fn validate_action(action: &ProposalAction) -> Result<(), String> {
+    match action {
+        ProposalAction::PropertySale { sale_price } if sale_price.is_zero() => {
+            Err("Sale price must be greater than zero".to_string())
+        }
+        ProposalAction::DividendDistribution { total_amount } if total_amount.is_zero() => {
+            Err("Distribution amount must be greater than zero".to_string())
+        }
+        ProposalAction::ManagementChange { new_manager } if *new_manager == Principal::anonymous() => {
+            Err("New manager cannot be anonymous".to_string())
+        }
+        ProposalAction::CorporateAction(kind) => validate_corporate_action(kind),
+        _ => Ok(()),
+    }
+}
+
+fn action_type(action: &ProposalAction) -> ProposalType {
+    match action {
+        ProposalAction::PropertyMaintenance { .. } => ProposalType::PropertyMaintenance,
+        ProposalAction::PropertySale { .. } => ProposalType::PropertySale,
+        ProposalAction::ManagementChange { .. } => ProposalType::ManagementChange,
+        ProposalAction::DividendDistribution { .. } => ProposalType::DividendDistribution,
+        ProposalAction::CorporateAction(_) => ProposalType::CorporateAction,
+        ProposalAction::Other => ProposalType::Other,
+    }
+}
+
+fn tally_outcome(proposal: &GovernanceProposal) -> ProposalOutcome {
+    let votes_cast = proposal.votes_for + proposal.votes_against;
+    if votes_cast == 0 || votes_cast < proposal.voting_power_required {
+        return ProposalOutcome::QuorumNotMet;
+    }
+    if proposal.votes_for == proposal.votes_against {
+        return ProposalOutcome::Tied;
+    }
+    let approval = proposal.votes_for as u128 * BASIS_POINTS as u128;
+    if approval > proposal.approval_threshold_bps as u128 * votes_cast as u128 {
+        ProposalOutcome::Approved
+    } else {
+        ProposalOutcome::ApprovalNotMet
+    }
+}
+
+fn proposal_thresholds(proposal_type: &ProposalType) -> ProposalThresholds {
+    PROPOSAL_THRESHOLD_STORAGE.with(|storage| storage.borrow().get(&proposal_type_key(proposal_type)))
+        .unwrap_or_else(|| default_thresholds(proposal_type))
+}
+
+/// Selling the asset needs a supermajority on high turnout; routine matters a
+/// simple majority of a fifth of the supply.
+fn default_thresholds(proposal_type: &ProposalType) -> ProposalThresholds {
+    match proposal_type {
+        ProposalType::PropertySale => ProposalThresholds { quorum_bps: 5_000, approval_bps: 6_666 },
+        ProposalType::ManagementChange | ProposalType::CorporateAction => {
+            ProposalThresholds { quorum_bps: 3_000, approval_bps: 5_000 }
+        }
+        ProposalType::PropertyMaintenance
+        | ProposalType::DividendDistribution
+        | ProposalType::Other => ProposalThresholds { quorum_bps: 2_000, approval_bps: 5_000 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn vote(voter: Principal, delegated: &[Principal]) -> Vote {
+        Vote {
+            proposal_id: 1,
+            voter,
+            weight: 100,
+            delegated: delegated.iter().map(|&delegator| DelegatedWeight { delegator, weight: 10 }).collect(),
+            vote_for: true,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn redelegated_weight_is_not_counted_twice() {
+        let (holder, first, second) = (principal(1), principal(2), principal(3));
+        let votes = vec![vote(first, &[holder])];
+
+        // The holder moved (or revoked and re-granted) their delegation after the
+        // first delegate had already voted with it.
+        assert!(uncounted_delegators(vec![holder], &votes, second).is_empty());
+    }
+
+    #[test]
+    fn delegate_keeps_weight_when_changing_vote() {
+        let (holder, delegate) = (principal(1), principal(2));
+        let votes = vec![vote(delegate, &[holder])];
+
+        assert_eq!(uncounted_delegators(vec![holder], &votes, delegate), vec![holder]);
+    }
+
+    #[test]
+    fn voting_window_is_bounded_and_checked() {
+        assert_eq!(voting_window(10, 1, MIN_VOTING_HOURS), Ok((10 + NANOS_PER_HOUR, 10 + 25 * NANOS_PER_HOUR)));
+        assert!(voting_window(10, 1, 0).is_err());
+        assert!(voting_window(10, 1, MAX_VOTING_HOURS + 1).is_err());
+        assert!(voting_window(10, u64::MAX / NANOS_PER_HOUR + 1, MIN_VOTING_HOURS).is_err());
+        assert!(voting_window(u64::MAX - NANOS_PER_HOUR, 0, MIN_VOTING_HOURS).is_err());
+    }
+
+    #[test]
+    fn direct_voters_are_skipped() {
+        let (holder, other, delegate) = (principal(1), principal(2), principal(3));
+        let votes = vec![vote(holder, &[])];
+
+        assert_eq!(uncounted_delegators(vec![holder, other], &votes, delegate), vec![other]);
+    }
 }
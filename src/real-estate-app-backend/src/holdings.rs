@@ -0,0 +1,533 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use std::collections::BTreeSet;
+use crate::investment::InvestmentStatus;
+use crate::portfolio::{dividends_received, refresh_portfolio};
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::{get_current_time, is_authenticated, mul_div, mul_div_exact, year_bounds};
+
+const NANOS_PER_YEAR: u64 = 365 * 24 * 3600 * 1_000_000_000;
+
+#[update]
+pub fn set_cost_basis_method(method: CostBasisMethod) -> Result<(), String> {
+    let caller = is_authenticated()?;
+    COST_BASIS_METHOD_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(caller, method)
+    });
+    Ok(())
+}
+
+#[query]
+pub fn get_cost_basis_method(owner: Principal) -> CostBasisMethod {
+    cost_basis_method(owner)
+}
+
+/// Open and closed lots, oldest first. Pass a property id to narrow to one holding.
+#[query]
+pub fn get_tax_lots(owner: Principal, property_id: Option<u64>) -> Vec<TaxLot> {
+    match property_id {
+        Some(property_id) => lots_for(owner, property_id),
+        None => LOT_STORAGE.with(|storage| {
+            storage.borrow()
+                .range((owner, 0, 0)..=(owner, u64::MAX, u64::MAX))
+                .map(|(_, lot)| lot)
+                .collect()
+        }),
+    }
+}
+
+#[query]
+pub fn get_realized_gains(owner: Principal) -> Vec<RealizedGain> {
+    realized_gains_between(owner, 0, u64::MAX)
+}
+
+/// Proceeds, cost basis and dividends for one calendar year (UTC). Gains on lots
+/// held longer than a year are reported as long-term.
+#[query]
+pub fn get_tax_statement(owner: Principal, year: u32) -> TaxStatement {
+    let (start, end) = year_bounds(year);
+    let disposals = realized_gains_between(owner, start, end - 1);
+
+    let mut short_term_gain = 0i64;
+    let mut long_term_gain = 0i64;
+    for disposal in disposals.iter() {
+        for lot in disposal.lots.iter() {
+            let proceeds = disposal.proceeds
+                .share(lot.quantity, disposal.quantity, Rounding::Down)
+                .unwrap_or(Amount::ZERO);
+            let gain = proceeds.saturating_signed_difference(lot.cost_basis);
+            if disposal.disposed_at.saturating_sub(lot.acquired_at) > NANOS_PER_YEAR {
+                long_term_gain = long_term_gain.saturating_add(gain);
+            } else {
+                short_term_gain = short_term_gain.saturating_add(gain);
+            }
+        }
+    }
+
+    let dividends = dividends_received(owner)
+        .iter()
+        .filter(|(_, paid_at, _)| *paid_at >= start && *paid_at < end)
+        .map(|(_, _, amount)| amount)
+        .sum();
+
+    TaxStatement {
+        owner,
+        year,
+        proceeds: disposals.iter().map(|d| d.proceeds).sum(),
+        cost_basis: disposals.iter().map(|d| d.cost_basis).sum(),
+        realized_gain: disposals.iter().map(|d| d.gain).sum(),
+        short_term_gain,
+        long_term_gain,
+        dividends_received: dividends,
+        disposals,
+    }
+}
+
+pub fn token_balance(owner: Principal, property_id: u64) -> u64 {
+    HOLDING_STORAGE.with(|storage| storage.borrow().get(&(property_id, owner)).unwrap_or(0))
+}
+
+/// Tokens locked as proposal deposits or escrowed for open sell orders; they
+/// still vote but can't be sold.
+pub fn locked_balance(owner: Principal, property_id: u64) -> u64 {
+    TOKEN_LOCK_STORAGE.with(|storage| storage.borrow().get(&(property_id, owner)).unwrap_or(0))
+}
+
+pub fn available_balance(owner: Principal, property_id: u64) -> u64 {
+    token_balance(owner, property_id).saturating_sub(locked_balance(owner, property_id))
+}
+
+pub fn lock_tokens(owner: Principal, property_id: u64, amount: u64) -> Result<(), String> {
+    if available_balance(owner, property_id) < amount {
+        return Err("Insufficient unlocked token balance".to_string());
+    }
+    set_locked(owner, property_id, locked_balance(owner, property_id) + amount);
+    Ok(())
+}
+
+pub fn unlock_tokens(owner: Principal, property_id: u64, amount: u64) {
+    set_locked(owner, property_id, locked_balance(owner, property_id).saturating_sub(amount));
+}
+
+/// Drops every lock on the property's tokens, e.g. before they're redeemed.
+pub fn release_locks(property_id: u64) {
+    for (owner, _) in locks_of(property_id) {
+        set_locked(owner, property_id, 0);
+    }
+}
+
+/// Checks that every balance, lock and open lot of the property scales by
+/// `numerator / denominator` to a whole number of tokens.
+pub fn check_rescale(property_id: u64, numerator: u64, denominator: u64) -> Result<(), String> {
+    let whole = |quantity: u64| mul_div_exact(quantity, numerator, denominator).is_some();
+    let holders = holders_of(property_id);
+
+    let fractional = holders.iter().any(|(_, balance)| !whole(*balance))
+        || locks_of(property_id).iter().any(|(_, locked)| !whole(*locked))
+        || open_lots(&holders, property_id).iter().any(|lot| !whole(lot.remaining_quantity));
+    if fractional {
+        return Err("The ratio would leave a holder with a fraction of a token".to_string());
+    }
+    Ok(())
+}
+
+/// Scales every balance, lock and open lot of the property by `numerator /
+/// denominator`; `check_rescale` must pass first. Cost bases are unchanged, and a
+/// lot's original quantity rounds down. Returns the number of holders.
+pub fn rescale_holdings(property_id: u64, numerator: u64, denominator: u64) -> u32 {
+    let holders = holders_of(property_id);
+
+    for lot in open_lots(&holders, property_id) {
+        let lot = TaxLot {
+            quantity: mul_div(lot.quantity, numerator, denominator),
+            remaining_quantity: mul_div(lot.remaining_quantity, numerator, denominator),
+            ..lot
+        };
+        LOT_STORAGE.with(|storage| {
+            storage.borrow_mut().insert((lot.owner, property_id, lot.id), lot)
+        });
+    }
+    for (owner, locked) in locks_of(property_id) {
+        set_locked(owner, property_id, mul_div(locked, numerator, denominator));
+    }
+    for (owner, balance) in &holders {
+        set_balance(*owner, property_id, mul_div(*balance, numerator, denominator), get_current_time());
+    }
+
+    holders.len() as u32
+}
+
+fn locks_of(property_id: u64) -> Vec<(Principal, u64)> {
+    TOKEN_LOCK_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, Principal::from_slice(&[]))..)
+            .take_while(|((id, _), _)| *id == property_id)
+            .map(|((_, owner), locked)| (owner, locked))
+            .collect()
+    })
+}
+
+fn open_lots(holders: &[(Principal, u64)], property_id: u64) -> Vec<TaxLot> {
+    holders
+        .iter()
+        .flat_map(|(owner, _)| lots_for(*owner, property_id))
+        .filter(|lot| lot.remaining_quantity > 0)
+        .collect()
+}
+
+fn set_locked(owner: Principal, property_id: u64, amount: u64) {
+    TOKEN_LOCK_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if amount == 0 {
+            storage.remove(&(property_id, owner));
+        } else {
+            storage.insert((property_id, owner), amount);
+        }
+    });
+}
+
+/// Every principal holding a non-zero balance of the property.
+pub fn holders_of(property_id: u64) -> Vec<(Principal, u64)> {
+    HOLDING_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, Principal::from_slice(&[]))..)
+            .take_while(|((id, _), _)| *id == property_id)
+            .map(|((_, owner), balance)| (owner, balance))
+            .collect()
+    })
+}
+
+pub fn lots_for(owner: Principal, property_id: u64) -> Vec<TaxLot> {
+    LOT_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((owner, property_id, 0)..=(owner, property_id, u64::MAX))
+            .map(|(_, lot)| lot)
+            .collect()
+    })
+}
+
+pub fn realized_gains_between(owner: Principal, from: u64, to: u64) -> Vec<RealizedGain> {
+    REALIZED_GAIN_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((owner, from, 0)..=(owner, to, u64::MAX))
+            .map(|(_, gain)| gain)
+            .collect()
+    })
+}
+
+/// Opens a lot for newly acquired tokens and credits the holder's balance.
+pub fn record_acquisition(owner: Principal, property_id: u64, investment_id: u64, quantity: u64, cost: Amount) -> TaxLot {
+    acquire(owner, property_id, investment_id, quantity, cost, get_current_time())
+}
+
+/// Closes `quantity` tokens out of the holder's lots using their chosen method and
+/// records the realized gain against `proceeds`. Nothing is written if the holder
+/// doesn't have enough unlocked tokens.
+pub fn record_disposal(owner: Principal, property_id: u64, order_id: u64, quantity: u64, proceeds: Amount) -> Result<RealizedGain, String> {
+    dispose(owner, property_id, order_id, quantity, proceeds, get_current_time())
+}
+
+/// Seeds lots, balances and checkpoints from the investment and order records the
+/// first time this version runs. Before holdings were tracked, a balance was the
+/// sum of the holder's investments and sellers were never debited, so the seller
+/// side of each filled order is replayed against their lots, in time order and
+/// capped at what they held. Properties' unsold supply is then reconciled with
+/// what's in circulation.
+pub fn backfill_holdings() {
+    let already_tracked = HOLDING_STORAGE.with(|storage| !storage.borrow().is_empty())
+        || LOT_STORAGE.with(|storage| !storage.borrow().is_empty());
+    if already_tracked {
+        return;
+    }
+
+    // (timestamp, is_disposal, record id) keeps replays in the order they happened,
+    // with acquisitions first on a tie.
+    let mut events: Vec<(u64, bool, u64)> = INVESTMENT_STORAGE.with(|storage| {
+        storage.borrow()
+            .iter()
+            .filter(|(_, investment)| matches!(investment.status, InvestmentStatus::Confirmed) && investment.token_amount > 0)
+            .map(|(id, investment)| (investment.timestamp, false, id))
+            .collect()
+    });
+    ORDER_STORAGE.with(|storage| {
+        events.extend(
+            storage.borrow()
+                .iter()
+                .filter(|(_, order)| matches!(order.status, OrderStatus::Filled))
+                .map(|(id, order)| (order.filled_at.unwrap_or(order.created_at), true, id)),
+        )
+    });
+    events.sort();
+
+    let mut owners = BTreeSet::new();
+    for (at, is_disposal, id) in events {
+        if is_disposal {
+            let Some(order) = ORDER_STORAGE.with(|storage| storage.borrow().get(&id)) else { continue };
+            let Some(buyer) = order.buyer else { continue };
+            // On a buy order the maker bought and the filler sold.
+            let seller = match order.order_type {
+                OrderType::Buy => buyer,
+                OrderType::Sell => order.seller,
+            };
+            let quantity = order.token_amount.min(available_balance(seller, order.property_id));
+            if quantity > 0 {
+                let proceeds = order.total_price.share(quantity, order.token_amount, Rounding::Down).unwrap_or(Amount::ZERO);
+                let _ = dispose(seller, order.property_id, order.id, quantity, proceeds, at);
+            }
+        } else {
+            let Some(investment) = INVESTMENT_STORAGE.with(|storage| storage.borrow().get(&id)) else { continue };
+            let cost = investment.investment_amount.checked_add(investment.fee).unwrap_or(investment.investment_amount);
+            acquire(investment.investor, investment.property_id, investment.id, investment.token_amount, cost, at);
+            owners.insert(investment.investor);
+        }
+    }
+
+    let properties: Vec<Property> = PROPERTY_STORAGE.with(|storage| storage.borrow().iter().map(|(_, property)| property).collect());
+    for mut property in properties {
+        let circulating: u64 = holders_of(property.id).iter().map(|(_, balance)| balance).sum();
+        let available_tokens = property.total_tokens.saturating_sub(circulating);
+        if available_tokens != property.available_tokens {
+            property.available_tokens = available_tokens;
+            insert_property(property);
+        }
+    }
+    for owner in owners {
+        refresh_portfolio(owner);
+    }
+}
+
+fn acquire(owner: Principal, property_id: u64, investment_id: u64, quantity: u64, cost: Amount, at: u64) -> TaxLot {
+    let lot = TaxLot {
+        id: get_next_id(),
+        owner,
+        property_id,
+        investment_id,
+        quantity,
+        remaining_quantity: quantity,
+        cost_basis: cost,
+        remaining_cost_basis: cost,
+        acquired_at: at,
+    };
+
+    LOT_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((owner, property_id, lot.id), lot.clone())
+    });
+    set_balance(owner, property_id, token_balance(owner, property_id) + quantity, at);
+
+    lot
+}
+
+fn dispose(owner: Principal, property_id: u64, order_id: u64, quantity: u64, proceeds: Amount, at: u64) -> Result<RealizedGain, String> {
+    let balance = token_balance(owner, property_id);
+    if available_balance(owner, property_id) < quantity {
+        return Err("Insufficient token balance".to_string());
+    }
+
+    let method = cost_basis_method(owner);
+    let mut lots: Vec<TaxLot> = lots_for(owner, property_id)
+        .into_iter()
+        .filter(|lot| lot.remaining_quantity > 0)
+        .collect();
+    let disposals = take_from_lots(&mut lots, &method, quantity)?;
+    let cost_basis: Amount = disposals.iter().map(|d| d.cost_basis).sum();
+    let realized = proceeds.signed_difference(cost_basis)?;
+
+    LOT_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for lot in lots {
+            storage.insert((owner, property_id, lot.id), lot);
+        }
+    });
+    set_balance(owner, property_id, balance - quantity, at);
+
+    let gain = RealizedGain {
+        id: get_next_id(),
+        owner,
+        property_id,
+        order_id,
+        quantity,
+        proceeds,
+        cost_basis,
+        gain: realized,
+        method,
+        lots: disposals,
+        disposed_at: at,
+    };
+
+    REALIZED_GAIN_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((owner, gain.disposed_at, gain.id), gain.clone())
+    });
+
+    Ok(gain)
+}
+
+/// Takes `quantity` tokens out of the open `lots`, given oldest first, and returns
+/// the cost basis each one gave up. FIFO and LIFO consume whole lots in order;
+/// average cost charges every token the pooled average and re-pools what's left.
+/// Costs round down, so rounding dust stays in the remaining lots.
+fn take_from_lots(lots: &mut [TaxLot], method: &CostBasisMethod, quantity: u64) -> Result<Vec<LotDisposal>, String> {
+    if matches!(method, CostBasisMethod::Lifo) {
+        lots.reverse();
+    }
+
+    let pooled_quantity: u64 = lots.iter().map(|lot| lot.remaining_quantity).sum();
+    let pooled_cost: Amount = lots.iter().map(|lot| lot.remaining_cost_basis).sum();
+
+    let mut remaining = quantity;
+    let mut disposals = Vec::new();
+    for lot in lots.iter_mut() {
+        if remaining == 0 {
+            break;
+        }
+        let taken = remaining.min(lot.remaining_quantity);
+        let cost = match method {
+            CostBasisMethod::AverageCost => pooled_cost.share(taken, pooled_quantity, Rounding::Down)?,
+            _ => lot.remaining_cost_basis.share(taken, lot.remaining_quantity, Rounding::Down)?,
+        };
+        lot.remaining_quantity -= taken;
+        lot.remaining_cost_basis = lot.remaining_cost_basis.saturating_sub(cost);
+        remaining -= taken;
+        disposals.push(LotDisposal {
+            lot_id: lot.id,
+            quantity: taken,
+            cost_basis: cost,
+            acquired_at: lot.acquired_at,
+        });
+    }
+
+    if matches!(method, CostBasisMethod::AverageCost) {
+        // Re-pool what's left so every remaining token carries the same average cost.
+        let released: Amount = disposals.iter().map(|d| d.cost_basis).sum();
+        let left_quantity = pooled_quantity.saturating_sub(quantity);
+        let pooled_left = pooled_cost.checked_sub(released)?;
+        let mut left_cost = pooled_left;
+        let open: Vec<&mut TaxLot> = lots.iter_mut().filter(|lot| lot.remaining_quantity > 0).collect();
+        let count = open.len();
+        for (i, lot) in open.into_iter().enumerate() {
+            let share = if i + 1 == count {
+                left_cost
+            } else {
+                pooled_left.share(lot.remaining_quantity, left_quantity, Rounding::Down)?
+            };
+            lot.remaining_cost_basis = share;
+            left_cost = left_cost.saturating_sub(share);
+        }
+    }
+
+    Ok(disposals)
+}
+
+/// Balance as it stood just before `timestamp`. Changes made at or after that
+/// instant (including ones in the same round) are not visible.
+pub fn balance_at(owner: Principal, property_id: u64, timestamp: u64) -> u64 {
+    if timestamp == 0 {
+        return 0;
+    }
+    BALANCE_CHECKPOINT_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, owner, 0)..=(property_id, owner, timestamp - 1))
+            .next_back()
+            .map(|(_, balance)| balance)
+            .unwrap_or(0)
+    })
+}
+
+fn set_balance(owner: Principal, property_id: u64, balance: u64, at: u64) {
+    HOLDING_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if balance == 0 {
+            storage.remove(&(property_id, owner));
+        } else {
+            storage.insert((property_id, owner), balance);
+        }
+    });
+
+    // Every change is checkpointed so governance can read balances as of a snapshot.
+    BALANCE_CHECKPOINT_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((property_id, owner, at), balance)
+    });
+}
+
+fn cost_basis_method(owner: Principal) -> CostBasisMethod {
+    COST_BASIS_METHOD_STORAGE.with(|storage| storage.borrow().get(&owner))
+        .unwrap_or(CostBasisMethod::Fifo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lot(id: u64, quantity: u64, cost: u64) -> TaxLot {
+        TaxLot {
+            id,
+            owner: Principal::from_slice(&[1]),
+            property_id: 1,
+            investment_id: id,
+            quantity,
+            remaining_quantity: quantity,
+            cost_basis: Amount::from_minor(cost),
+            remaining_cost_basis: Amount::from_minor(cost),
+            acquired_at: id,
+        }
+    }
+
+    fn taken(disposals: &[LotDisposal]) -> Vec<(u64, u64, u64)> {
+        disposals.iter().map(|d| (d.lot_id, d.quantity, d.cost_basis.minor_units())).collect()
+    }
+
+    #[test]
+    fn fifo_consumes_the_oldest_lots_first() {
+        let mut lots = vec![lot(1, 10, 1_000), lot(2, 10, 2_000)];
+        let disposals = take_from_lots(&mut lots, &CostBasisMethod::Fifo, 15).unwrap();
+
+        assert_eq!(taken(&disposals), vec![(1, 10, 1_000), (2, 5, 1_000)]);
+        assert_eq!(lots[0].remaining_quantity, 0);
+        assert_eq!(lots[1].remaining_quantity, 5);
+        assert_eq!(lots[1].remaining_cost_basis, Amount::from_minor(1_000));
+    }
+
+    #[test]
+    fn lifo_consumes_the_newest_lots_first() {
+        let mut lots = vec![lot(1, 10, 1_000), lot(2, 10, 2_000)];
+        let disposals = take_from_lots(&mut lots, &CostBasisMethod::Lifo, 15).unwrap();
+
+        assert_eq!(taken(&disposals), vec![(2, 10, 2_000), (1, 5, 500)]);
+    }
+
+    #[test]
+    fn partial_lots_round_the_released_cost_down() {
+        let mut lots = vec![lot(1, 3, 100)];
+        let disposals = take_from_lots(&mut lots, &CostBasisMethod::Fifo, 1).unwrap();
+
+        assert_eq!(taken(&disposals), vec![(1, 1, 33)]);
+        assert_eq!(lots[0].remaining_cost_basis, Amount::from_minor(67));
+    }
+
+    #[test]
+    fn average_cost_charges_the_pooled_average_and_repools_the_rest() {
+        let mut lots = vec![lot(1, 10, 1_000), lot(2, 10, 2_000), lot(3, 10, 3_001)];
+        let disposals = take_from_lots(&mut lots, &CostBasisMethod::AverageCost, 15).unwrap();
+
+        // 6 001 over 30 tokens; every token taken costs the same, rounded down.
+        assert_eq!(taken(&disposals), vec![(1, 10, 2_000), (2, 5, 1_000)]);
+        let left: Vec<(u64, u64)> = lots.iter()
+            .map(|lot| (lot.remaining_quantity, lot.remaining_cost_basis.minor_units()))
+            .collect();
+        assert_eq!(left, vec![(0, 0), (5, 1_000), (10, 2_001)]);
+    }
+
+    #[test]
+    fn taking_nothing_leaves_the_lots_alone() {
+        let mut lots = vec![lot(1, 10, 1_000)];
+        assert!(take_from_lots(&mut lots, &CostBasisMethod::Fifo, 0).unwrap().is_empty());
+        assert_eq!(lots[0].remaining_cost_basis, Amount::from_minor(1_000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount overflow")]
+    fn a_pooled_cost_past_u64_traps() {
+        let mut lots = vec![lot(1, 1, u64::MAX), lot(2, 1, 1)];
+        let _ = take_from_lots(&mut lots, &CostBasisMethod::AverageCost, 1);
+    }
+}
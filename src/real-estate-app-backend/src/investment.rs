@@ -1,9 +1,17 @@
 use candid::{CandidType, Principal};
 use serde::{Serialize, Deserialize};
 use ic_cdk_macros::*;
-use crate::storage::{get_next_id, INVESTMENT_STORAGE, PROPERTY_STORAGE};
-use crate::types::PropertyStatus;
-use crate::utils::{get_current_time, is_authenticated, validate_kyc};
+use crate::analytics::{increment_metric, Metric};
+use crate::cash::{cash_balance, credit_cash, debit_cash};
+use crate::exchange::to_platform_currency;
+use crate::fees::{collect_fee, fee_for};
+use crate::holdings::{available_balance, record_acquisition};
+use crate::offering::open_offering_round;
+use crate::portfolio::refresh_portfolio;
+use crate::presale::{check_primary_purchase, record_primary_purchase};
+use crate::storage::{get_next_id, insert_investment, insert_property, investments_by_user, PROPERTY_STORAGE};
+use crate::types::{Amount, CashTransactionKind, FeeSource, Page, PageRequest, PropertyStatus, SortField};
+use crate::utils::{get_current_time, is_authenticated, paginate, validate_kyc, yield_sort_key, Paginated};
 
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub struct Investment {
@@ -11,7 +19,8 @@ pub struct Investment {
     pub investor: Principal,
     pub property_id: u64,
     pub token_amount: u64,
-    pub investment_amount: u64,
+    pub investment_amount: Amount,
+    pub fee: Amount,
     pub timestamp: u64,
     pub status: InvestmentStatus,
 }
@@ -34,7 +43,7 @@ pub fn invest_in_property(payload: InvestmentPayload) -> Result<Investment, Stri
     let caller = is_authenticated()?;
     validate_kyc(caller)?;
 
-    let property = PROPERTY_STORAGE.with(|storage| {
+    let mut property = PROPERTY_STORAGE.with(|storage| {
         storage.borrow().get(&payload.property_id)
             .ok_or_else(|| "Property not found".to_string())
     })?;
@@ -43,11 +52,27 @@ pub fn invest_in_property(payload: InvestmentPayload) -> Result<Investment, Stri
         return Err("Property is not available for investment".to_string());
     }
 
+    if open_offering_round(&mut property).is_some() {
+        return Err("Property is being sold through an offering round".to_string());
+    }
+
     if payload.token_amount > property.available_tokens {
         return Err("Insufficient tokens available".to_string());
     }
 
-    let investment_amount = payload.token_amount * property.price_per_token;
+    let investment_amount = property.price_per_token.times(payload.token_amount)?;
+    check_primary_purchase(payload.property_id, caller, investment_amount)?;
+    let fee = fee_for(&FeeSource::Issuance, payload.property_id, investment_amount)?;
+    let cost_basis = investment_amount.checked_add(fee)?;
+    let value_locked = to_platform_currency(property.settlement_asset, investment_amount)?;
+
+    // Both cash legs are checked up front and moved last, once nothing else can
+    // fail; an error between them would otherwise commit half an investment.
+    if cash_balance(caller, property.settlement_asset).available < cost_basis {
+        return Err("Insufficient available balance".to_string());
+    }
+    cash_balance(property.owner, property.settlement_asset).available.checked_add(investment_amount)?;
+
     let investment_id = get_next_id();
 
     let investment = Investment {
@@ -56,24 +81,61 @@ pub fn invest_in_property(payload: InvestmentPayload) -> Result<Investment, Stri
         property_id: payload.property_id,
         token_amount: payload.token_amount,
         investment_amount,
+        fee,
         timestamp: get_current_time(),
         status: InvestmentStatus::Confirmed,
     };
 
-    INVESTMENT_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(investment_id, investment.clone())
-    });
+    record_primary_purchase(payload.property_id, caller, investment_amount)?;
+
+    insert_investment(investment.clone());
+    // The issuance fee is paid on top of the token price and is part of the cost basis.
+    record_acquisition(caller, payload.property_id, investment_id, payload.token_amount, cost_basis);
+    collect_fee(FeeSource::Issuance, payload.property_id, property.settlement_asset, caller, investment_amount, fee, investment_id);
+    increment_metric(Metric::ValueLocked, value_locked.minor_units());
+
+    property.available_tokens -= payload.token_amount;
+    property.updated_at = investment.timestamp;
+    let (asset, issuer) = (property.settlement_asset, property.owner);
+    insert_property(property);
+
+    // The investor pays the price plus the issuance fee; the issuer receives the
+    // price and the fee goes to the treasury.
+    debit_cash(caller, asset, cost_basis, CashTransactionKind::Investment, investment_id)
+        .unwrap_or_else(|error| ic_cdk::trap(&error));
+    credit_cash(issuer, asset, investment_amount, CashTransactionKind::IssuanceProceeds, investment_id)
+        .unwrap_or_else(|error| ic_cdk::trap(&error));
+
+    refresh_portfolio(caller);
 
     Ok(investment)
 }
 
 #[query]
-pub fn get_investments_by_user(user: Principal) -> Vec<Investment> {
-    INVESTMENT_STORAGE.with(|storage| {
-        storage.borrow()
-            .iter()
-            .filter(|(_, investment)| investment.investor == user)
-            .map(|(_, investment)| investment)
-            .collect()
-    })
+pub fn get_investments_by_user(user: Principal, page: PageRequest) -> Page<Investment> {
+    paginate(investments_by_user(user), &page)
+}
+
+/// Tokens the user can trade, i.e. excluding any locked as proposal deposits.
+pub(crate) fn get_user_token_balance(user: Principal, property_id: u64) -> Result<u64, String> {
+    Ok(available_balance(user, property_id))
+}
+
+impl Paginated for Investment {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn sort_key(&self, field: &SortField) -> u64 {
+        match field {
+            SortField::CreatedAt => self.timestamp,
+            SortField::Price => self.investment_amount.minor_units().checked_div(self.token_amount).unwrap_or(0),
+            SortField::Yield => PROPERTY_STORAGE.with(|storage| {
+                storage.borrow().get(&self.property_id)
+                    .map(|property| yield_sort_key(property.rental_yield))
+                    .unwrap_or(0)
+            }),
+            SortField::Value => self.investment_amount.minor_units(),
+        }
+    }
 }
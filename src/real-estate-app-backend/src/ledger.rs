@@ -0,0 +1,184 @@
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::{CallResult, RejectionCode};
+use serde::Deserialize;
+use crate::types::{Account, Amount, OutgoingTransfer};
+
+// The subset of the ICRC-1 and ICRC-2 ledger interfaces the platform calls.
+
+#[derive(CandidType)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+/// Why a ledger transfer didn't complete.
+pub enum TransferFailure {
+    /// The ledger definitely didn't move the funds.
+    Rejected(String),
+    /// The transfer may or may not have happened; retrying it with the same memo
+    /// and `created_at_time` finds out.
+    Unknown(String),
+}
+
+/// Pulls `amount` from `from`'s main account into the canister's, using the
+/// allowance they approved. The ledger charges its fee to `from` on top. A retry
+/// with the same `memo` and `created_at_time` is deduplicated by the ledger.
+/// Returns the block index.
+pub async fn transfer_from(ledger: Principal, from: Principal, amount: Amount, memo: u64, created_at_time: u64) -> Result<u64, TransferFailure> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: Account { owner: from, subaccount: None },
+        to: Account { owner: ic_cdk::id(), subaccount: None },
+        amount: Nat::from(amount.minor_units()),
+        fee: None,
+        memo: Some(memo.to_be_bytes().to_vec()),
+        created_at_time: Some(created_at_time),
+    };
+
+    let result: CallResult<(Result<Nat, TransferFromError>,)> = ic_cdk::call(ledger, "icrc2_transfer_from", (args,)).await;
+    outcome(result)
+}
+
+/// Fixes the arguments of a payout out of the canister's main account. The ledger
+/// fee comes out of `amount`, so `to` receives `amount` less the fee. `memo` ties
+/// the ledger entry back to the withdrawal. Nothing is sent yet.
+pub async fn prepare_transfer(ledger: Principal, to: Account, amount: Amount, memo: u64) -> Result<OutgoingTransfer, String> {
+    let fee = ledger_fee(ledger).await?;
+    let net = amount.checked_sub(fee)
+        .ok()
+        .filter(|net| !net.is_zero())
+        .ok_or_else(|| "Amount does not cover the ledger fee".to_string())?;
+
+    Ok(OutgoingTransfer {
+        ledger,
+        to,
+        amount: net,
+        fee,
+        memo,
+        created_at_time: ic_cdk::api::time(),
+    })
+}
+
+/// Sends a prepared payout and returns its block index. Sending the same transfer
+/// again within the ledger's deduplication window returns the original block
+/// rather than paying twice.
+pub async fn send_transfer(transfer: &OutgoingTransfer) -> Result<u64, TransferFailure> {
+    let args = TransferArg {
+        from_subaccount: None,
+        to: transfer.to.clone(),
+        amount: Nat::from(transfer.amount.minor_units()),
+        fee: Some(Nat::from(transfer.fee.minor_units())),
+        memo: Some(transfer.memo.to_be_bytes().to_vec()),
+        created_at_time: Some(transfer.created_at_time),
+    };
+
+    let result: CallResult<(Result<Nat, TransferError>,)> = ic_cdk::call(transfer.ledger, "icrc1_transfer", (args,)).await;
+    outcome(result)
+}
+
+/// The two ledger error types share the variants that decide an outcome.
+trait LedgerError: std::fmt::Debug {
+    fn duplicate_of(self) -> Result<Nat, Self> where Self: Sized;
+    fn is_too_old(&self) -> bool;
+}
+
+impl LedgerError for TransferError {
+    fn duplicate_of(self) -> Result<Nat, Self> {
+        match self {
+            TransferError::Duplicate { duplicate_of } => Ok(duplicate_of),
+            error => Err(error),
+        }
+    }
+
+    fn is_too_old(&self) -> bool {
+        matches!(self, TransferError::TooOld)
+    }
+}
+
+impl LedgerError for TransferFromError {
+    fn duplicate_of(self) -> Result<Nat, Self> {
+        match self {
+            TransferFromError::Duplicate { duplicate_of } => Ok(duplicate_of),
+            error => Err(error),
+        }
+    }
+
+    fn is_too_old(&self) -> bool {
+        matches!(self, TransferFromError::TooOld)
+    }
+}
+
+/// Sorts a ledger call's result into a block index, a definite rejection or an
+/// unknown outcome. A duplicate is the earlier attempt going through.
+fn outcome<E: LedgerError>(result: CallResult<(Result<Nat, E>,)>) -> Result<u64, TransferFailure> {
+    match result {
+        Ok((Ok(block),)) => block_index(block).map_err(TransferFailure::Unknown),
+        Ok((Err(error),)) => match error.duplicate_of() {
+            Ok(block) => block_index(block).map_err(TransferFailure::Unknown),
+            // Past the deduplication window a retry can't tell whether an earlier
+            // attempt went through; only an admin who checked the ledger can.
+            Err(error) if error.is_too_old() => {
+                Err(TransferFailure::Unknown(format!("Ledger rejected the transfer: {:?}", error)))
+            }
+            Err(error) => Err(TransferFailure::Rejected(format!("Ledger rejected the transfer: {:?}", error))),
+        },
+        // The ledger never ran the call, or trapped and rolled it back.
+        Err((code @ (RejectionCode::DestinationInvalid | RejectionCode::CanisterReject | RejectionCode::CanisterError), message)) => {
+            Err(TransferFailure::Rejected(format!("Ledger call failed ({:?}): {}", code, message)))
+        }
+        Err((code, message)) => Err(TransferFailure::Unknown(format!("Ledger call failed ({:?}): {}", code, message))),
+    }
+}
+
+async fn ledger_fee(ledger: Principal) -> Result<Amount, String> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, message)| format!("Ledger call failed ({:?}): {}", code, message))?;
+    u64::try_from(&fee.0)
+        .map(Amount::from_minor)
+        .map_err(|_| "Ledger fee out of range".to_string())
+}
+
+fn block_index(block: Nat) -> Result<u64, String> {
+    u64::try_from(&block.0).map_err(|_| "Block index out of range".to_string())
+}
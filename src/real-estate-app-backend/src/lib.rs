@@ -1,27 +1,72 @@
 use candid::Principal;
 use ic_cdk_macros::*;
 
+mod money;
 mod types;
 mod storage;
 mod utils;
 mod property;
 mod investment;
+mod portfolio;
+mod holdings;
 mod user;
 mod marketplace;
 mod governance;
+mod dividend;
+mod market_data;
+mod fees;
+mod exchange;
+mod cash;
+mod ledger;
+mod offering;
+mod presale;
+mod redemption;
+mod corporate;
+mod valuation;
 mod analytics;
 mod compliance;
+mod migration;
 use types::*;
 
 // Explicitly re-export all module functions
 pub use property::*;
 pub use investment::*;
+pub use portfolio::*;
+pub use holdings::*;
 pub use user::*;
 pub use marketplace::*;
 pub use governance::*;
+pub use dividend::*;
+pub use market_data::*;
+pub use fees::*;
+pub use exchange::*;
+pub use cash::*;
+pub use offering::*;
+pub use presale::*;
+pub use redemption::*;
+pub use corporate::*;
+pub use valuation::*;
 pub use analytics::*;
 pub use compliance::*;
 
+#[init]
+fn init(_args: Option<UpgradeArgs>) {
+    storage::set_schema_version(storage::SCHEMA_VERSION);
+}
+
+#[post_upgrade]
+fn post_upgrade(args: Option<UpgradeArgs>) {
+    if storage::schema_version() == storage::SCHEMA_VERSION {
+        return;
+    }
+    migration::migrate_legacy_records(args);
+    migration::migrate_unlabelled_totals();
+    storage::rebuild_indexes();
+    holdings::backfill_holdings();
+    analytics::backfill_metrics();
+    storage::set_schema_version(storage::SCHEMA_VERSION);
+}
+
 // Manual function exports to ensure visibility
 #[update]
 pub fn create_property_wrapper(payload: CreatePropertyPayload) -> Result<Property, String> {
@@ -29,8 +74,13 @@ pub fn create_property_wrapper(payload: CreatePropertyPayload) -> Result<Propert
 }
 
 #[query]
-pub fn get_all_properties_wrapper() -> Vec<Property> {
-    property::get_all_properties()
+pub fn get_all_properties_wrapper(page: PageRequest) -> Page<Property> {
+    property::get_all_properties(page)
+}
+
+#[query]
+pub fn search_properties_wrapper(filter: PropertySearchFilter, page: PageRequest) -> Page<Property> {
+    property::search_properties(filter, page)
 }
 
 #[update]
@@ -38,9 +88,114 @@ pub fn invest_in_property_wrapper(payload: InvestmentPayload) -> Result<Investme
     investment::invest_in_property(payload)
 }
 
+#[update]
+pub fn sell_property_wrapper(property_id: u64, sale_price: Amount) -> Result<Redemption, String> {
+    redemption::sell_property(property_id, sale_price)
+}
+
+#[query]
+pub fn get_redemption_wrapper(property_id: u64) -> Option<Redemption> {
+    redemption::get_redemption(property_id)
+}
+
+#[update]
+pub fn apply_corporate_action_wrapper(property_id: u64, kind: CorporateActionKind) -> Result<CorporateAction, String> {
+    corporate::apply_corporate_action(property_id, kind)
+}
+
+#[query]
+pub fn get_corporate_actions_wrapper(property_id: u64) -> Vec<CorporateAction> {
+    corporate::get_corporate_actions(property_id)
+}
+
+#[update]
+pub fn create_offering_round_wrapper(payload: OfferingRoundPayload) -> Result<OfferingRound, String> {
+    offering::create_offering_round(payload)
+}
+
+#[update]
+pub fn subscribe_to_offering_wrapper(property_id: u64, round_id: u64, amount: Amount) -> Result<Subscription, String> {
+    offering::subscribe_to_offering(property_id, round_id, amount)
+}
+
+#[update]
+pub fn finalize_offering_round_wrapper(property_id: u64, round_id: u64) -> Result<OfferingRound, String> {
+    offering::finalize_offering_round(property_id, round_id)
+}
+
+#[update]
+pub fn cancel_offering_round_wrapper(property_id: u64, round_id: u64) -> Result<OfferingRound, String> {
+    offering::cancel_offering_round(property_id, round_id)
+}
+
+#[query]
+pub fn get_offering_rounds_wrapper(property_id: u64) -> Vec<OfferingRound> {
+    offering::get_offering_rounds(property_id)
+}
+
+#[query]
+pub fn get_offering_subscriptions_wrapper(round_id: u64) -> Vec<Subscription> {
+    offering::get_offering_subscriptions(round_id)
+}
+
+#[update]
+pub fn set_offering_access_wrapper(property_id: u64, payload: Option<OfferingAccessPayload>) -> Result<Option<OfferingAccess>, String> {
+    presale::set_offering_access(property_id, payload)
+}
+
+#[update]
+pub fn add_to_allowlist_wrapper(property_id: u64, investors: Vec<Principal>, allocation_cap: Option<Amount>) -> Result<u32, String> {
+    presale::add_to_allowlist(property_id, investors, allocation_cap)
+}
+
+#[update]
+pub fn remove_from_allowlist_wrapper(property_id: u64, investors: Vec<Principal>) -> Result<u32, String> {
+    presale::remove_from_allowlist(property_id, investors)
+}
+
+#[query]
+pub fn get_offering_access_wrapper(property_id: u64) -> Option<OfferingAccess> {
+    presale::get_offering_access(property_id)
+}
+
 #[query]
-pub fn get_investments_by_user_wrapper(user: Principal) -> Vec<Investment> {
-    investment::get_investments_by_user(user)
+pub fn get_allowlist_wrapper(property_id: u64) -> Vec<AllowlistEntry> {
+    presale::get_allowlist(property_id)
+}
+
+#[query]
+pub fn get_investments_by_user_wrapper(user: Principal, page: PageRequest) -> Page<Investment> {
+    investment::get_investments_by_user(user, page)
+}
+
+#[query]
+pub fn get_portfolio_wrapper(owner: Principal) -> Portfolio {
+    portfolio::get_portfolio(owner)
+}
+
+#[update]
+pub fn set_cost_basis_method_wrapper(method: CostBasisMethod) -> Result<(), String> {
+    holdings::set_cost_basis_method(method)
+}
+
+#[query]
+pub fn get_cost_basis_method_wrapper(owner: Principal) -> CostBasisMethod {
+    holdings::get_cost_basis_method(owner)
+}
+
+#[query]
+pub fn get_tax_lots_wrapper(owner: Principal, property_id: Option<u64>) -> Vec<TaxLot> {
+    holdings::get_tax_lots(owner, property_id)
+}
+
+#[query]
+pub fn get_realized_gains_wrapper(owner: Principal) -> Vec<RealizedGain> {
+    holdings::get_realized_gains(owner)
+}
+
+#[query]
+pub fn get_tax_statement_wrapper(owner: Principal, year: u32) -> TaxStatement {
+    holdings::get_tax_statement(owner, year)
 }
 
 #[update]
@@ -53,6 +208,11 @@ pub fn get_user_profile_wrapper(user: Principal) -> Result<UserProfile, String>
     user::get_user_profile(user)
 }
 
+#[update]
+pub fn set_display_currency_wrapper(code: String) -> Result<UserProfile, String> {
+    user::set_display_currency(code)
+}
+
 #[update]
 pub fn create_token_order_wrapper(payload: CreateOrderPayload) -> Result<TokenOrder, String> {
     marketplace::create_token_order(payload)
@@ -63,25 +223,209 @@ pub fn execute_order_wrapper(order_id: u64) -> Result<TokenOrder, String> {
     marketplace::execute_order(order_id)
 }
 
+#[update]
+pub fn cancel_order_wrapper(order_id: u64) -> Result<TokenOrder, String> {
+    marketplace::cancel_order(order_id)
+}
+
+#[update]
+pub fn expire_orders_wrapper(property_id: u64) -> Result<u32, String> {
+    marketplace::expire_orders(property_id)
+}
+
+#[update]
+pub async fn deposit_wrapper(asset: Principal, amount: Amount) -> Result<CashBalance, String> {
+    cash::deposit(asset, amount).await
+}
+
+#[update]
+pub async fn withdraw_wrapper(asset: Principal, amount: Amount, to: Account) -> Result<CashBalance, String> {
+    cash::withdraw(asset, amount, to).await
+}
+
+#[update]
+pub async fn reconcile_withdrawal_wrapper(id: u64) -> Result<u64, String> {
+    cash::reconcile_withdrawal(id).await
+}
+
+#[update]
+pub fn resolve_pending_withdrawal_wrapper(id: u64, block_index: Option<u64>) -> Result<(), String> {
+    cash::resolve_pending_withdrawal(id, block_index)
+}
+
+#[query]
+pub fn get_pending_withdrawals_wrapper() -> Vec<PendingWithdrawal> {
+    cash::get_pending_withdrawals()
+}
+
+#[update]
+pub async fn reconcile_deposit_wrapper(id: u64) -> Result<u64, String> {
+    cash::reconcile_deposit(id).await
+}
+
+#[update]
+pub fn resolve_pending_deposit_wrapper(id: u64, block_index: Option<u64>) -> Result<(), String> {
+    cash::resolve_pending_deposit(id, block_index)
+}
+
+#[query]
+pub fn get_pending_deposits_wrapper() -> Vec<PendingDeposit> {
+    cash::get_pending_deposits()
+}
+
+#[query]
+pub fn get_cash_balances_wrapper(owner: Principal) -> Vec<CashBalance> {
+    cash::get_cash_balances(owner)
+}
+
+#[query]
+pub fn get_cash_transactions_wrapper(owner: Principal, asset: Option<Principal>, page: PageRequest) -> Page<CashTransaction> {
+    cash::get_cash_transactions(owner, asset, page)
+}
+
+#[query]
+pub fn get_active_orders_wrapper(property_id: u64, page: PageRequest) -> Page<TokenOrder> {
+    marketplace::get_active_orders(property_id, page)
+}
+
+#[query]
+pub fn get_user_orders_wrapper(user: Principal, page: PageRequest) -> Page<TokenOrder> {
+    marketplace::get_user_orders(user, page)
+}
+
+#[update]
+pub fn register_appraiser_wrapper(appraiser: Principal, name: String, license_number: String) -> Result<Appraiser, String> {
+    valuation::register_appraiser(appraiser, name, license_number)
+}
+
+#[update]
+pub fn remove_appraiser_wrapper(appraiser: Principal) -> Result<Appraiser, String> {
+    valuation::remove_appraiser(appraiser)
+}
+
+#[query]
+pub fn get_appraisers_wrapper() -> Vec<Appraiser> {
+    valuation::get_appraisers()
+}
+
+#[update]
+pub fn submit_appraisal_wrapper(payload: AppraisalPayload) -> Result<Appraisal, String> {
+    valuation::submit_appraisal(payload)
+}
+
+#[update]
+pub fn approve_appraisal_wrapper(property_id: u64, appraisal_id: u64) -> Result<Appraisal, String> {
+    valuation::approve_appraisal(property_id, appraisal_id)
+}
+
+#[update]
+pub fn reject_appraisal_wrapper(property_id: u64, appraisal_id: u64) -> Result<Appraisal, String> {
+    valuation::reject_appraisal(property_id, appraisal_id)
+}
+
+#[query]
+pub fn get_appraisals_wrapper(property_id: u64) -> Vec<Appraisal> {
+    valuation::get_appraisals(property_id)
+}
+
+#[query]
+pub fn get_order_book_wrapper(property_id: u64, quote_asset: Option<Principal>, depth: u32) -> Result<OrderBook, String> {
+    marketplace::get_order_book(property_id, quote_asset, depth)
+}
+
+#[query]
+pub fn get_market_data_wrapper(property_id: u64) -> Option<MarketData> {
+    market_data::get_market_data(property_id)
+}
+
+#[query]
+pub fn get_candles_wrapper(property_id: u64, interval: CandleInterval, from: u64, to: u64) -> Vec<Candle> {
+    market_data::get_candles(property_id, interval, from, to)
+}
+
+#[query]
+pub fn get_recent_trades_wrapper(property_id: u64, limit: u32) -> Vec<Trade> {
+    market_data::get_recent_trades(property_id, limit)
+}
+
+#[update]
+pub fn set_fee_config_wrapper(config: FeeConfig) -> Result<(), String> {
+    fees::set_fee_config(config)
+}
+
+#[update]
+pub fn set_property_fee_override_wrapper(property_id: u64, config: Option<FeeConfig>) -> Result<(), String> {
+    fees::set_property_fee_override(property_id, config)
+}
+
 #[query]
-pub fn get_active_orders_wrapper(property_id: u64) -> Vec<TokenOrder> {
-    marketplace::get_active_orders(property_id)
+pub fn get_currency_wrapper() -> Currency {
+    money::platform_currency()
+}
+
+#[update]
+pub fn register_settlement_asset_wrapper(asset: SettlementAsset, rate: u64) -> Result<SettlementAsset, String> {
+    exchange::register_settlement_asset(asset, rate)
 }
 
 #[query]
-pub fn get_user_orders_wrapper(user: Principal) -> Vec<TokenOrder> {
-    marketplace::get_user_orders(user)
+pub fn get_settlement_assets_wrapper() -> Vec<SettlementAsset> {
+    exchange::get_settlement_assets()
 }
 
 #[update]
-pub fn create_proposal_wrapper(
-    property_id: u64,
-    title: String,
-    description: String,
-    proposal_type: ProposalType,
-    voting_duration_hours: u64,
-) -> Result<GovernanceProposal, String> {
-    governance::create_proposal(property_id, title, description, proposal_type, voting_duration_hours)
+pub fn register_display_currency_wrapper(currency: Currency, rate: u64) -> Result<Currency, String> {
+    exchange::register_display_currency(currency, rate)
+}
+
+#[query]
+pub fn get_display_currencies_wrapper() -> Vec<Currency> {
+    exchange::get_display_currencies()
+}
+
+#[update]
+pub fn set_exchange_rate_wrapper(code: String, rate: u64) -> Result<ExchangeRate, String> {
+    exchange::set_exchange_rate(code, rate)
+}
+
+#[query]
+pub fn get_exchange_rates_wrapper() -> Vec<ExchangeRate> {
+    exchange::get_exchange_rates()
+}
+
+#[query]
+pub fn get_fee_config_wrapper(property_id: Option<u64>) -> FeeConfig {
+    fees::get_fee_config(property_id)
+}
+
+#[query]
+pub fn get_fee_ledger_wrapper(property_id: Option<u64>, source: Option<FeeSource>, page: PageRequest) -> Page<FeeEntry> {
+    fees::get_fee_ledger(property_id, source, page)
+}
+
+#[query]
+pub fn get_fee_summary_wrapper() -> Vec<FeeSummary> {
+    fees::get_fee_summary()
+}
+
+#[update]
+pub async fn withdraw_fees_wrapper(asset: Principal, amount: Amount, recipient: Principal) -> Result<TreasuryWithdrawal, String> {
+    fees::withdraw_fees(asset, amount, recipient).await
+}
+
+#[query]
+pub fn get_treasury_withdrawals_wrapper(asset: Option<Principal>, page: PageRequest) -> Page<TreasuryWithdrawal> {
+    fees::get_treasury_withdrawals(asset, page)
+}
+
+#[update]
+pub fn create_proposal_wrapper(payload: CreateProposalPayload) -> Result<GovernanceProposal, String> {
+    governance::create_proposal(payload)
+}
+
+#[update]
+pub fn amend_proposal_wrapper(proposal_id: u64, payload: AmendProposalPayload) -> Result<GovernanceProposal, String> {
+    governance::amend_proposal(proposal_id, payload)
 }
 
 #[update]
@@ -89,9 +433,84 @@ pub fn vote_on_proposal_wrapper(proposal_id: u64, vote_for: bool) -> Result<(),
     governance::vote_on_proposal(proposal_id, vote_for)
 }
 
+#[update]
+pub fn finalize_proposal_wrapper(proposal_id: u64) -> Result<GovernanceProposal, String> {
+    governance::finalize_proposal(proposal_id)
+}
+
+#[update]
+pub fn delegate_votes_wrapper(delegate: Principal, property_id: Option<u64>) -> Result<Delegation, String> {
+    governance::delegate_votes(delegate, property_id)
+}
+
+#[update]
+pub fn revoke_delegation_wrapper(property_id: Option<u64>) -> Result<(), String> {
+    governance::revoke_delegation(property_id)
+}
+
+#[query]
+pub fn get_delegations_wrapper(delegator: Principal) -> Vec<Delegation> {
+    governance::get_delegations(delegator)
+}
+
 #[query]
-pub fn get_property_proposals_wrapper(property_id: u64) -> Vec<GovernanceProposal> {
-    governance::get_property_proposals(property_id)
+pub fn get_delegators_wrapper(delegate: Principal, property_id: Option<u64>) -> Vec<Delegation> {
+    governance::get_delegators(delegate, property_id)
+}
+
+#[update]
+pub fn execute_proposal_wrapper(proposal_id: u64) -> Result<GovernanceProposal, String> {
+    governance::execute_proposal(proposal_id)
+}
+
+#[update]
+pub fn distribute_dividends_wrapper(property_id: u64, total_amount: Amount) -> Result<DividendDistribution, String> {
+    dividend::distribute_dividends(property_id, total_amount)
+}
+
+#[query]
+pub fn get_property_distributions_wrapper(property_id: u64) -> Vec<DividendDistribution> {
+    dividend::get_property_distributions(property_id)
+}
+
+#[update]
+pub fn set_governance_config_wrapper(config: GovernanceConfig) -> Result<(), String> {
+    governance::set_governance_config(config)
+}
+
+#[query]
+pub fn get_governance_config_wrapper() -> GovernanceConfig {
+    governance::get_governance_config()
+}
+
+#[update]
+pub fn set_proposal_thresholds_wrapper(proposal_type: ProposalType, thresholds: ProposalThresholds) -> Result<(), String> {
+    governance::set_proposal_thresholds(proposal_type, thresholds)
+}
+
+#[query]
+pub fn get_proposal_thresholds_wrapper(proposal_type: ProposalType) -> ProposalThresholds {
+    governance::get_proposal_thresholds(proposal_type)
+}
+
+#[update]
+pub fn change_vote_wrapper(proposal_id: u64, vote_for: bool) -> Result<(), String> {
+    governance::change_vote(proposal_id, vote_for)
+}
+
+#[query]
+pub fn get_proposal_votes_wrapper(proposal_id: u64) -> Vec<Vote> {
+    governance::get_proposal_votes(proposal_id)
+}
+
+#[query]
+pub fn get_voter_votes_wrapper(voter: Principal) -> Vec<Vote> {
+    governance::get_voter_votes(voter)
+}
+
+#[query]
+pub fn get_property_proposals_wrapper(property_id: u64, page: PageRequest) -> Page<GovernanceProposal> {
+    governance::get_property_proposals(property_id, page)
 }
 
 #[update]
@@ -104,5 +523,15 @@ pub fn get_enhanced_platform_stats() -> PlatformStats {
     analytics::get_platform_analytics()
 }
 
+#[query]
+pub fn get_property_analytics_wrapper(property_id: u64) -> Result<PropertyAnalytics, String> {
+    analytics::get_property_analytics(property_id)
+}
+
+#[query]
+pub fn get_platform_snapshots_wrapper(from_day: u64, to_day: u64) -> Vec<PlatformSnapshot> {
+    analytics::get_platform_snapshots(from_day, to_day)
+}
+
 // Generate complete Candid interface
 ic_cdk::export_candid!();
@@ -0,0 +1,259 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::get_current_time;
+
+const NANOS_PER_HOUR: u64 = 3600 * 1_000_000_000;
+const WINDOW_24H: u64 = 24 * NANOS_PER_HOUR;
+const MAX_TRADES_PER_QUERY: usize = 500;
+
+/// Statistics over the trailing 24 hours as of now, so volume and change decay
+/// even when the property hasn't traded recently.
+#[query]
+pub fn get_market_data(property_id: u64) -> Option<MarketData> {
+    MARKET_DATA_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .map(|market_data| rolled_forward(market_data, get_current_time()))
+}
+
+/// Candles whose interval opened within `[from, to]`, oldest first. Intervals
+/// without trades are omitted.
+#[query]
+pub fn get_candles(property_id: u64, interval: CandleInterval, from: u64, to: u64) -> Vec<Candle> {
+    let key = interval_key(&interval);
+    CANDLE_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, key, from)..=(property_id, key, to))
+            .map(|(_, candle)| candle)
+            .collect()
+    })
+}
+
+/// Most recent trades first, at most 500.
+#[query]
+pub fn get_recent_trades(property_id: u64, limit: u32) -> Vec<Trade> {
+    TRADE_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, 0, 0)..=(property_id, u64::MAX, u64::MAX))
+            .rev()
+            .take((limit as usize).min(MAX_TRADES_PER_QUERY))
+            .map(|(_, trade)| trade)
+            .collect()
+    })
+}
+
+/// Stores the fill and, when it was quoted in the property's settlement asset,
+/// folds it into every candle interval and refreshes the property's market data.
+/// An empty fill is ignored so it can't move the price.
+pub fn record_trade(order: &TokenOrder, buyer: Principal, seller: Principal) {
+    if order.token_amount == 0 || order.price_per_token.is_zero() {
+        return;
+    }
+    let trade = Trade {
+        id: get_next_id(),
+        property_id: order.property_id,
+        order_id: order.id,
+        buyer,
+        seller,
+        price_per_token: order.price_per_token,
+        token_amount: order.token_amount,
+        total_price: order.total_price,
+        quote_asset: order.quote_asset,
+        timestamp: get_current_time(),
+    };
+
+    TRADE_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((trade.property_id, trade.timestamp, trade.id), trade.clone())
+    });
+
+    let settlement_asset = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&trade.property_id))
+        .map(|property| property.settlement_asset);
+    if settlement_asset != Some(trade.quote_asset) {
+        return;
+    }
+
+    for interval in [CandleInterval::Hour, CandleInterval::Day, CandleInterval::Week] {
+        update_candle(&trade, interval);
+    }
+
+    let market_data = stored_market_data(trade.property_id, trade.price_per_token, trade.timestamp);
+    let market_data = rolled_forward(
+        MarketData { current_price: trade.price_per_token, ..market_data },
+        trade.timestamp,
+    );
+
+    MARKET_DATA_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(trade.property_id, market_data)
+    });
+}
+
+/// Stores the liquidity score of the property's current order book.
+pub fn set_liquidity_score(property_id: u64, liquidity_score: Rate) {
+    let now = get_current_time();
+    let issue_price = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .map(|property| property.price_per_token)
+        .unwrap_or(Amount::ZERO);
+    let market_data = MarketData {
+        liquidity_score,
+        ..stored_market_data(property_id, issue_price, now)
+    };
+    MARKET_DATA_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(property_id, market_data)
+    });
+}
+
+/// The stored market data, or a blank record priced at `price` for a property
+/// that has none yet.
+fn stored_market_data(property_id: u64, price: Amount, now: u64) -> MarketData {
+    MARKET_DATA_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .unwrap_or_else(|| MarketData {
+            property_id,
+            current_price: price,
+            price_change_24h: Rate::ZERO,
+            trading_volume_24h: 0,
+            market_cap: Amount::ZERO,
+            liquidity_score: Rate::ZERO,
+            nav_per_token: nav_per_token(property_id),
+            last_updated: now,
+        })
+}
+
+/// Publishes the property's appraised net asset value per token next to its price.
+pub fn refresh_nav(property_id: u64) {
+    let now = get_current_time();
+    let issue_price = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .map(|property| property.price_per_token)
+        .unwrap_or(Amount::ZERO);
+    let market_data = MarketData {
+        nav_per_token: nav_per_token(property_id),
+        ..stored_market_data(property_id, issue_price, now)
+    };
+    MARKET_DATA_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(property_id, market_data)
+    });
+}
+
+/// Sets the last price after a split, so holdings keep their value until the next
+/// trade. Trades and candles keep the prices they traded at.
+pub fn restate_price(property_id: u64, price: Amount) {
+    MARKET_DATA_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if let Some(mut market_data) = storage.get(&property_id) {
+            market_data.current_price = price;
+            storage.insert(property_id, market_data);
+        }
+    });
+    refresh_nav(property_id);
+}
+
+fn nav_per_token(property_id: u64) -> Amount {
+    PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .and_then(|property| property.total_value.per_unit(property.total_tokens, Rounding::HalfUp).ok())
+        .unwrap_or(Amount::ZERO)
+}
+
+fn update_candle(trade: &Trade, interval: CandleInterval) {
+    let key = interval_key(&interval);
+    let length = interval_nanos(&interval);
+    let open_time = trade.timestamp - trade.timestamp % length;
+    let price = trade.price_per_token;
+
+    CANDLE_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let candle = match storage.get(&(trade.property_id, key, open_time)) {
+            Some(candle) => {
+                let mut quote_volume = candle.quote_volume;
+                quote_volume += trade.total_price;
+                Candle {
+                    high: candle.high.max(price),
+                    low: candle.low.min(price),
+                    close: price,
+                    volume: candle.volume + trade.token_amount,
+                    quote_volume,
+                    trade_count: candle.trade_count + 1,
+                    ..candle
+                }
+            }
+            None => Candle {
+                property_id: trade.property_id,
+                interval,
+                open_time,
+                open: price,
+                high: price,
+                low: price,
+                close: price,
+                volume: trade.token_amount,
+                quote_volume: trade.total_price,
+                trade_count: 1,
+            },
+        };
+        storage.insert((trade.property_id, key, open_time), candle);
+    });
+}
+
+/// Recomputes the rolling fields of `market_data` for the 24 hours ending at `now`,
+/// over trades in the settlement asset. The change is measured against the last
+/// trade before the window, or the first trade inside it for properties that only
+/// started trading within the day.
+fn rolled_forward(market_data: MarketData, now: u64) -> MarketData {
+    let property_id = market_data.property_id;
+    let window_start = now.saturating_sub(WINDOW_24H);
+    let Some(property) = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id)) else {
+        return market_data;
+    };
+    let in_settlement_asset = |trade: &Trade| trade.quote_asset == property.settlement_asset;
+
+    let (volume, first_in_window) = TRADE_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        let mut trades = storage.range((property_id, window_start, 0)..=(property_id, now, u64::MAX))
+            .map(|(_, trade)| trade)
+            .filter(in_settlement_asset);
+        let first = trades.next();
+        let volume = first.iter().map(|trade| trade.token_amount).sum::<u64>()
+            + trades.map(|trade| trade.token_amount).sum::<u64>();
+        (volume, first)
+    });
+    let reference_price = TRADE_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, 0, 0)..(property_id, window_start, 0))
+            .rev()
+            .map(|(_, trade)| trade)
+            .find(in_settlement_asset)
+            .map(|trade| trade.price_per_token)
+    })
+    .or(first_in_window.map(|trade| trade.price_per_token));
+
+    let price_change_24h = match reference_price {
+        Some(reference) => Rate::ratio(
+            market_data.current_price.minor_units() as i128 - reference.minor_units() as i128,
+            reference.minor_units() as i128,
+            Rounding::HalfUp,
+        ),
+        None => Rate::ZERO,
+    };
+
+    MarketData {
+        price_change_24h,
+        trading_volume_24h: volume,
+        market_cap: market_data.current_price.times(property.total_tokens).unwrap_or(Amount::from_minor(u64::MAX)),
+        last_updated: now,
+        ..market_data
+    }
+}
+
+fn interval_key(interval: &CandleInterval) -> u8 {
+    match interval {
+        CandleInterval::Hour => 0,
+        CandleInterval::Day => 1,
+        CandleInterval::Week => 2,
+    }
+}
+
+/// Candles are aligned to the Unix epoch, so weekly candles open on Thursdays (UTC).
+fn interval_nanos(interval: &CandleInterval) -> u64 {
+    match interval {
+        CandleInterval::Hour => NANOS_PER_HOUR,
+        CandleInterval::Day => 24 * NANOS_PER_HOUR,
+        CandleInterval::Week => 7 * 24 * NANOS_PER_HOUR,
+    }
+}
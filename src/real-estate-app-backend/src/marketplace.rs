@@ -1,10 +1,24 @@
-use crate::investment::{Investment, InvestmentStatus};
+use crate::analytics::{decrement_metric, increment_metric, Metric};
+use crate::cash::{cash_balance, credit_cash, debit_cash, lock_cash, spend_locked_cash, unlock_cash};
+use crate::exchange::{settlement_asset, to_platform_currency};
+use crate::fees::{collect_fee, fee_for};
+use crate::holdings::{lock_tokens, locked_balance, record_acquisition, record_disposal, unlock_tokens};
+use crate::investment::{get_user_token_balance, Investment, InvestmentStatus};
+use crate::market_data::{record_trade, set_liquidity_score};
+use crate::portfolio::refresh_portfolio;
 use crate::storage::*;
 use crate::types::*;
 use crate::utils::*;
 use candid::Principal;
 use ic_cdk::api::time;
 use ic_cdk_macros::*;
+use std::collections::BTreeMap;
+
+const DEFAULT_BOOK_DEPTH: u32 = 20;
+const MAX_BOOK_DEPTH: u32 = 100;
+const LIQUIDITY_BAND: Rate = Rate::from_bps(500);
+const LIQUIDITY_DEPTH_TARGET: Rate = Rate::from_bps(1_000);
+const MAX_LIQUID_SPREAD: Rate = Rate::from_bps(1_000);
 
 #[update]
 pub fn create_token_order(payload: CreateOrderPayload) -> Result<TokenOrder, String> {
@@ -12,22 +26,44 @@ pub fn create_token_order(payload: CreateOrderPayload) -> Result<TokenOrder, Str
     validate_kyc(caller)?;
 
     // Verify property exists
-    let _property = PROPERTY_STORAGE.with(|storage| {
+    let property = PROPERTY_STORAGE.with(|storage| {
         storage.borrow().get(&payload.property_id)
             .ok_or_else(|| "Property not found".to_string())
     })?;
+    if matches!(property.status, PropertyStatus::Sold) {
+        return Err("Trading has halted; the property has been sold".to_string());
+    }
+    let quote_asset = settlement_asset(payload.quote_asset.unwrap_or(property.settlement_asset))?.ledger;
 
-    // For sell orders, verify user has enough tokens
-    if matches!(payload.order_type, OrderType::Sell) {
-        let user_tokens = get_user_token_balance(caller, payload.property_id)?;
-        if user_tokens < payload.token_amount {
-            return Err("Insufficient token balance".to_string());
-        }
+    // A zero-sized or free order escrows nothing, yet filling it would still set
+    // the market price.
+    if payload.token_amount == 0 {
+        return Err("Order must be for at least one token".to_string());
+    }
+    if payload.price_per_token.is_zero() {
+        return Err("Price per token must be greater than zero".to_string());
     }
+    if payload.expires_in_hours == 0 {
+        return Err("Order must stay open for at least an hour".to_string());
+    }
+    let current_time = time();
+    let expires_at = payload.expires_in_hours
+        .checked_mul(3600 * 1_000_000_000)
+        .and_then(|lifetime| current_time.checked_add(lifetime))
+        .ok_or_else(|| "Order expiry is too far in the future".to_string())?;
+
+    let total_price = payload.price_per_token.times(payload.token_amount)?;
+    let maker_fee = fee_for(&FeeSource::Maker, payload.property_id, total_price)?;
 
+    let escrow = total_price.checked_add(maker_fee)?;
     let order_id = get_next_id();
-    let current_time = time();
-    let expires_at = current_time + (payload.expires_in_hours * 3600 * 1_000_000_000);
+
+    // Escrow what the maker is offering: tokens for a sell order, the price plus
+    // the maker fee for a buy order.
+    match payload.order_type {
+        OrderType::Sell => lock_tokens(caller, payload.property_id, payload.token_amount)?,
+        OrderType::Buy => lock_cash(caller, quote_asset, escrow, order_id)?,
+    }
 
     let order = TokenOrder {
         id: order_id,
@@ -36,16 +72,20 @@ pub fn create_token_order(payload: CreateOrderPayload) -> Result<TokenOrder, Str
         buyer: None,
         token_amount: payload.token_amount,
         price_per_token: payload.price_per_token,
-        total_price: payload.token_amount * payload.price_per_token,
+        total_price,
         order_type: payload.order_type,
         status: OrderStatus::Active,
         created_at: current_time,
         expires_at,
+        maker_fee,
+        taker_fee: Amount::ZERO,
+        quote_asset,
+        filled_at: None,
     };
 
-    ORDER_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(order_id, order.clone())
-    });
+    insert_order(order.clone());
+    increment_metric(Metric::ActiveOrders, 1);
+    refresh_liquidity_score(order.property_id);
 
     Ok(order)
 }
@@ -63,178 +103,345 @@ pub fn execute_order(order_id: u64) -> Result<TokenOrder, String> {
     if !matches!(order.status, OrderStatus::Active) {
         return Err("Order is not active".to_string());
     }
+    if order.seller == caller {
+        return Err("Cannot fill your own order".to_string());
+    }
 
-    if order.expires_at < time() {
-        order.status = OrderStatus::Expired;
-        ORDER_STORAGE.with(|storage| {
-            storage.borrow_mut().insert(order_id, order.clone())
-        });
+    let now = time();
+    if order.expires_at < now {
+        close_order(order, OrderStatus::Expired)?;
         return Err("Order has expired".to_string());
     }
 
-    // Execute the trade
+    // The order's creator is the maker and the caller the taker. The maker fee was
+    // fixed when the order was placed; each side's fee is taken out of the
+    // seller's proceeds or added to the buyer's cost.
+    order.taker_fee = fee_for(&FeeSource::Taker, order.property_id, order.total_price)?;
+    let volume = to_platform_currency(order.quote_asset, order.total_price)?;
+
+    // `seller` is whoever placed the order, so on a buy order the caller sold.
+    let (buyer, seller, buyer_fee, seller_fee) = match order.order_type {
+        OrderType::Buy => (order.seller, caller, order.maker_fee, order.taker_fee),
+        OrderType::Sell => (caller, order.seller, order.taker_fee, order.maker_fee),
+    };
+    let buyer_cost = order.total_price.checked_add(buyer_fee)?;
+    let seller_proceeds = order.total_price.checked_sub(seller_fee)?;
+
+    // Delivery versus payment: everything that can fail is checked before either
+    // leg moves, so the tokens and the funds change hands together or not at all.
     match order.order_type {
         OrderType::Buy => {
-            // Caller is selling to the buy order
-            let seller_tokens = get_user_token_balance(caller, order.property_id)?;
-            if seller_tokens < order.token_amount {
+            if get_user_token_balance(seller, order.property_id)? < order.token_amount {
                 return Err("Insufficient tokens to sell".to_string());
             }
-            
-            // Transfer tokens from seller to buyer
-            transfer_tokens(caller, order.seller, order.property_id, order.token_amount)?;
-            order.buyer = Some(caller);
+            if cash_balance(buyer, order.quote_asset).locked < buyer_cost {
+                return Err("The buyer's escrow no longer covers the order".to_string());
+            }
         }
         OrderType::Sell => {
-            // Caller is buying from the sell order
-            // Transfer tokens from seller to buyer
-            transfer_tokens(order.seller, caller, order.property_id, order.token_amount)?;
-            order.buyer = Some(caller);
+            if cash_balance(buyer, order.quote_asset).available < buyer_cost {
+                return Err("Insufficient available balance".to_string());
+            }
+            if locked_balance(seller, order.property_id) < order.token_amount {
+                return Err("The seller's escrow no longer covers the order".to_string());
+            }
         }
     }
+    cash_balance(seller, order.quote_asset).available.checked_add(seller_proceeds)?;
+
+    // The checks above cover every failure below, so an error here is a bug; trap
+    // to roll back the whole fill rather than leave one leg moved.
+    order.filled_at = Some(now);
+    let settled = (|| -> Result<(), String> {
+        match order.order_type {
+            OrderType::Buy => spend_locked_cash(buyer, order.quote_asset, buyer_cost, CashTransactionKind::TradePurchase, order.id)?,
+            OrderType::Sell => {
+                debit_cash(buyer, order.quote_asset, buyer_cost, CashTransactionKind::TradePurchase, order.id)?;
+                unlock_tokens(seller, order.property_id, order.token_amount);
+            }
+        }
+        transfer_tokens(seller, buyer, &order, seller_fee, buyer_fee)?;
+        credit_cash(seller, order.quote_asset, seller_proceeds, CashTransactionKind::TradeSale, order.id)?;
+        Ok(())
+    })();
+    settled.unwrap_or_else(|error| ic_cdk::trap(&error));
+    order.buyer = Some(caller);
+
+    collect_fee(FeeSource::Maker, order.property_id, order.quote_asset, order.seller, order.total_price, order.maker_fee, order.id);
+    collect_fee(FeeSource::Taker, order.property_id, order.quote_asset, caller, order.total_price, order.taker_fee, order.id);
 
     order.status = OrderStatus::Filled;
-    ORDER_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(order_id, order.clone())
-    });
+    insert_order(order.clone());
+    decrement_metric(Metric::ActiveOrders, 1);
+    increment_metric(Metric::TradingVolume, volume.minor_units());
+
+    // Both sides' holdings changed
+    refresh_portfolio(order.seller);
+    if let Some(buyer) = order.buyer {
+        refresh_portfolio(buyer);
+    }
 
-    // Update market data
-    update_market_data(order.property_id, order.price_per_token, order.token_amount)?;
+    record_trade(&order, buyer, seller);
+    refresh_liquidity_score(order.property_id);
 
     Ok(order)
 }
 
-#[query]
-pub fn get_active_orders(property_id: u64) -> Vec<TokenOrder> {
-    ORDER_STORAGE.with(|storage| {
-        storage.borrow()
-            .iter()
-            .filter(|(_, order)| {
-                order.property_id == property_id && 
-                matches!(order.status, OrderStatus::Active) &&
-                order.expires_at > time()
-            })
-            .map(|(_, order)| order)
-            .collect()
-    })
+/// Withdraws an active order and returns its escrow to the maker.
+#[update]
+pub fn cancel_order(order_id: u64) -> Result<TokenOrder, String> {
+    let caller = is_authenticated()?;
+
+    let order = ORDER_STORAGE.with(|storage| {
+        storage.borrow().get(&order_id)
+            .ok_or_else(|| "Order not found".to_string())
+    })?;
+
+    if order.seller != caller {
+        return Err("Only the order's creator can cancel it".to_string());
+    }
+    if !matches!(order.status, OrderStatus::Active) {
+        return Err("Order is not active".to_string());
+    }
+
+    close_order(order, OrderStatus::Cancelled)
 }
 
-#[query]
-pub fn get_user_orders(user: Principal) -> Vec<TokenOrder> {
-    ORDER_STORAGE.with(|storage| {
-        storage.borrow()
-            .iter()
-            .filter(|(_, order)| order.seller == user || order.buyer == Some(user))
-            .map(|(_, order)| order)
-            .collect()
-    })
-}
-
-fn get_user_token_balance(user: Principal, property_id: u64) -> Result<u64, String> {
-    INVESTMENT_STORAGE.with(|storage| {
-        let balance = storage.borrow()
-            .iter()
-            .filter(|(_, investment)| investment.investor == user && investment.property_id == property_id)
-            .map(|(_, investment)| investment.token_amount)
-            .sum();
-        Ok(balance)
-    })
-}
-
-fn transfer_tokens(from: Principal, to: Principal, property_id: u64, amount: u64) -> Result<(), String> {
-    // Create investment record for the buyer
-    let investment_id = get_next_id();
-    let investment = Investment {
-        id: investment_id,
-        investor: to,
-        property_id,
-        token_amount: amount,
-        investment_amount: 0, // Will be calculated based on order price
-        timestamp: time(),
-        status: InvestmentStatus::Confirmed,
-    };
+/// Marks the property's lapsed orders expired and refunds their escrow. Anyone may
+/// call this; a lapsed order is also expired when someone tries to fill it.
+#[update]
+pub fn expire_orders(property_id: u64) -> Result<u32, String> {
+    let now = time();
+    let lapsed: Vec<TokenOrder> = orders_by_property_and_status(property_id, &OrderStatus::Active)
+        .into_iter()
+        .filter(|order| order.expires_at < now)
+        .collect();
 
-    INVESTMENT_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(investment_id, investment)
-    });
+    let count = lapsed.len() as u32;
+    for order in lapsed {
+        close_order(order, OrderStatus::Expired)?;
+    }
+    Ok(count)
+}
 
-    // Update portfolios
-    update_portfolio_after_trade(from, to, property_id, amount)?;
+/// Cancels every active order on the property and refunds their escrow.
+pub fn cancel_property_orders(property_id: u64) -> Result<u32, String> {
+    let orders = orders_by_property_and_status(property_id, &OrderStatus::Active);
+    let count = orders.len() as u32;
+    for order in orders {
+        close_order(order, OrderStatus::Cancelled)?;
+    }
+    Ok(count)
+}
 
-    Ok(())
+/// The property's active orders restated for a split: the token amount scales by
+/// `numerator / denominator` and the price by the inverse, so each order's total,
+/// fees and escrow stay the same. Nothing is written.
+pub fn rescaled_orders(property_id: u64, numerator: u64, denominator: u64) -> Result<Vec<TokenOrder>, String> {
+    orders_by_property_and_status(property_id, &OrderStatus::Active)
+        .into_iter()
+        .map(|mut order| {
+            let token_amount = mul_div_exact(order.token_amount, numerator, denominator);
+            let price = mul_div_exact(order.price_per_token.minor_units(), denominator, numerator);
+            match (token_amount, price) {
+                (Some(token_amount), Some(price)) => {
+                    order.token_amount = token_amount;
+                    order.price_per_token = Amount::from_minor(price);
+                    Ok(order)
+                }
+                _ => Err(format!("Order {} can't be restated at this ratio; it must be cancelled first", order.id)),
+            }
+        })
+        .collect()
 }
 
-fn update_market_data(property_id: u64, price: u64, volume: u64) -> Result<(), String> {
-    let current_time = time();
-    
-    let market_data = MARKET_DATA_STORAGE.with(|storage| {
+/// Stores orders from `rescaled_orders`.
+pub fn restate_orders(property_id: u64, orders: Vec<TokenOrder>) {
+    for order in orders {
+        insert_order(order);
+    }
+    refresh_liquidity_score(property_id);
+}
+
+#[query]
+pub fn get_active_orders(property_id: u64, page: PageRequest) -> Page<TokenOrder> {
+    let orders = orders_by_property_and_status(property_id, &OrderStatus::Active)
+        .into_iter()
+        .filter(|order| order.expires_at > time())
+        .collect();
+    paginate(orders, &page)
+}
+
+/// Active orders quoted in `quote_asset` (the property's settlement asset by
+/// default), aggregated into at most `depth` price levels per side (20 when zero,
+/// capped at 100). Each quote asset has its own book.
+#[query]
+pub fn get_order_book(property_id: u64, quote_asset: Option<Principal>, depth: u32) -> Result<OrderBook, String> {
+    let property = PROPERTY_STORAGE.with(|storage| {
         storage.borrow().get(&property_id)
-    }).unwrap_or_else(|| MarketData {
-        property_id,
-        current_price: price,
-        price_change_24h: 0.0,
-        trading_volume_24h: 0,
-        market_cap: 0,
-        liquidity_score: 0.0,
-        last_updated: current_time,
-    });
-
-    let mut updated_data = market_data;
-    updated_data.current_price = price;
-    updated_data.trading_volume_24h += volume;
-    updated_data.last_updated = current_time;
-
-    MARKET_DATA_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(property_id, updated_data)
-    });
+            .ok_or_else(|| "Property not found".to_string())
+    })?;
+    let depth = match depth {
+        0 => DEFAULT_BOOK_DEPTH,
+        depth => depth.min(MAX_BOOK_DEPTH),
+    } as usize;
+    let mut book = order_book(property_id, quote_asset.unwrap_or(property.settlement_asset));
+    book.bids.truncate(depth);
+    book.asks.truncate(depth);
+    Ok(book)
+}
 
-    Ok(())
+#[query]
+pub fn get_user_orders(user: Principal, page: PageRequest) -> Page<TokenOrder> {
+    paginate(orders_by_user(user), &page)
 }
 
-fn update_portfolio_after_trade(from: Principal, to: Principal, property_id: u64, amount: u64) -> Result<(), String> {
-    // Update seller's portfolio
-    PORTFOLIO_STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
-        if let Some(mut portfolio) = storage.get(&from) {
-            if let Some(prop) = portfolio.properties.iter_mut().find(|p| p.property_id == property_id) {
-                prop.token_amount = prop.token_amount.saturating_sub(amount);
-            }
-            storage.insert(from, portfolio);
+fn order_book(property_id: u64, quote_asset: Principal) -> OrderBook {
+    let now = time();
+    let mut bids: BTreeMap<Amount, PriceLevel> = BTreeMap::new();
+    let mut asks: BTreeMap<Amount, PriceLevel> = BTreeMap::new();
+    for order in orders_by_property_and_status(property_id, &OrderStatus::Active) {
+        if order.expires_at <= now || order.quote_asset != quote_asset {
+            continue;
         }
-    });
-
-    // Update buyer's portfolio
-    PORTFOLIO_STORAGE.with(|storage| {
-        let mut storage = storage.borrow_mut();
-        let mut portfolio = storage.get(&to).unwrap_or_else(|| Portfolio {
-            owner: to,
-            total_value: 0,
-            total_tokens: 0,
-            properties: Vec::new(),
-            total_dividends_received: 0,
-            performance_metrics: PerformanceMetrics {
-                total_return: 0.0,
-                annual_yield: 0.0,
-                roi_percentage: 0.0,
-                diversification_score: 0.0,
-            },
+        let side = match order.order_type {
+            OrderType::Buy => &mut bids,
+            OrderType::Sell => &mut asks,
+        };
+        let level = side.entry(order.price_per_token).or_insert(PriceLevel {
+            price: order.price_per_token,
+            token_amount: 0,
+            order_count: 0,
         });
+        level.token_amount += order.token_amount;
+        level.order_count += 1;
+    }
 
-        if let Some(prop) = portfolio.properties.iter_mut().find(|p| p.property_id == property_id) {
-            prop.token_amount += amount;
-        } else {
-            portfolio.properties.push(PortfolioProperty {
-                property_id,
-                token_amount: amount,
-                initial_investment: 0,
-                current_value: 0,
-                dividends_received: 0,
-                purchase_date: time(),
-            });
+    let bids: Vec<PriceLevel> = bids.into_values().rev().collect();
+    let asks: Vec<PriceLevel> = asks.into_values().collect();
+    let best_bid = bids.first().map(|level| level.price);
+    let best_ask = asks.first().map(|level| level.price);
+    let (spread, mid_price) = match (best_bid, best_ask) {
+        (Some(bid), Some(ask)) => {
+            let (bid, ask) = (bid.minor_units(), ask.minor_units());
+            let mid = bid / 2 + ask / 2 + (bid % 2 + ask % 2) / 2;
+            (Some(Amount::from_minor(ask.saturating_sub(bid))), Some(Amount::from_minor(mid)))
         }
+        _ => (None, None),
+    };
+    let circulating = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .map(|property| property.total_tokens - property.available_tokens)
+        .unwrap_or(0);
+
+    OrderBook {
+        property_id,
+        quote_asset,
+        liquidity_score: liquidity_score(&bids, &asks, spread, mid_price, circulating),
+        bids,
+        asks,
+        best_bid,
+        best_ask,
+        spread,
+        mid_price,
+        timestamp: now,
+    }
+}
+
+/// 0-100%. Depth is the tokens quoted within 5% of the mid on both sides, scoring
+/// full marks once it reaches 10% of the circulating supply; the result is scaled
+/// down linearly with the relative spread, hitting zero at a 10% spread. One-sided
+/// books score zero.
+fn liquidity_score(bids: &[PriceLevel], asks: &[PriceLevel], spread: Option<Amount>, mid_price: Option<Amount>, circulating: u64) -> Rate {
+    let (Some(spread), Some(mid)) = (spread, mid_price) else {
+        return Rate::ZERO;
+    };
+    if mid.is_zero() || circulating == 0 {
+        return Rate::ZERO;
+    }
+
+    let full = Rate::ONE_HUNDRED_PERCENT.bps() as i128;
+    let band = LIQUIDITY_BAND.bps() as i128;
+    let mid = mid.minor_units() as i128;
+    let near_depth: u64 = bids.iter()
+        .take_while(|level| level.price.minor_units() as i128 * full >= mid * (full - band))
+        .chain(asks.iter().take_while(|level| level.price.minor_units() as i128 * full <= mid * (full + band)))
+        .map(|level| level.token_amount)
+        .sum();
+
+    let depth = Rate::ratio(
+        near_depth as i128 * full,
+        circulating as i128 * LIQUIDITY_DEPTH_TARGET.bps() as i128,
+        Rounding::Down,
+    )
+    .min(Rate::ONE_HUNDRED_PERCENT);
+    let relative_spread = Rate::ratio(spread.minor_units() as i128, mid, Rounding::Up);
+    let tightness = Rate::ONE_HUNDRED_PERCENT.bps() as i128
+        - relative_spread.bps() as i128 * full / MAX_LIQUID_SPREAD.bps() as i128;
+    Rate::ratio(depth.bps() as i128 * tightness.max(0), full * full, Rounding::Down)
+}
+
+/// Scored on the book in the property's settlement asset.
+fn refresh_liquidity_score(property_id: u64) {
+    if let Some(property) = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id)) {
+        set_liquidity_score(property_id, order_book(property_id, property.settlement_asset).liquidity_score);
+    }
+}
+
+impl Paginated for TokenOrder {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn sort_key(&self, field: &SortField) -> u64 {
+        match field {
+            SortField::CreatedAt => self.created_at,
+            SortField::Price => self.price_per_token.minor_units(),
+            SortField::Yield => PROPERTY_STORAGE.with(|storage| {
+                storage.borrow().get(&self.property_id)
+                    .map(|property| yield_sort_key(property.rental_yield))
+                    .unwrap_or(0)
+            }),
+            SortField::Value => self.total_price.minor_units(),
+        }
+    }
+}
+
+/// Releases the order's escrow back to the maker and takes it off the book.
+fn close_order(mut order: TokenOrder, status: OrderStatus) -> Result<TokenOrder, String> {
+    match order.order_type {
+        OrderType::Buy => unlock_cash(order.seller, order.quote_asset, order.total_price.checked_add(order.maker_fee)?, order.id)?,
+        OrderType::Sell => unlock_tokens(order.seller, order.property_id, order.token_amount),
+    }
+
+    order.status = status;
+    insert_order(order.clone());
+    decrement_metric(Metric::ActiveOrders, 1);
+    refresh_liquidity_score(order.property_id);
+
+    Ok(order)
+}
+
+fn transfer_tokens(from: Principal, to: Principal, order: &TokenOrder, seller_fee: Amount, buyer_fee: Amount) -> Result<(), String> {
+    // Close the seller's lots first; the escrow or the caller's balance check
+    // guarantees they hold the tokens
+    let buyer_cost = order.total_price.checked_add(buyer_fee)?;
+    record_disposal(from, order.property_id, order.id, order.token_amount, order.total_price.saturating_sub(seller_fee))?;
+
+    // Create investment record for the buyer at the traded price
+    let investment_id = get_next_id();
+    let investment = Investment {
+        id: investment_id,
+        investor: to,
+        property_id: order.property_id,
+        token_amount: order.token_amount,
+        investment_amount: order.total_price,
+        fee: buyer_fee,
+        timestamp: order.filled_at.unwrap_or_else(time),
+        status: InvestmentStatus::Confirmed,
+    };
 
-        storage.insert(to, portfolio);
-    });
+    insert_investment(investment);
+    record_acquisition(to, order.property_id, investment_id, order.token_amount, buyer_cost);
 
     Ok(())
 }
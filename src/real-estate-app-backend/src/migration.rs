@@ -0,0 +1,478 @@
+use candid::{CandidType, Decode, Deserialize, Principal};
+use std::cell::OnceCell;
+use crate::exchange::add_settlement_asset;
+use crate::investment::{Investment, InvestmentStatus};
+use crate::market_data::refresh_nav;
+use crate::money::platform_currency;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::get_current_time;
+
+// Memory ids of the stores that existed before records were versioned.
+const PROPERTY_MEMORY: u8 = 1;
+const INVESTMENT_MEMORY: u8 = 2;
+const USER_MEMORY: u8 = 3;
+const ORDER_MEMORY: u8 = 4;
+const DIVIDEND_MEMORY: u8 = 5;
+const PORTFOLIO_MEMORY: u8 = 6;
+const PROPOSAL_MEMORY: u8 = 7;
+const MARKET_DATA_MEMORY: u8 = 8;
+
+const PLATFORM_SNAPSHOT_MEMORY: u8 = 38;
+
+/// Legacy proposals passed on a simple majority of the votes cast.
+const LEGACY_APPROVAL_BPS: u32 = 5_000;
+
+// Record layouts from before amounts were `Amount`, rates were basis points and
+// records named their asset. Only ever decoded.
+
+#[derive(CandidType, Deserialize)]
+struct LegacyProperty {
+    id: u64,
+    title: String,
+    description: String,
+    location: String,
+    total_value: u64,
+    total_tokens: u64,
+    available_tokens: u64,
+    price_per_token: u64,
+    owner: Principal,
+    created_at: u64,
+    updated_at: u64,
+    property_type: PropertyType,
+    status: PropertyStatus,
+    images: Vec<String>,
+    documents: Vec<String>,
+    rental_yield: f64,
+    appreciation_rate: f64,
+    property_highlights: Vec<String>,
+    legal_structure: String,
+    valuation_date: u64,
+    next_dividend_date: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyInvestment {
+    id: u64,
+    investor: Principal,
+    property_id: u64,
+    token_amount: u64,
+    investment_amount: u64,
+    timestamp: u64,
+    status: InvestmentStatus,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyUserProfile {
+    principal: Principal,
+    name: String,
+    email: String,
+    kyc_status: KycStatus,
+    kyc_verification: Option<KycVerification>,
+    created_at: u64,
+    total_investments: u64,
+    investment_limit: u64,
+    accredited_investor: bool,
+    jurisdiction: String,
+    risk_profile: RiskProfile,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyTokenOrder {
+    id: u64,
+    property_id: u64,
+    seller: Principal,
+    buyer: Option<Principal>,
+    token_amount: u64,
+    price_per_token: u64,
+    total_price: u64,
+    order_type: OrderType,
+    status: OrderStatus,
+    created_at: u64,
+    expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyDividendDistribution {
+    id: u64,
+    property_id: u64,
+    total_amount: u64,
+    per_token_amount: u64,
+    distribution_date: u64,
+    payment_status: PaymentStatus,
+    recipients: Vec<DividendRecipient>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyGovernanceProposal {
+    id: u64,
+    property_id: u64,
+    proposer: Principal,
+    title: String,
+    description: String,
+    proposal_type: ProposalType,
+    voting_power_required: u64,
+    votes_for: u64,
+    votes_against: u64,
+    status: ProposalStatus,
+    created_at: u64,
+    voting_ends_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+struct LegacyMarketData {
+    property_id: u64,
+    current_price: u64,
+    price_change_24h: f64,
+    trading_volume_24h: u64,
+    market_cap: u64,
+    liquidity_score: f64,
+    last_updated: u64,
+}
+
+/// Upgrades every record still in its pre-versioning layout. Those records don't
+/// name an asset, so the first one found registers `args.legacy_settlement_asset`
+/// and is denominated in it; the upgrade traps if that argument is missing.
+///
+/// Legacy orders escrowed nothing, so active ones are cancelled rather than left
+/// fillable; legacy proposals still open are closed, since their votes weren't
+/// recorded per voter; and legacy portfolios are dropped for the holdings backfill
+/// to recompute.
+pub fn migrate_legacy_records(args: Option<UpgradeArgs>) {
+    let legacy = args.unwrap_or_default().legacy_settlement_asset;
+    let registered = OnceCell::new();
+    let settlement_asset = || *registered.get_or_init(|| register_legacy_asset(legacy.clone()));
+    let now = get_current_time();
+    let mut revalued = Vec::new();
+
+    migrate_store::<u64, Property>(PROPERTY_MEMORY, |bytes| {
+        let property = upgrade_property(decode_legacy::<LegacyProperty>(bytes), settlement_asset());
+        revalued.push(property.id);
+        Some(property)
+    });
+    migrate_store::<u64, Investment>(INVESTMENT_MEMORY, |bytes| {
+        Some(upgrade_investment(decode_legacy(bytes)))
+    });
+    migrate_store::<Principal, UserProfile>(USER_MEMORY, |bytes| {
+        Some(upgrade_user_profile(decode_legacy(bytes)))
+    });
+    migrate_store::<u64, TokenOrder>(ORDER_MEMORY, |bytes| {
+        Some(upgrade_order(decode_legacy(bytes), settlement_asset()))
+    });
+    migrate_store::<u64, DividendDistribution>(DIVIDEND_MEMORY, |bytes| {
+        Some(upgrade_distribution(decode_legacy(bytes)))
+    });
+    migrate_store::<Principal, Portfolio>(PORTFOLIO_MEMORY, |_| None);
+    migrate_store::<u64, GovernanceProposal>(PROPOSAL_MEMORY, |bytes| {
+        Some(upgrade_proposal(decode_legacy(bytes), now))
+    });
+    migrate_store::<u64, MarketData>(MARKET_DATA_MEMORY, |bytes| {
+        let market_data = upgrade_market_data(decode_legacy(bytes));
+        revalued.push(market_data.property_id);
+        Some(market_data)
+    });
+
+    // Legacy market data had no NAV, and legacy properties may have market data
+    // written since.
+    revalued.sort_unstable();
+    revalued.dedup();
+    for property_id in revalued {
+        refresh_nav(property_id);
+    }
+}
+
+/// Records written before converted totals carried their currency: platform
+/// snapshots held bare amounts, which were always in the platform currency.
+#[derive(CandidType, Deserialize)]
+struct UnlabelledPlatformSnapshot {
+    day: u64,
+    total_properties: u64,
+    total_investments: u64,
+    total_users: u64,
+    total_value_locked: Amount,
+    total_trading_volume: Amount,
+    trading_volume: Amount,
+    active_orders: u64,
+    total_dividends_paid: Amount,
+    platform_fee_collected: Amount,
+    recorded_at: u64,
+}
+
+/// Labels the stored platform snapshots with the platform currency. Portfolios
+/// from that layout are dropped and recomputed like legacy ones.
+pub fn migrate_unlabelled_totals() {
+    let currency = platform_currency();
+    migrate_store::<u64, PlatformSnapshot>(PLATFORM_SNAPSHOT_MEMORY, |bytes| {
+        Some(label_snapshot(decode_legacy(bytes), &currency))
+    });
+    migrate_store::<Principal, Portfolio>(PORTFOLIO_MEMORY, |_| None);
+}
+
+fn label_snapshot(snapshot: UnlabelledPlatformSnapshot, currency: &Currency) -> PlatformSnapshot {
+    let money = |amount| Money::new(amount, currency.clone());
+    PlatformSnapshot {
+        day: snapshot.day,
+        total_properties: snapshot.total_properties,
+        total_investments: snapshot.total_investments,
+        total_users: snapshot.total_users,
+        total_value_locked: money(snapshot.total_value_locked),
+        total_trading_volume: money(snapshot.total_trading_volume),
+        trading_volume: money(snapshot.trading_volume),
+        active_orders: snapshot.active_orders,
+        total_dividends_paid: money(snapshot.total_dividends_paid),
+        platform_fee_collected: money(snapshot.platform_fee_collected),
+        recorded_at: snapshot.recorded_at,
+    }
+}
+
+fn register_legacy_asset(legacy: Option<LegacySettlementAsset>) -> Principal {
+    let Some(legacy) = legacy else {
+        ic_cdk::trap("Stored records predate settlement assets; upgrade with `legacy_settlement_asset` set");
+    };
+    let ledger = legacy.asset.ledger;
+    if SETTLEMENT_ASSET_STORAGE.with(|storage| !storage.borrow().contains_key(&ledger)) {
+        add_settlement_asset(legacy.asset, legacy.rate, ic_cdk::caller())
+            .unwrap_or_else(|error| ic_cdk::trap(&error));
+    }
+    ledger
+}
+
+fn decode_legacy<T: CandidType + for<'de> Deserialize<'de>>(bytes: &[u8]) -> T {
+    Decode!(bytes, T).unwrap_or_else(|error| ic_cdk::trap(&format!("Unreadable stored record: {}", error)))
+}
+
+/// Legacy rates were percentages (5.25 for 5.25%).
+fn percent_to_rate(percent: f64) -> Rate {
+    if !percent.is_finite() {
+        return Rate::ZERO;
+    }
+    Rate::from_bps((percent * 100.0).round() as i64)
+}
+
+fn upgrade_property(legacy: LegacyProperty, settlement_asset: Principal) -> Property {
+    Property {
+        id: legacy.id,
+        title: legacy.title,
+        description: legacy.description,
+        location: legacy.location,
+        total_value: Amount::from_minor(legacy.total_value),
+        total_tokens: legacy.total_tokens,
+        available_tokens: legacy.available_tokens,
+        price_per_token: Amount::from_minor(legacy.price_per_token),
+        owner: legacy.owner,
+        manager: legacy.owner,
+        created_at: legacy.created_at,
+        updated_at: legacy.updated_at,
+        property_type: legacy.property_type,
+        status: legacy.status,
+        images: legacy.images,
+        documents: legacy.documents,
+        rental_yield: percent_to_rate(legacy.rental_yield),
+        appreciation_rate: percent_to_rate(legacy.appreciation_rate),
+        property_highlights: legacy.property_highlights,
+        legal_structure: legacy.legal_structure,
+        valuation_date: legacy.valuation_date,
+        next_dividend_date: legacy.next_dividend_date,
+        settlement_asset,
+    }
+}
+
+fn upgrade_investment(legacy: LegacyInvestment) -> Investment {
+    Investment {
+        id: legacy.id,
+        investor: legacy.investor,
+        property_id: legacy.property_id,
+        token_amount: legacy.token_amount,
+        investment_amount: Amount::from_minor(legacy.investment_amount),
+        fee: Amount::ZERO,
+        timestamp: legacy.timestamp,
+        status: legacy.status,
+    }
+}
+
+fn upgrade_user_profile(legacy: LegacyUserProfile) -> UserProfile {
+    UserProfile {
+        principal: legacy.principal,
+        name: legacy.name,
+        email: legacy.email,
+        kyc_status: legacy.kyc_status,
+        kyc_verification: legacy.kyc_verification,
+        created_at: legacy.created_at,
+        total_investments: Amount::from_minor(legacy.total_investments),
+        investment_limit: Amount::from_minor(legacy.investment_limit),
+        accredited_investor: legacy.accredited_investor,
+        jurisdiction: legacy.jurisdiction,
+        risk_profile: legacy.risk_profile,
+        display_currency: platform_currency().code,
+    }
+}
+
+fn upgrade_order(legacy: LegacyTokenOrder, quote_asset: Principal) -> TokenOrder {
+    let status = match legacy.status {
+        OrderStatus::Active => OrderStatus::Cancelled,
+        status => status,
+    };
+    TokenOrder {
+        id: legacy.id,
+        property_id: legacy.property_id,
+        seller: legacy.seller,
+        buyer: legacy.buyer,
+        token_amount: legacy.token_amount,
+        price_per_token: Amount::from_minor(legacy.price_per_token),
+        total_price: Amount::from_minor(legacy.total_price),
+        order_type: legacy.order_type,
+        status,
+        created_at: legacy.created_at,
+        expires_at: legacy.expires_at,
+        maker_fee: Amount::ZERO,
+        taker_fee: Amount::ZERO,
+        quote_asset,
+        filled_at: None,
+    }
+}
+
+fn upgrade_distribution(legacy: LegacyDividendDistribution) -> DividendDistribution {
+    DividendDistribution {
+        id: legacy.id,
+        property_id: legacy.property_id,
+        kind: DistributionKind::Dividend,
+        proposal_id: None,
+        total_amount: Amount::from_minor(legacy.total_amount),
+        management_fee: Amount::ZERO,
+        per_token_amount: Amount::from_minor(legacy.per_token_amount),
+        distribution_date: legacy.distribution_date,
+        payment_status: legacy.payment_status,
+        recipients: legacy.recipients,
+    }
+}
+
+/// Legacy proposals carried no action, so they upgrade as `Other` and can never
+/// execute anything.
+fn upgrade_proposal(legacy: LegacyGovernanceProposal, now: u64) -> GovernanceProposal {
+    let still_open = matches!(legacy.status, ProposalStatus::Active);
+    GovernanceProposal {
+        id: legacy.id,
+        property_id: legacy.property_id,
+        proposer: legacy.proposer,
+        title: legacy.title,
+        description: legacy.description,
+        proposal_type: legacy.proposal_type,
+        action: ProposalAction::Other,
+        voting_power_required: legacy.voting_power_required,
+        approval_threshold_bps: LEGACY_APPROVAL_BPS,
+        eligible_supply: legacy.voting_power_required,
+        votes_for: legacy.votes_for,
+        votes_against: legacy.votes_against,
+        status: if still_open { ProposalStatus::Rejected } else { legacy.status },
+        outcome: None,
+        created_at: legacy.created_at,
+        snapshot_at: legacy.created_at,
+        voting_ends_at: legacy.voting_ends_at,
+        finalized_at: still_open.then_some(now),
+        executable_at: None,
+        executed_at: None,
+        deposit: 0,
+        deposit_status: DepositStatus::Refunded,
+        discussion_links: Vec::new(),
+        document_hashes: Vec::new(),
+        amendments: Vec::new(),
+        voting_starts_at: legacy.created_at,
+    }
+}
+
+fn upgrade_market_data(legacy: LegacyMarketData) -> MarketData {
+    MarketData {
+        property_id: legacy.property_id,
+        current_price: Amount::from_minor(legacy.current_price),
+        price_change_24h: percent_to_rate(legacy.price_change_24h),
+        trading_volume_24h: legacy.trading_volume_24h,
+        market_cap: Amount::from_minor(legacy.market_cap),
+        liquidity_score: percent_to_rate(legacy.liquidity_score),
+        nav_per_token: Amount::ZERO,
+        last_updated: legacy.last_updated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Encode;
+
+    fn legacy_order(status: OrderStatus) -> LegacyTokenOrder {
+        LegacyTokenOrder {
+            id: 7,
+            property_id: 1,
+            seller: Principal::from_slice(&[1]),
+            buyer: None,
+            token_amount: 10,
+            price_per_token: 2_500,
+            total_price: 25_000,
+            order_type: OrderType::Sell,
+            status,
+            created_at: 100,
+            expires_at: 200,
+        }
+    }
+
+    #[test]
+    fn legacy_layouts_do_not_decode_as_current() {
+        let bytes = Encode!(&legacy_order(OrderStatus::Filled)).unwrap();
+        assert!(Decode!(&bytes, TokenOrder).is_err());
+        assert_eq!(decode_legacy::<LegacyTokenOrder>(&bytes).total_price, 25_000);
+    }
+
+    #[test]
+    fn legacy_amounts_keep_their_minor_units() {
+        let bytes = Encode!(&LegacyInvestment {
+            id: 3,
+            investor: Principal::from_slice(&[2]),
+            property_id: 1,
+            token_amount: 4,
+            investment_amount: 10_000,
+            timestamp: 5,
+            status: InvestmentStatus::Confirmed,
+        })
+        .unwrap();
+        let investment = upgrade_investment(decode_legacy(&bytes));
+        assert_eq!(investment.investment_amount, Amount::from_minor(10_000));
+        assert_eq!(investment.fee, Amount::ZERO);
+    }
+
+    #[test]
+    fn unlabelled_snapshots_take_the_platform_currency() {
+        let bytes = Encode!(&UnlabelledPlatformSnapshot {
+            day: 20_000,
+            total_properties: 2,
+            total_investments: 3,
+            total_users: 4,
+            total_value_locked: Amount::from_minor(1_000),
+            total_trading_volume: Amount::from_minor(500),
+            trading_volume: Amount::from_minor(50),
+            active_orders: 1,
+            total_dividends_paid: Amount::from_minor(20),
+            platform_fee_collected: Amount::from_minor(5),
+            recorded_at: 7,
+        })
+        .unwrap();
+        assert!(Decode!(&bytes, PlatformSnapshot).is_err());
+        let snapshot = label_snapshot(decode_legacy(&bytes), &platform_currency());
+        assert_eq!(snapshot.total_value_locked, Money::new(Amount::from_minor(1_000), platform_currency()));
+        assert_eq!(snapshot.trading_volume.amount, Amount::from_minor(50));
+    }
+
+    #[test]
+    fn legacy_percentages_become_basis_points() {
+        assert_eq!(percent_to_rate(5.25), Rate::from_bps(525));
+        assert_eq!(percent_to_rate(-1.5), Rate::from_bps(-150));
+        assert_eq!(percent_to_rate(f64::NAN), Rate::ZERO);
+    }
+
+    #[test]
+    fn active_legacy_orders_are_cancelled() {
+        let asset = Principal::from_slice(&[9]);
+        let order = upgrade_order(legacy_order(OrderStatus::Active), asset);
+        assert!(matches!(order.status, OrderStatus::Cancelled));
+        assert_eq!(order.quote_asset, asset);
+        assert!(matches!(upgrade_order(legacy_order(OrderStatus::Filled), asset).status, OrderStatus::Filled));
+    }
+}
@@ -0,0 +1,351 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::iter::Sum;
+use std::ops::AddAssign;
+
+/// How a division that doesn't come out even is resolved.
+#[derive(CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Rounding {
+    Down,
+    Up,
+    /// Nearest, with exact halves rounded away from zero.
+    HalfUp,
+}
+
+/// A unit amounts are denominated in: a settlement asset or a display currency.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Currency {
+    pub code: String,
+    pub decimals: u8,
+}
+
+pub const PLATFORM_CURRENCY_CODE: &str = "USD";
+pub const PLATFORM_CURRENCY_DECIMALS: u8 = 2;
+
+pub fn platform_currency() -> Currency {
+    Currency {
+        code: PLATFORM_CURRENCY_CODE.to_string(),
+        decimals: PLATFORM_CURRENCY_DECIMALS,
+    }
+}
+
+/// Money in minor units of the asset or currency its context names: a property's
+/// settlement asset, an order's quote asset, or the platform currency (cents for
+/// USD) for platform-wide totals. Arithmetic is checked; overflow and underflow
+/// are errors, never silent wrap-around. Signed money such as profit and loss is
+/// carried as `i64` minor units.
+///
+/// `Amount` is the bare quantity used in storage and arithmetic. Values that are
+/// reported in a converted currency (a display or platform total) are `Money`,
+/// which carries its currency and decimals with it.
+#[derive(CandidType, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn from_minor(minor_units: u64) -> Amount {
+        Amount(minor_units)
+    }
+
+    pub const fn minor_units(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Result<Amount, String> {
+        self.0.checked_add(other.0).map(Amount).ok_or_else(overflow)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Result<Amount, String> {
+        self.0.checked_sub(other.0).map(Amount).ok_or_else(|| "Amount underflow".to_string())
+    }
+
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+
+    /// The price of `quantity` units at `self` each.
+    pub fn times(self, quantity: u64) -> Result<Amount, String> {
+        self.0.checked_mul(quantity).map(Amount).ok_or_else(overflow)
+    }
+
+    /// `self * part / whole`, e.g. one holder's share of a payout.
+    pub fn share(self, part: u64, whole: u64, rounding: Rounding) -> Result<Amount, String> {
+        mul_div_rounded(self.0 as u128, part as u128, whole as u128, rounding)
+            .and_then(|value| u64::try_from(value).ok())
+            .map(Amount)
+            .ok_or_else(overflow)
+    }
+
+    /// The amount per unit when `self` is split into `quantity` units.
+    pub fn per_unit(self, quantity: u64, rounding: Rounding) -> Result<Amount, String> {
+        self.share(1, quantity, rounding)
+    }
+
+    /// `self` scaled by a non-negative rate, e.g. a fee.
+    pub fn apply_rate(self, rate: Rate, rounding: Rounding) -> Result<Amount, String> {
+        let bps = u64::try_from(rate.bps()).map_err(|_| "Rate must not be negative".to_string())?;
+        self.share(bps, BPS_PER_UNIT as u64, rounding)
+    }
+
+    /// `self - other` as signed minor units; an error when it doesn't fit in `i64`.
+    pub fn signed_difference(self, other: Amount) -> Result<i64, String> {
+        i64::try_from(self.0 as i128 - other.0 as i128).map_err(|_| "Amount difference out of range".to_string())
+    }
+
+    /// `self - other` as signed minor units, clamped to the `i64` range. For
+    /// reports that can't fail, such as portfolio and tax summaries; anything that
+    /// is stored uses `signed_difference`.
+    pub fn saturating_signed_difference(self, other: Amount) -> i64 {
+        (self.0 as i128 - other.0 as i128).clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+}
+
+/// Summing amounts traps on overflow rather than wrapping; a trap rolls back the
+/// whole message, so no partial state is left behind.
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, |total, amount| total.checked_add(amount).expect("Amount overflow"))
+    }
+}
+
+impl<'a> Sum<&'a Amount> for Amount {
+    fn sum<I: Iterator<Item = &'a Amount>>(iter: I) -> Amount {
+        iter.copied().sum()
+    }
+}
+
+/// Running totals trap on overflow for the same reason `Sum` does.
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = self.checked_add(other).expect("Amount overflow");
+    }
+}
+
+/// An amount with the currency it's denominated in. Arithmetic across currencies
+/// is an error; convert through `exchange` first.
+#[derive(CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Money {
+    pub amount: Amount,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(amount: Amount, currency: Currency) -> Money {
+        Money { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Money {
+        Money::new(Amount::ZERO, currency)
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, String> {
+        self.same_currency(other)?;
+        Ok(Money::new(self.amount.checked_add(other.amount)?, self.currency.clone()))
+    }
+
+    pub fn checked_sub(&self, other: &Money) -> Result<Money, String> {
+        self.same_currency(other)?;
+        Ok(Money::new(self.amount.checked_sub(other.amount)?, self.currency.clone()))
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), String> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(format!("Cannot combine {} with {}", self.currency.code, other.currency.code))
+        }
+    }
+}
+
+const BPS_PER_UNIT: i64 = 10_000;
+
+/// A rate or percentage in basis points: 1% is 100, 100% is 10 000. Negative rates
+/// (losses, falling prices) are allowed.
+#[derive(CandidType, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Rate(i64);
+
+impl Rate {
+    pub const ZERO: Rate = Rate(0);
+    pub const ONE_HUNDRED_PERCENT: Rate = Rate(BPS_PER_UNIT);
+
+    pub const fn from_bps(bps: i64) -> Rate {
+        Rate(bps)
+    }
+
+    pub const fn bps(self) -> i64 {
+        self.0
+    }
+
+    /// `part / whole` as a rate; zero when `whole` is zero.
+    pub fn ratio(part: i128, whole: i128, rounding: Rounding) -> Rate {
+        if whole == 0 {
+            return Rate::ZERO;
+        }
+        let negative = (part < 0) != (whole < 0);
+        let magnitude = mul_div_rounded(part.unsigned_abs(), BPS_PER_UNIT as u128, whole.unsigned_abs(), rounding)
+            .unwrap_or(i64::MAX as u128)
+            .min(i64::MAX as u128) as i64;
+        Rate(if negative { -magnitude } else { magnitude })
+    }
+
+    /// Converts a growth factor (1.05 for +5%). Roots and powers have no exact
+    /// fixed-point form, so callers compute them in `f64` and round once here.
+    /// Every replica runs the same wasm, so the result is identical on-chain, but
+    /// it is not a fixed-point figure: an off-chain audit recomputing it with a
+    /// different float library can land on the neighbouring basis point when the
+    /// value sits on a rounding boundary.
+    pub fn from_growth_factor(factor: f64) -> Rate {
+        if !factor.is_finite() {
+            return Rate::ZERO;
+        }
+        Rate(((factor - 1.0) * BPS_PER_UNIT as f64).round() as i64)
+    }
+
+    pub fn growth_factor(self) -> f64 {
+        1.0 + self.0 as f64 / BPS_PER_UNIT as f64
+    }
+
+    pub fn checked_add(self, other: Rate) -> Result<Rate, String> {
+        self.0.checked_add(other.0).map(Rate).ok_or_else(|| "Rate overflow".to_string())
+    }
+
+    pub fn min(self, other: Rate) -> Rate {
+        Rate(self.0.min(other.0))
+    }
+
+    pub fn max(self, other: Rate) -> Rate {
+        Rate(self.0.max(other.0))
+    }
+}
+
+/// `value * numerator / denominator` in u128 with the requested rounding; `None`
+/// when the denominator is zero or the result overflows.
+pub fn mul_div_rounded(value: u128, numerator: u128, denominator: u128, rounding: Rounding) -> Option<u128> {
+    if denominator == 0 {
+        return None;
+    }
+    let product = value.checked_mul(numerator)?;
+    let quotient = product / denominator;
+    let remainder = product % denominator;
+    let round_up = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => remainder > 0,
+        Rounding::HalfUp => remainder >= denominator - remainder,
+    } && remainder > 0;
+    Some(quotient + round_up as u128)
+}
+
+fn overflow() -> String {
+    "Amount overflow".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_rounds_in_the_requested_direction() {
+        assert_eq!(mul_div_rounded(10, 1, 3, Rounding::Down), Some(3));
+        assert_eq!(mul_div_rounded(10, 1, 3, Rounding::Up), Some(4));
+        assert_eq!(mul_div_rounded(10, 1, 3, Rounding::HalfUp), Some(3));
+        assert_eq!(mul_div_rounded(10, 1, 4, Rounding::HalfUp), Some(3));
+        assert_eq!(mul_div_rounded(9, 1, 4, Rounding::HalfUp), Some(2));
+        assert_eq!(mul_div_rounded(12, 1, 4, Rounding::Up), Some(3));
+    }
+
+    #[test]
+    fn mul_div_rejects_zero_denominators_and_overflow() {
+        assert_eq!(mul_div_rounded(1, 1, 0, Rounding::Down), None);
+        assert_eq!(mul_div_rounded(u128::MAX, 2, 2, Rounding::Down), None);
+        assert_eq!(mul_div_rounded(0, u128::MAX, 1, Rounding::Up), Some(0));
+    }
+
+    #[test]
+    fn share_splits_by_part_and_rounds() {
+        let total = Amount::from_minor(100);
+        assert_eq!(total.share(1, 3, Rounding::Down), Ok(Amount::from_minor(33)));
+        assert_eq!(total.share(1, 3, Rounding::Up), Ok(Amount::from_minor(34)));
+        assert_eq!(total.share(2, 3, Rounding::HalfUp), Ok(Amount::from_minor(67)));
+        assert_eq!(total.share(0, 3, Rounding::Up), Ok(Amount::ZERO));
+        assert_eq!(total.share(3, 3, Rounding::Down), Ok(total));
+    }
+
+    #[test]
+    fn share_works_past_u64_intermediates_but_not_past_u64_results() {
+        let max = Amount::from_minor(u64::MAX);
+        assert_eq!(max.share(u64::MAX, u64::MAX, Rounding::Down), Ok(max));
+        assert!(max.share(2, 1, Rounding::Down).is_err());
+        assert!(max.share(1, 0, Rounding::Down).is_err());
+    }
+
+    #[test]
+    fn per_unit_rounds_and_rejects_zero_quantities() {
+        let total = Amount::from_minor(1_000);
+        assert_eq!(total.per_unit(3, Rounding::Down), Ok(Amount::from_minor(333)));
+        assert_eq!(total.per_unit(3, Rounding::Up), Ok(Amount::from_minor(334)));
+        assert!(total.per_unit(0, Rounding::Down).is_err());
+    }
+
+    #[test]
+    fn signed_differences_error_or_clamp_outside_i64() {
+        let small = Amount::from_minor(5);
+        let large = Amount::from_minor(u64::MAX);
+        assert_eq!(small.signed_difference(Amount::from_minor(8)), Ok(-3));
+        assert!(large.signed_difference(Amount::ZERO).is_err());
+        assert!(Amount::ZERO.signed_difference(large).is_err());
+        assert_eq!(large.saturating_signed_difference(Amount::ZERO), i64::MAX);
+        assert_eq!(Amount::ZERO.saturating_signed_difference(large), i64::MIN);
+        assert_eq!(large.saturating_signed_difference(large), 0);
+    }
+
+    #[test]
+    fn money_only_combines_within_a_currency() {
+        let usd = |minor| Money::new(Amount::from_minor(minor), platform_currency());
+        let eur = Money::new(Amount::from_minor(5), Currency { code: "EUR".to_string(), decimals: 2 });
+        assert_eq!(usd(5).checked_add(&usd(7)), Ok(usd(12)));
+        assert_eq!(usd(7).checked_sub(&usd(5)), Ok(usd(2)));
+        assert!(usd(5).checked_add(&eur).is_err());
+        assert!(usd(5).checked_sub(&usd(7)).is_err());
+        assert!(usd(u64::MAX).checked_add(&usd(1)).is_err());
+    }
+
+    #[test]
+    fn ratio_keeps_the_sign_and_rounds_the_magnitude() {
+        assert_eq!(Rate::ratio(1, 3, Rounding::Down), Rate::from_bps(3_333));
+        assert_eq!(Rate::ratio(2, 3, Rounding::HalfUp), Rate::from_bps(6_667));
+        assert_eq!(Rate::ratio(-1, 3, Rounding::Down), Rate::from_bps(-3_333));
+        assert_eq!(Rate::ratio(-1, 3, Rounding::Up), Rate::from_bps(-3_334));
+        assert_eq!(Rate::ratio(1, -4, Rounding::Down), Rate::from_bps(-2_500));
+        assert_eq!(Rate::ratio(-1, -4, Rounding::Down), Rate::from_bps(2_500));
+    }
+
+    #[test]
+    fn ratio_is_zero_over_nothing_and_saturates() {
+        assert_eq!(Rate::ratio(5, 0, Rounding::Down), Rate::ZERO);
+        assert_eq!(Rate::ratio(0, 5, Rounding::Up), Rate::ZERO);
+        assert_eq!(Rate::ratio(i128::MAX, 1, Rounding::Down), Rate::from_bps(i64::MAX));
+        assert_eq!(Rate::ratio(i128::MIN, 1, Rounding::Down), Rate::from_bps(-i64::MAX));
+    }
+
+    #[test]
+    fn fees_round_in_the_requested_direction() {
+        let base = Amount::from_minor(999);
+        let fee = Rate::from_bps(25);
+        assert_eq!(base.apply_rate(fee, Rounding::Down), Ok(Amount::from_minor(2)));
+        assert_eq!(base.apply_rate(fee, Rounding::Up), Ok(Amount::from_minor(3)));
+        assert_eq!(base.apply_rate(Rate::ZERO, Rounding::Up), Ok(Amount::ZERO));
+        assert_eq!(base.apply_rate(Rate::ONE_HUNDRED_PERCENT, Rounding::Down), Ok(base));
+    }
+
+    #[test]
+    fn fees_reject_negative_rates_and_overflow() {
+        let base = Amount::from_minor(1_000);
+        assert!(base.apply_rate(Rate::from_bps(-1), Rounding::Down).is_err());
+        assert!(Amount::from_minor(u64::MAX).apply_rate(Rate::from_bps(20_000), Rounding::Down).is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::analytics::{increment_metric, Metric};
+use crate::cash::{cash_balance, credit_cash, lock_cash, spend_locked_cash, unlock_cash};
+use crate::exchange::to_platform_currency;
+use crate::fees::{collect_fee, fee_for};
+use crate::holdings::record_acquisition;
+use crate::investment::{Investment, InvestmentStatus};
+use crate::portfolio::refresh_portfolio;
+use crate::presale::{check_primary_purchase, primary_purchases, record_primary_purchase};
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::{get_current_time, is_authenticated, validate_kyc};
+
+/// Opens a round selling the property's unsold tokens at its current token price.
+/// A property can have only one open round, and direct investment is closed
+/// while it runs.
+#[update]
+pub fn create_offering_round(payload: OfferingRoundPayload) -> Result<OfferingRound, String> {
+    let caller = is_authenticated()?;
+    let mut property = load_property(payload.property_id)?;
+
+    if caller != property.owner && caller != property.manager {
+        return Err("Only the property owner or manager can open an offering round".to_string());
+    }
+    if !matches!(property.status, PropertyStatus::Active) {
+        return Err("Property is not available for investment".to_string());
+    }
+    if property.price_per_token.is_zero() {
+        return Err("Property has no token price to sell at".to_string());
+    }
+    if open_offering_round(&mut property).is_some() {
+        return Err("Property already has an open offering round".to_string());
+    }
+
+    let now = get_current_time();
+    if payload.starts_at >= payload.ends_at || payload.ends_at <= now {
+        return Err("Offering round must end in the future and after it starts".to_string());
+    }
+    if payload.min_ticket < property.price_per_token {
+        return Err("Minimum ticket must cover at least one token".to_string());
+    }
+    if payload.soft_cap.is_zero() || payload.soft_cap > payload.hard_cap {
+        return Err("Soft cap must be greater than zero and at most the hard cap".to_string());
+    }
+    if payload.hard_cap > property.price_per_token.times(property.available_tokens)? {
+        return Err("Hard cap exceeds the value of the unsold tokens".to_string());
+    }
+
+    let round = OfferingRound {
+        id: get_next_id(),
+        property_id: property.id,
+        price_per_token: property.price_per_token,
+        soft_cap: payload.soft_cap,
+        hard_cap: payload.hard_cap,
+        min_ticket: payload.min_ticket,
+        starts_at: payload.starts_at,
+        ends_at: payload.ends_at,
+        status: OfferingStatus::Open,
+        total_committed: Amount::ZERO,
+        tokens_issued: 0,
+        created_by: caller,
+        created_at: now,
+        finalized_at: None,
+    };
+    store_round(&round);
+
+    Ok(round)
+}
+
+/// Commits `amount` to a running round. The amount plus the issuance fee on it is
+/// escrowed from the investor's cash balance until the round is finalized.
+/// Subscribing again adds to the existing commitment.
+#[update]
+pub fn subscribe_to_offering(property_id: u64, round_id: u64, amount: Amount) -> Result<Subscription, String> {
+    let caller = is_authenticated()?;
+    validate_kyc(caller)?;
+
+    let mut round = load_round(property_id, round_id)?;
+    let now = get_current_time();
+    if !matches!(round.status, OfferingStatus::Open) || now < round.starts_at || now >= round.ends_at {
+        return Err("Offering round is not accepting subscriptions".to_string());
+    }
+    if amount.is_zero() {
+        return Err("Subscription amount must be greater than zero".to_string());
+    }
+
+    let property = load_property(property_id)?;
+    let mut subscription = SUBSCRIPTION_STORAGE.with(|storage| storage.borrow().get(&(round_id, caller)))
+        .unwrap_or(Subscription {
+            round_id,
+            investor: caller,
+            committed: Amount::ZERO,
+            fee_escrowed: Amount::ZERO,
+            tokens_allocated: 0,
+            refunded: Amount::ZERO,
+            investment_id: None,
+            subscribed_at: now,
+        });
+
+    let committed = subscription.committed.checked_add(amount)?;
+    if committed < round.min_ticket {
+        return Err("Commitment is below the minimum ticket".to_string());
+    }
+    check_primary_purchase(property_id, caller, committed)?;
+    let fee = fee_for(&FeeSource::Issuance, property_id, amount)?;
+    let total_committed = round.total_committed.checked_add(amount)?;
+    let fee_escrowed = subscription.fee_escrowed.checked_add(fee)?;
+
+    lock_cash(caller, property.settlement_asset, amount.checked_add(fee)?, round_id)?;
+
+    subscription.committed = committed;
+    subscription.fee_escrowed = fee_escrowed;
+    subscription.subscribed_at = now;
+    SUBSCRIPTION_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((round_id, caller), subscription.clone())
+    });
+    round.total_committed = total_committed;
+    store_round(&round);
+
+    Ok(subscription)
+}
+
+/// Closes a round once its end date has passed; anyone may call it, and the next
+/// call that checks the property for an open round does it anyway. If the soft
+/// cap was met, tokens are issued at the round price, pro rata by commitment when
+/// the hard cap was exceeded, and whatever wasn't allocated is refunded. Otherwise
+/// every subscriber is refunded in full.
+#[update]
+pub fn finalize_offering_round(property_id: u64, round_id: u64) -> Result<OfferingRound, String> {
+    let mut round = load_round(property_id, round_id)?;
+    if !matches!(round.status, OfferingStatus::Open) {
+        return Err("Offering round is not open".to_string());
+    }
+    if get_current_time() < round.ends_at {
+        return Err("Offering round has not ended yet".to_string());
+    }
+
+    let property = load_property(property_id)?;
+    let subscriptions = subscriptions_of(round_id);
+    if round.total_committed < round.soft_cap {
+        refund_all(&property, subscriptions)?;
+        round.status = OfferingStatus::Failed;
+    } else {
+        round.tokens_issued = issue_tokens(&round, property, subscriptions)?;
+        round.status = OfferingStatus::Succeeded;
+    }
+
+    round.finalized_at = Some(get_current_time());
+    store_round(&round);
+
+    Ok(round)
+}
+
+/// Abandons an open round and refunds every subscriber. Only the property owner or
+/// manager can cancel.
+#[update]
+pub fn cancel_offering_round(property_id: u64, round_id: u64) -> Result<OfferingRound, String> {
+    let caller = is_authenticated()?;
+    let property = load_property(property_id)?;
+
+    if caller != property.owner && caller != property.manager {
+        return Err("Only the property owner or manager can cancel an offering round".to_string());
+    }
+
+    let mut round = load_round(property_id, round_id)?;
+    if !matches!(round.status, OfferingStatus::Open) {
+        return Err("Offering round is not open".to_string());
+    }
+
+    refund_all(&property, subscriptions_of(round_id))?;
+    round.status = OfferingStatus::Cancelled;
+    round.finalized_at = Some(get_current_time());
+    store_round(&round);
+
+    Ok(round)
+}
+
+/// All rounds for the property, oldest first.
+#[query]
+pub fn get_offering_rounds(property_id: u64) -> Vec<OfferingRound> {
+    OFFERING_ROUND_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|(_, round)| round)
+            .collect()
+    })
+}
+
+#[query]
+pub fn get_offering_subscriptions(round_id: u64) -> Vec<Subscription> {
+    subscriptions_of(round_id)
+}
+
+/// The property's open round, if any. A round past its end date is finalized
+/// first and `property` reloaded, since finalizing issues tokens; a round that
+/// can't be finalized yet is still reported as open.
+pub fn open_offering_round(property: &mut Property) -> Option<OfferingRound> {
+    let round = get_offering_rounds(property.id)
+        .into_iter()
+        .find(|round| matches!(round.status, OfferingStatus::Open))?;
+    if get_current_time() < round.ends_at || finalize_offering_round(property.id, round.id).is_err() {
+        return Some(round);
+    }
+
+    if let Ok(finalized) = load_property(property.id) {
+        *property = finalized;
+    }
+    None
+}
+
+/// Tokens a subscriber receives: everything their commitment buys, or when the
+/// round is oversubscribed their pro rata share of what the hard cap buys. Both
+/// round down; leftover tokens stay unsold.
+fn allocation(round: &OfferingRound, subscription: &Subscription) -> u64 {
+    let price = round.price_per_token.minor_units();
+    if price == 0 {
+        return 0;
+    }
+    if round.total_committed <= round.hard_cap {
+        return subscription.committed.minor_units() / price;
+    }
+
+    let cap_tokens = (round.hard_cap.minor_units() / price) as u128;
+    let share = subscription.committed.minor_units() as u128 * cap_tokens
+        / round.total_committed.minor_units() as u128;
+    share as u64
+}
+
+/// Settles every subscription of a successful round. Allocations, amounts and
+/// every balance they touch are checked before anything is written, so a failure
+/// past that point is a broken invariant and traps rather than leaving the round
+/// half issued.
+fn issue_tokens(round: &OfferingRound, mut property: Property, subscriptions: Vec<Subscription>) -> Result<u64, String> {
+    let settlements = subscriptions
+        .into_iter()
+        .map(|subscription| {
+            let tokens = allocation(round, &subscription);
+            let cost = round.price_per_token.times(tokens)?;
+            let fee = subscription.fee_escrowed.share(
+                cost.minor_units(),
+                subscription.committed.minor_units(),
+                Rounding::Down,
+            )?;
+            let refund = subscription.committed
+                .checked_add(subscription.fee_escrowed)?
+                .checked_sub(cost.checked_add(fee)?)?;
+            Ok((subscription, tokens, cost, fee, refund))
+        })
+        .collect::<Result<Vec<(Subscription, u64, Amount, Amount, Amount)>, String>>()?;
+
+    let tokens_issued: u64 = settlements.iter().map(|(_, tokens, ..)| tokens).sum();
+    if tokens_issued > property.available_tokens {
+        return Err("Not enough unsold tokens to fill the round; cancel it to refund subscribers".to_string());
+    }
+    let raised: Amount = settlements.iter().map(|(_, _, cost, ..)| *cost).sum();
+    let value_locked = to_platform_currency(property.settlement_asset, raised)?;
+    let asset = property.settlement_asset;
+
+    cash_balance(property.owner, asset).available.checked_add(raised)?;
+    for (subscription, _, cost, ..) in &settlements {
+        let escrowed = subscription.committed.checked_add(subscription.fee_escrowed)?;
+        if cash_balance(subscription.investor, asset).locked < escrowed {
+            return Err("A subscriber's escrow no longer covers their commitment".to_string());
+        }
+        primary_purchases(property.id, subscription.investor).checked_add(*cost)?;
+    }
+
+    for (mut subscription, tokens, cost, fee, refund) in settlements {
+        let investor = subscription.investor;
+        if tokens > 0 {
+            let investment_id = get_next_id();
+            let cost_basis = cost.checked_add(fee).unwrap_or_else(|error| ic_cdk::trap(&error));
+            record_primary_purchase(property.id, investor, cost)
+                .unwrap_or_else(|error| ic_cdk::trap(&error));
+
+            insert_investment(Investment {
+                id: investment_id,
+                investor,
+                property_id: property.id,
+                token_amount: tokens,
+                investment_amount: cost,
+                fee,
+                timestamp: get_current_time(),
+                status: InvestmentStatus::Confirmed,
+            });
+            record_acquisition(investor, property.id, investment_id, tokens, cost_basis);
+            collect_fee(FeeSource::Issuance, property.id, asset, investor, cost, fee, investment_id);
+            // Cash moves once the rest of the purchase is on record.
+            spend_locked_cash(investor, asset, cost_basis, CashTransactionKind::Investment, investment_id)
+                .unwrap_or_else(|error| ic_cdk::trap(&error));
+            credit_cash(property.owner, asset, cost, CashTransactionKind::IssuanceProceeds, investment_id)
+                .unwrap_or_else(|error| ic_cdk::trap(&error));
+            subscription.investment_id = Some(investment_id);
+        }
+        if !refund.is_zero() {
+            unlock_cash(investor, asset, refund, round.id)
+                .unwrap_or_else(|error| ic_cdk::trap(&error));
+        }
+
+        subscription.tokens_allocated = tokens;
+        subscription.refunded = refund;
+        SUBSCRIPTION_STORAGE.with(|storage| {
+            storage.borrow_mut().insert((round.id, investor), subscription)
+        });
+        refresh_portfolio(investor);
+    }
+
+    increment_metric(Metric::ValueLocked, value_locked.minor_units());
+    property.available_tokens -= tokens_issued;
+    property.updated_at = get_current_time();
+    insert_property(property);
+
+    Ok(tokens_issued)
+}
+
+/// Returns every subscriber's escrow. Checked up front like `issue_tokens`, and
+/// traps if an unlock still fails.
+fn refund_all(property: &Property, subscriptions: Vec<Subscription>) -> Result<(), String> {
+    for subscription in &subscriptions {
+        let refund = subscription.committed.checked_add(subscription.fee_escrowed)?;
+        if cash_balance(subscription.investor, property.settlement_asset).locked < refund {
+            return Err("A subscriber's escrow no longer covers their commitment".to_string());
+        }
+    }
+
+    for mut subscription in subscriptions {
+        let refund = subscription.committed.checked_add(subscription.fee_escrowed)
+            .unwrap_or_else(|error| ic_cdk::trap(&error));
+        unlock_cash(subscription.investor, property.settlement_asset, refund, subscription.round_id)
+            .unwrap_or_else(|error| ic_cdk::trap(&error));
+
+        subscription.refunded = refund;
+        SUBSCRIPTION_STORAGE.with(|storage| {
+            storage.borrow_mut().insert((subscription.round_id, subscription.investor), subscription)
+        });
+    }
+    Ok(())
+}
+
+fn subscriptions_of(round_id: u64) -> Vec<Subscription> {
+    SUBSCRIPTION_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((round_id, Principal::from_slice(&[]))..)
+            .take_while(|((id, _), _)| *id == round_id)
+            .map(|(_, subscription)| subscription)
+            .collect()
+    })
+}
+
+fn load_round(property_id: u64, round_id: u64) -> Result<OfferingRound, String> {
+    OFFERING_ROUND_STORAGE.with(|storage| storage.borrow().get(&(property_id, round_id)))
+        .ok_or_else(|| "Offering round not found".to_string())
+}
+
+fn store_round(round: &OfferingRound) {
+    OFFERING_ROUND_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((round.property_id, round.id), round.clone())
+    });
+}
+
+fn load_property(property_id: u64) -> Result<Property, String> {
+    PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().get(&property_id)
+            .ok_or_else(|| "Property not found".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round(price: u64, hard_cap: u64, total_committed: u64) -> OfferingRound {
+        OfferingRound {
+            id: 1,
+            property_id: 1,
+            price_per_token: Amount::from_minor(price),
+            soft_cap: Amount::from_minor(1),
+            hard_cap: Amount::from_minor(hard_cap),
+            min_ticket: Amount::from_minor(price),
+            starts_at: 0,
+            ends_at: 1,
+            status: OfferingStatus::Open,
+            total_committed: Amount::from_minor(total_committed),
+            tokens_issued: 0,
+            created_by: Principal::from_slice(&[1]),
+            created_at: 0,
+            finalized_at: None,
+        }
+    }
+
+    fn subscription(committed: u64) -> Subscription {
+        Subscription {
+            round_id: 1,
+            investor: Principal::from_slice(&[2]),
+            committed: Amount::from_minor(committed),
+            fee_escrowed: Amount::ZERO,
+            tokens_allocated: 0,
+            refunded: Amount::ZERO,
+            investment_id: None,
+            subscribed_at: 0,
+        }
+    }
+
+    #[test]
+    fn undersubscribed_rounds_fill_every_whole_token_committed() {
+        let round = round(100, 10_000, 5_000);
+        assert_eq!(allocation(&round, &subscription(1_000)), 10);
+        assert_eq!(allocation(&round, &subscription(1_099)), 10);
+        assert_eq!(allocation(&round, &subscription(99)), 0);
+    }
+
+    #[test]
+    fn oversubscribed_rounds_allocate_pro_rata_and_round_down() {
+        // 30 000 committed against a 10 000 cap: 100 tokens shared three ways.
+        let even = round(100, 10_000, 30_000);
+        let allocations: Vec<u64> = [10_000, 10_000, 10_000]
+            .iter()
+            .map(|committed| allocation(&even, &subscription(*committed)))
+            .collect();
+        assert_eq!(allocations, vec![33, 33, 33]);
+
+        let uneven = round(100, 10_000, 20_000);
+        assert_eq!(allocation(&uneven, &subscription(15_000)), 75);
+        assert_eq!(allocation(&uneven, &subscription(5_000)), 25);
+    }
+
+    #[test]
+    fn a_zero_price_allocates_nothing() {
+        assert_eq!(allocation(&round(0, 10_000, 5_000), &subscription(5_000)), 0);
+        assert_eq!(allocation(&round(0, 10_000, 50_000), &subscription(50_000)), 0);
+    }
+
+    #[test]
+    fn allocation_does_not_overflow_on_large_commitments() {
+        let round = round(1, u64::MAX / 2, u64::MAX);
+        assert_eq!(allocation(&round, &subscription(u64::MAX)), u64::MAX / 2);
+        assert_eq!(allocation(&round, &subscription(u64::MAX / 2)), u64::MAX / 4);
+    }
+}
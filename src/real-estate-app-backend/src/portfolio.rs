@@ -0,0 +1,336 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use std::collections::BTreeMap;
+use crate::exchange::{convert, display_currency, settlement_asset};
+use crate::money::platform_currency;
+use crate::storage::*;
+use crate::types::*;
+use crate::holdings::{get_tax_lots, realized_gains_between};
+use crate::utils::get_current_time;
+
+const NANOS_PER_YEAR: u64 = 365 * 24 * 3600 * 1_000_000_000;
+
+/// A change in an investor's holding of one property.
+struct Flow {
+    timestamp: u64,
+    property_id: u64,
+    tokens: u64,
+    amount: Amount,
+    acquisition: bool,
+}
+
+/// Converts amounts in each property's settlement asset into the investor's
+/// display currency at current rates. Amounts that can't be converted count as zero.
+/// Signed totals saturate at the `i64` range rather than wrapping.
+struct Valuation {
+    display: Currency,
+    assets: BTreeMap<u64, Currency>,
+}
+
+impl Valuation {
+    fn new(owner: Principal) -> Valuation {
+        let display = USER_STORAGE.with(|storage| storage.borrow().get(&owner))
+            .and_then(|profile| display_currency(&profile.display_currency).ok())
+            .unwrap_or_else(platform_currency);
+        Valuation { display, assets: BTreeMap::new() }
+    }
+
+    fn add_property(&mut self, property: &Property) {
+        if let Ok(asset) = settlement_asset(property.settlement_asset) {
+            self.assets.insert(property.id, asset.currency());
+        }
+    }
+
+    fn amount(&self, property_id: u64, amount: Amount) -> Amount {
+        self.assets.get(&property_id)
+            .and_then(|asset| convert(amount, asset, &self.display).ok())
+            .unwrap_or(Amount::ZERO)
+    }
+
+    fn signed(&self, property_id: u64, minor_units: i64) -> i64 {
+        let converted = self.amount(property_id, Amount::from_minor(minor_units.unsigned_abs()));
+        let converted = i64::try_from(converted.minor_units()).unwrap_or(i64::MAX);
+        if minor_units < 0 { -converted } else { converted }
+    }
+}
+
+#[derive(Default)]
+struct Position {
+    tokens: u64,
+    cost_basis: Amount,
+    invested: Amount,
+    realized_pnl: i64,
+    dividends: Amount,
+    purchase_date: u64,
+}
+
+/// Recomputes the investor's portfolio from their lots, realized gains and dividends.
+#[query]
+pub fn get_portfolio(owner: Principal) -> Portfolio {
+    compute_portfolio(owner)
+}
+
+/// Recomputes and caches the portfolio; called whenever a holding changes.
+pub fn refresh_portfolio(owner: Principal) {
+    let portfolio = compute_portfolio(owner);
+    PORTFOLIO_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(owner, portfolio)
+    });
+}
+
+fn compute_portfolio(owner: Principal) -> Portfolio {
+    let now = get_current_time();
+    let mut valuation = Valuation::new(owner);
+    let flows = portfolio_flows(owner);
+    let dividends = dividends_received(owner);
+
+    // Open lots carry the remaining cost basis; realized gains were booked at each
+    // disposal under the investor's cost basis method.
+    let mut positions: BTreeMap<u64, Position> = BTreeMap::new();
+    for lot in get_tax_lots(owner, None) {
+        let position = positions.entry(lot.property_id).or_default();
+        if position.purchase_date == 0 || lot.acquired_at < position.purchase_date {
+            position.purchase_date = lot.acquired_at;
+        }
+        position.tokens += lot.remaining_quantity;
+        position.cost_basis += lot.remaining_cost_basis;
+        position.invested += lot.cost_basis;
+    }
+    for gain in realized_gains_between(owner, 0, u64::MAX) {
+        let position = positions.entry(gain.property_id).or_default();
+        position.realized_pnl = position.realized_pnl.saturating_add(gain.gain);
+    }
+    for (property_id, _, amount) in dividends.iter() {
+        positions.entry(*property_id).or_default().dividends += *amount;
+    }
+
+    let mut properties = Vec::new();
+    let mut weights: Vec<(Property, Amount)> = Vec::new();
+    let mut total_invested = Amount::ZERO;
+    let mut total_dividends_received = Amount::ZERO;
+    let mut realized_pnl = 0i64;
+    let mut unrealized_pnl = 0i64;
+    for (property_id, position) in positions.iter() {
+        let Some(property) = PROPERTY_STORAGE.with(|storage| storage.borrow().get(property_id)) else {
+            continue;
+        };
+        valuation.add_property(&property);
+
+        let price = current_price(*property_id);
+        let current_value = price.times(position.tokens).unwrap_or(Amount::from_minor(u64::MAX));
+        let current_value_display = valuation.amount(*property_id, current_value);
+        total_invested += valuation.amount(*property_id, position.invested);
+        total_dividends_received += valuation.amount(*property_id, position.dividends);
+        realized_pnl = realized_pnl.saturating_add(valuation.signed(*property_id, position.realized_pnl));
+        unrealized_pnl = unrealized_pnl.saturating_add(
+            current_value_display.saturating_signed_difference(valuation.amount(*property_id, position.cost_basis)),
+        );
+
+        properties.push(PortfolioProperty {
+            property_id: *property_id,
+            settlement_asset: property.settlement_asset,
+            token_amount: position.tokens,
+            initial_investment: position.invested,
+            cost_basis: position.cost_basis,
+            current_value,
+            current_value_display: Money::new(current_value_display, valuation.display.clone()),
+            realized_pnl: position.realized_pnl,
+            unrealized_pnl: current_value.saturating_signed_difference(position.cost_basis),
+            dividends_received: position.dividends,
+            purchase_date: position.purchase_date,
+        });
+        weights.push((property, current_value_display));
+    }
+
+    let total_value: Amount = properties.iter().map(|p| p.current_value_display.amount).sum();
+    let total_tokens: u64 = properties.iter().map(|p| p.token_amount).sum();
+
+    let total_return = realized_pnl
+        .saturating_add(unrealized_pnl)
+        .saturating_add(i64::try_from(total_dividends_received.minor_units()).unwrap_or(i64::MAX));
+    let roi_percentage = Rate::ratio(total_return as i128, total_invested.minor_units() as i128, Rounding::HalfUp);
+    let first_purchase = flows.first().map(|flow| flow.timestamp).unwrap_or(now);
+    let trailing_dividends: Amount = dividends.iter()
+        .filter(|(_, paid_at, _)| now.saturating_sub(*paid_at) <= NANOS_PER_YEAR)
+        .map(|(property_id, _, amount)| valuation.amount(*property_id, *amount))
+        .sum();
+
+    Portfolio {
+        owner,
+        display_currency: valuation.display.clone(),
+        total_value: Money::new(total_value, valuation.display.clone()),
+        total_tokens,
+        properties,
+        total_dividends_received: Money::new(total_dividends_received, valuation.display.clone()),
+        performance_metrics: PerformanceMetrics {
+            total_return,
+            annual_yield: annualize(roi_percentage, now.saturating_sub(first_purchase)),
+            roi_percentage,
+            diversification_score: diversification_score(&weights),
+            realized_pnl,
+            unrealized_pnl,
+            dividend_yield: Rate::ratio(trailing_dividends.minor_units() as i128, total_value.minor_units() as i128, Rounding::HalfUp),
+            time_weighted_return: time_weighted_return(&flows, &dividends, &valuation, now),
+        },
+        last_updated: now,
+    }
+}
+
+/// Acquisitions are the investor's lots; disposals are their realized gains.
+fn portfolio_flows(owner: Principal) -> Vec<Flow> {
+    let mut flows: Vec<Flow> = get_tax_lots(owner, None)
+        .into_iter()
+        .map(|lot| Flow {
+            timestamp: lot.acquired_at,
+            property_id: lot.property_id,
+            tokens: lot.quantity,
+            amount: lot.cost_basis,
+            acquisition: true,
+        })
+        .collect();
+
+    flows.extend(realized_gains_between(owner, 0, u64::MAX).into_iter().map(|gain| Flow {
+        timestamp: gain.disposed_at,
+        property_id: gain.property_id,
+        tokens: gain.quantity,
+        amount: gain.proceeds,
+        acquisition: false,
+    }));
+
+    flows.sort_by_key(|flow| (flow.timestamp, !flow.acquisition));
+    flows
+}
+
+/// (property_id, distribution_date, amount) for every dividend paid to the investor.
+pub(crate) fn dividends_received(owner: Principal) -> Vec<(u64, u64, Amount)> {
+    distributions_by_recipient(owner)
+        .into_iter()
+        .filter(|distribution| matches!(distribution.kind, DistributionKind::Dividend))
+        .flat_map(|distribution| {
+            distribution.recipients
+                .into_iter()
+                .filter(|recipient| recipient.investor == owner && recipient.paid)
+                .map(move |recipient| (distribution.property_id, distribution.distribution_date, recipient.dividend_amount))
+        })
+        .collect()
+}
+
+/// Last traded price, falling back to the issue price for properties that never traded.
+pub fn current_price(property_id: u64) -> Amount {
+    MARKET_DATA_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .map(|market_data| market_data.current_price)
+        .or_else(|| PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id)).map(|property| property.price_per_token))
+        .unwrap_or(Amount::ZERO)
+}
+
+/// Chain-links the return of every sub-period between cash flows, so deposits and
+/// withdrawals don't distort performance. Historical prices are the prices the
+/// investor actually transacted at; the final period is marked to current prices.
+/// Values are in the display currency at current rates. Period values are exact;
+/// only the chained growth factor is floating point.
+fn time_weighted_return(flows: &[Flow], dividends: &[(u64, u64, Amount)], valuation: &Valuation, now: u64) -> Rate {
+    let mut holdings: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut prices: BTreeMap<u64, Amount> = BTreeMap::new();
+    let mut growth = 1.0;
+    let mut period_start: Option<(u64, u128)> = None;
+
+    let value = |holdings: &BTreeMap<u64, u64>, prices: &BTreeMap<u64, Amount>| -> u128 {
+        holdings.iter()
+            .map(|(property_id, tokens)| {
+                let price = prices.get(property_id).copied().unwrap_or(Amount::ZERO);
+                let value = price.times(*tokens).unwrap_or(Amount::from_minor(u64::MAX));
+                valuation.amount(*property_id, value).minor_units() as u128
+            })
+            .sum()
+    };
+    let dividends_between = |from: u64, to: u64| -> u128 {
+        dividends.iter()
+            .filter(|(_, paid_at, _)| *paid_at > from && *paid_at <= to)
+            .map(|(property_id, _, amount)| valuation.amount(*property_id, *amount).minor_units() as u128)
+            .sum()
+    };
+
+    for flow in flows.iter() {
+        if flow.tokens > 0 {
+            if let Ok(price) = flow.amount.per_unit(flow.tokens, Rounding::HalfUp) {
+                prices.insert(flow.property_id, price);
+            }
+        }
+        if let Some((start, start_value)) = period_start {
+            if start_value > 0 {
+                let end_value = value(&holdings, &prices) + dividends_between(start, flow.timestamp);
+                growth *= end_value as f64 / start_value as f64;
+            }
+        }
+
+        let held = holdings.entry(flow.property_id).or_insert(0);
+        if flow.acquisition {
+            *held += flow.tokens;
+        } else {
+            *held = held.saturating_sub(flow.tokens);
+        }
+        period_start = Some((flow.timestamp, value(&holdings, &prices)));
+    }
+
+    if let Some((start, start_value)) = period_start {
+        if start_value > 0 {
+            for property_id in holdings.keys() {
+                prices.insert(*property_id, current_price(*property_id));
+            }
+            let end_value = value(&holdings, &prices) + dividends_between(start, now);
+            growth *= end_value as f64 / start_value as f64;
+        }
+    }
+
+    Rate::from_growth_factor(growth)
+}
+
+/// 0 for a single asset in a single region, approaching 100% as value spreads evenly
+/// across property types and regions (one minus the Herfindahl index, averaged).
+fn diversification_score(weights: &[(Property, Amount)]) -> Rate {
+    let mut by_type: BTreeMap<String, u128> = BTreeMap::new();
+    let mut by_region: BTreeMap<String, u128> = BTreeMap::new();
+    for (property, value) in weights.iter() {
+        *by_type.entry(property_type_name(&property.property_type).to_string()).or_insert(0) += value.minor_units() as u128;
+        *by_region.entry(region(&property.location)).or_insert(0) += value.minor_units() as u128;
+    }
+
+    let spread = |buckets: &BTreeMap<String, u128>| -> i64 {
+        let total: u128 = buckets.values().sum();
+        if total == 0 {
+            return 0;
+        }
+        let hhi: i64 = buckets.values()
+            .map(|value| {
+                let share = Rate::ratio(*value as i128, total as i128, Rounding::HalfUp).bps();
+                share * share / Rate::ONE_HUNDRED_PERCENT.bps()
+            })
+            .sum();
+        Rate::ONE_HUNDRED_PERCENT.bps() - hhi
+    };
+
+    Rate::from_bps((spread(&by_type) + spread(&by_region)) / 2)
+}
+
+fn property_type_name(property_type: &PropertyType) -> &'static str {
+    match property_type {
+        PropertyType::Residential => "Residential",
+        PropertyType::Commercial => "Commercial",
+        PropertyType::Industrial => "Industrial",
+        PropertyType::Land => "Land",
+        PropertyType::Trophy => "Trophy",
+    }
+}
+
+/// The broadest part of a location, e.g. "California" in "San Francisco, California".
+fn region(location: &str) -> String {
+    location.rsplit(',').next().unwrap_or("").trim().to_lowercase()
+}
+
+fn annualize(rate: Rate, elapsed: u64) -> Rate {
+    let years = elapsed as f64 / NANOS_PER_YEAR as f64;
+    if years < 1.0 {
+        return rate;
+    }
+    Rate::from_growth_factor(rate.growth_factor().max(0.0).powf(1.0 / years))
+}
@@ -0,0 +1,280 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::{get_current_time, is_authenticated};
+
+const MAX_TIERS: usize = 10;
+const MAX_TIER_NAME_CHARS: usize = 50;
+const MAX_ALLOWLIST_BATCH: usize = 500;
+
+/// Puts the property's issuance behind a pre-sale, replacing any earlier
+/// settings. `None` opens the sale to everyone straight away. The public sale
+/// must still be ahead, and tiers can't open in the past, except that a tier
+/// kept under the same name and opening time from the current settings stays open.
+#[update]
+pub fn set_offering_access(property_id: u64, payload: Option<OfferingAccessPayload>) -> Result<Option<OfferingAccess>, String> {
+    let caller = authorize(property_id)?;
+
+    let Some(payload) = payload else {
+        OFFERING_ACCESS_STORAGE.with(|storage| storage.borrow_mut().remove(&property_id));
+        return Ok(None);
+    };
+
+    let now = get_current_time();
+    validate_access(&payload, get_offering_access(property_id).as_ref(), now)?;
+
+    let access = OfferingAccess {
+        property_id,
+        tiers: payload.tiers,
+        public_sale_at: payload.public_sale_at,
+        updated_by: caller,
+        updated_at: now,
+    };
+    OFFERING_ACCESS_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(property_id, access.clone())
+    });
+
+    Ok(Some(access))
+}
+
+fn validate_access(payload: &OfferingAccessPayload, current: Option<&OfferingAccess>, now: u64) -> Result<(), String> {
+    if payload.public_sale_at <= now {
+        return Err("The public sale must open in the future".to_string());
+    }
+    if payload.tiers.len() > MAX_TIERS {
+        return Err(format!("At most {} tiers are allowed", MAX_TIERS));
+    }
+    let already_open = |tier: &AccessTier| current.is_some_and(|current| {
+        current.tiers.iter().any(|open| open.name == tier.name && open.opens_at == tier.opens_at)
+    });
+    for tier in &payload.tiers {
+        if tier.name.is_empty() || tier.name.chars().count() > MAX_TIER_NAME_CHARS {
+            return Err(format!("Tier names must be 1-{} characters", MAX_TIER_NAME_CHARS));
+        }
+        if tier.opens_at >= payload.public_sale_at {
+            return Err("Every tier must open before the public sale".to_string());
+        }
+        if tier.opens_at < now && !already_open(tier) {
+            return Err(format!("Tier {} cannot open in the past", tier.name));
+        }
+        if tier.allocation_cap.is_some_and(|cap| cap.is_zero()) {
+            return Err("Allocation caps must be greater than zero".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Adds investors to the property's allowlist, or updates the cap of those
+/// already on it.
+#[update]
+pub fn add_to_allowlist(property_id: u64, investors: Vec<Principal>, allocation_cap: Option<Amount>) -> Result<u32, String> {
+    let caller = authorize(property_id)?;
+
+    if investors.len() > MAX_ALLOWLIST_BATCH {
+        return Err(format!("At most {} investors can be added at once", MAX_ALLOWLIST_BATCH));
+    }
+    if allocation_cap.is_some_and(|cap| cap.is_zero()) {
+        return Err("Allocation caps must be greater than zero".to_string());
+    }
+
+    let now = get_current_time();
+    ALLOWLIST_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for investor in &investors {
+            storage.insert((property_id, *investor), AllowlistEntry {
+                property_id,
+                investor: *investor,
+                allocation_cap,
+                added_by: caller,
+                added_at: now,
+            });
+        }
+    });
+
+    Ok(investors.len() as u32)
+}
+
+#[update]
+pub fn remove_from_allowlist(property_id: u64, investors: Vec<Principal>) -> Result<u32, String> {
+    authorize(property_id)?;
+
+    let removed = ALLOWLIST_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        investors
+            .iter()
+            .filter(|investor| storage.remove(&(property_id, **investor)).is_some())
+            .count()
+    });
+
+    Ok(removed as u32)
+}
+
+#[query]
+pub fn get_offering_access(property_id: u64) -> Option<OfferingAccess> {
+    OFFERING_ACCESS_STORAGE.with(|storage| storage.borrow().get(&property_id))
+}
+
+#[query]
+pub fn get_allowlist(property_id: u64) -> Vec<AllowlistEntry> {
+    ALLOWLIST_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, Principal::from_slice(&[]))..)
+            .take_while(|((id, _), _)| *id == property_id)
+            .map(|(_, entry)| entry)
+            .collect()
+    })
+}
+
+/// Checks that `investor` may buy `amount` more at issuance right now. During the
+/// pre-sale the investor needs an allowlist entry or an open tier they qualify
+/// for, and the most generous cap among those applies to everything they've
+/// bought at issuance so far plus `amount`.
+pub fn check_primary_purchase(property_id: u64, investor: Principal, amount: Amount) -> Result<(), String> {
+    let Some(access) = get_offering_access(property_id) else {
+        return Ok(());
+    };
+    let now = get_current_time();
+    if now >= access.public_sale_at {
+        return Ok(());
+    }
+
+    let user = USER_STORAGE.with(|storage| storage.borrow().get(&investor));
+    let mut caps: Vec<Option<Amount>> = access.tiers
+        .iter()
+        .filter(|tier| tier.opens_at <= now && user.as_ref().is_some_and(|user| qualifies(tier, user)))
+        .map(|tier| tier.allocation_cap)
+        .collect();
+    if let Some(entry) = ALLOWLIST_STORAGE.with(|storage| storage.borrow().get(&(property_id, investor))) {
+        caps.push(entry.allocation_cap);
+    }
+
+    if caps.is_empty() {
+        return Err("The pre-sale is limited to allowlisted and qualifying investors until the public sale opens".to_string());
+    }
+    if caps.iter().any(Option::is_none) {
+        return Ok(());
+    }
+
+    let cap = caps.into_iter().flatten().max().unwrap_or(Amount::ZERO);
+    if primary_purchases(property_id, investor).checked_add(amount)? > cap {
+        return Err("Purchase exceeds your pre-sale allocation".to_string());
+    }
+    Ok(())
+}
+
+/// Counts an issuance purchase against the investor's pre-sale allocation.
+pub fn record_primary_purchase(property_id: u64, investor: Principal, amount: Amount) -> Result<(), String> {
+    let total = primary_purchases(property_id, investor).checked_add(amount)?;
+    PRIMARY_PURCHASE_STORAGE.with(|storage| storage.borrow_mut().insert((property_id, investor), total));
+    Ok(())
+}
+
+/// Limits the sale to the given holders until `public_sale_at`, each allowed to buy
+/// up to their entitlement on top of what they've already bought. Replaces the
+/// property's tiers and allowlist.
+pub fn grant_preemptive_rights(property_id: u64, entitlements: Vec<(Principal, Amount)>, public_sale_at: u64, granted_by: Principal) -> Result<(), String> {
+    let now = get_current_time();
+    let entries = entitlements
+        .into_iter()
+        .map(|(investor, entitlement)| {
+            Ok(AllowlistEntry {
+                property_id,
+                investor,
+                allocation_cap: Some(primary_purchases(property_id, investor).checked_add(entitlement)?),
+                added_by: granted_by,
+                added_at: now,
+            })
+        })
+        .collect::<Result<Vec<AllowlistEntry>, String>>()?;
+
+    let previous: Vec<Principal> = get_allowlist(property_id).into_iter().map(|entry| entry.investor).collect();
+    ALLOWLIST_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for investor in previous {
+            storage.remove(&(property_id, investor));
+        }
+        for entry in entries {
+            storage.insert((property_id, entry.investor), entry);
+        }
+    });
+    OFFERING_ACCESS_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(property_id, OfferingAccess {
+            property_id,
+            tiers: Vec::new(),
+            public_sale_at,
+            updated_by: granted_by,
+            updated_at: now,
+        })
+    });
+
+    Ok(())
+}
+
+pub fn primary_purchases(property_id: u64, investor: Principal) -> Amount {
+    PRIMARY_PURCHASE_STORAGE.with(|storage| storage.borrow().get(&(property_id, investor)))
+        .unwrap_or(Amount::ZERO)
+}
+
+fn qualifies(tier: &AccessTier, user: &UserProfile) -> bool {
+    let level = match (&user.kyc_status, &user.kyc_verification) {
+        (KycStatus::Verified, Some(verification)) => &verification.verification_level,
+        _ => return false,
+    };
+    *level >= tier.min_kyc_level
+        && (tier.risk_profiles.is_empty() || tier.risk_profiles.contains(&user.risk_profile))
+}
+
+fn authorize(property_id: u64) -> Result<Principal, String> {
+    let caller = is_authenticated()?;
+    let property = PROPERTY_STORAGE.with(|storage| storage.borrow().get(&property_id))
+        .ok_or_else(|| "Property not found".to_string())?;
+
+    if caller != property.owner && caller != property.manager {
+        return Err("Only the property owner or manager can manage the pre-sale".to_string());
+    }
+    Ok(caller)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tier(name: &str, opens_at: u64) -> AccessTier {
+        AccessTier {
+            name: name.to_string(),
+            min_kyc_level: KycLevel::Basic,
+            risk_profiles: Vec::new(),
+            opens_at,
+            allocation_cap: None,
+        }
+    }
+
+    fn payload(tiers: Vec<AccessTier>, public_sale_at: u64) -> OfferingAccessPayload {
+        OfferingAccessPayload { tiers, public_sale_at }
+    }
+
+    #[test]
+    fn the_public_sale_and_new_tiers_must_be_ahead() {
+        let now = 1_000;
+        assert!(validate_access(&payload(vec![tier("early", now)], now + 10), None, now).is_ok());
+        assert!(validate_access(&payload(Vec::new(), now), None, now).is_err());
+        assert!(validate_access(&payload(vec![tier("early", now - 1)], now + 10), None, now).is_err());
+    }
+
+    #[test]
+    fn tiers_already_open_can_be_kept() {
+        let now = 1_000;
+        let current = OfferingAccess {
+            property_id: 1,
+            tiers: vec![tier("early", 500)],
+            public_sale_at: 2_000,
+            updated_by: Principal::anonymous(),
+            updated_at: 400,
+        };
+        let kept = payload(vec![tier("early", 500), tier("late", 1_500)], 2_000);
+        assert!(validate_access(&kept, Some(&current), now).is_ok());
+        let moved = payload(vec![tier("early", 600)], 2_000);
+        assert!(validate_access(&moved, Some(&current), now).is_err());
+    }
+}
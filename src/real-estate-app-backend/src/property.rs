@@ -1,17 +1,35 @@
 use ic_cdk_macros::*;
-use crate::storage::{get_next_id, PROPERTY_STORAGE};
+use crate::analytics::record_snapshot;
+use crate::cash::debit_cash;
+use crate::exchange::settlement_asset;
+use crate::fees::{collect_fee, listing_fee};
+use crate::storage::*;
 use crate::types::*;
-use crate::utils::{get_current_time, is_authenticated, validate_kyc};
+use crate::utils::{get_current_time, is_authenticated, signed_rate_key, tokenize, validate_kyc, yield_sort_key};
+use std::collections::BTreeSet;
 
 #[update]
 pub fn create_property(payload: CreatePropertyPayload) -> Result<Property, String> {
     let caller = is_authenticated()?;
     validate_kyc(caller)?;
 
+    settlement_asset(payload.settlement_asset)?;
+    if payload.total_tokens == 0 {
+        return Err("Total tokens must be greater than zero".to_string());
+    }
+    if payload.rental_yield < Rate::ZERO {
+        return Err("Rental yield cannot be negative".to_string());
+    }
+    // A remainder would be value that no token represents.
+    let price_per_token = payload.total_value.per_unit(payload.total_tokens, Rounding::Down)?;
+    if price_per_token.times(payload.total_tokens)? != payload.total_value {
+        return Err("Total value must divide evenly into tokens".to_string());
+    }
+
     let property_id = get_next_id();
     let current_time = get_current_time();
-    let price_per_token = payload.total_value / payload.total_tokens;
-    
+    let listing_fee = listing_fee(property_id, payload.settlement_asset)?;
+
     let property = Property {
         id: property_id,
         title: payload.title,
@@ -22,6 +40,7 @@ pub fn create_property(payload: CreatePropertyPayload) -> Result<Property, Strin
         available_tokens: payload.total_tokens,
         price_per_token,
         owner: caller,
+        manager: caller,
         created_at: current_time,
         updated_at: current_time,
         property_type: payload.property_type,
@@ -29,23 +48,148 @@ pub fn create_property(payload: CreatePropertyPayload) -> Result<Property, Strin
         images: payload.images,
         documents: payload.documents,
         rental_yield: payload.rental_yield,
-        appreciation_rate: 0.0,
+        appreciation_rate: Rate::ZERO,
         property_highlights: payload.property_highlights,
         legal_structure: payload.legal_structure,
         valuation_date: current_time,
+        settlement_asset: payload.settlement_asset,
         next_dividend_date: current_time + (90 * 24 * 3600 * 1_000_000_000),
     };
 
-    PROPERTY_STORAGE.with(|storage| {
-        storage.borrow_mut().insert(property_id, property.clone())
-    });
+    if !listing_fee.is_zero() {
+        debit_cash(caller, property.settlement_asset, listing_fee, CashTransactionKind::ListingFee, property_id)?;
+    }
+    insert_property(property.clone());
+
+    collect_fee(FeeSource::Listing, property_id, property.settlement_asset, caller, payload.total_value, listing_fee, property_id);
+    record_snapshot();
 
     Ok(property)
 }
 
 #[query]
-pub fn get_all_properties() -> Vec<Property> {
-    PROPERTY_STORAGE.with(|storage| {
-        storage.borrow().iter().map(|(_, property)| property).collect()
-    })
+pub fn get_all_properties(page: PageRequest) -> Page<Property> {
+    properties_page(&page, None)
+}
+
+/// Each filter is answered from its own index and the candidate ids are
+/// intersected, then paged through the sort index, so only the properties on the
+/// returned page are ever loaded.
+#[query]
+pub fn search_properties(filter: PropertySearchFilter, page: PageRequest) -> Page<Property> {
+    let mut candidates: Vec<BTreeSet<u64>> = Vec::new();
+
+    if let Some(property_type) = filter.property_type.as_ref() {
+        candidates.push(property_ids_by_type(property_type));
+    }
+    if let Some(status) = filter.status.as_ref() {
+        candidates.push(property_ids_by_status(status));
+    }
+    if let Some(location) = filter.location.as_ref() {
+        candidates.extend(tokenize(location).iter().map(|term| property_ids_by_location_term(term)));
+    }
+    if let Some(text) = filter.text.as_ref() {
+        candidates.extend(tokenize(text).iter().map(|term| property_ids_by_text_term(term)));
+    }
+    if let Some(range) = filter.total_value.as_ref() {
+        candidates.push(ids_in_amount_range(VALUE_FIELD, range));
+    }
+    if let Some(range) = filter.price_per_token.as_ref() {
+        candidates.push(ids_in_amount_range(PRICE_FIELD, range));
+    }
+    if let Some(range) = filter.available_tokens.as_ref() {
+        candidates.push(ids_in_amount_range(AVAILABLE_TOKENS_FIELD, range));
+    }
+    if let Some(range) = filter.rental_yield.as_ref() {
+        let min = range.min.map_or(0, yield_sort_key);
+        let max = range.max.map_or(u64::MAX, yield_sort_key);
+        candidates.push(property_ids_in_range(YIELD_FIELD, min, max));
+    }
+    if let Some(range) = filter.appreciation_rate.as_ref() {
+        let min = range.min.map_or(0, signed_rate_key);
+        let max = range.max.map_or(u64::MAX, signed_rate_key);
+        candidates.push(property_ids_in_range(APPRECIATION_FIELD, min, max));
+    }
+
+    if candidates.is_empty() {
+        return properties_page(&page, None);
+    }
+
+    // Intersect smallest-first so the working set only shrinks.
+    candidates.sort_by_key(|ids| ids.len());
+    let mut matches = candidates.remove(0);
+    for ids in candidates {
+        matches.retain(|id| ids.contains(id));
+        if matches.is_empty() {
+            break;
+        }
+    }
+
+    properties_page(&page, Some(&matches))
+}
+
+fn ids_in_amount_range(field: u8, range: &AmountRange) -> BTreeSet<u64> {
+    property_ids_in_range(field, range.min.unwrap_or(0), range.max.unwrap_or(u64::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+
+    fn listed(id: u64, location: &str) -> Property {
+        let owner = Principal::from_slice(&[1]);
+        Property {
+            id,
+            title: format!("Listing {}", id),
+            description: String::new(),
+            location: location.to_string(),
+            total_value: Amount::from_minor(1_000),
+            total_tokens: 10,
+            available_tokens: 10,
+            price_per_token: Amount::from_minor(100),
+            owner,
+            manager: owner,
+            created_at: id,
+            updated_at: id,
+            property_type: PropertyType::Residential,
+            status: PropertyStatus::Active,
+            images: Vec::new(),
+            documents: Vec::new(),
+            rental_yield: Rate::ZERO,
+            appreciation_rate: Rate::ZERO,
+            property_highlights: Vec::new(),
+            legal_structure: String::new(),
+            valuation_date: id,
+            next_dividend_date: id,
+            settlement_asset: Principal::from_slice(&[2]),
+        }
+    }
+
+    fn in_location(location: &str, limit: u32, cursor: Option<PageCursor>) -> Page<Property> {
+        let filter = PropertySearchFilter { location: Some(location.to_string()), ..Default::default() };
+        search_properties(filter, PageRequest { limit: Some(limit), cursor, sort_by: None, direction: None })
+    }
+
+    fn ids(page: &Page<Property>) -> Vec<u64> {
+        page.items.iter().map(|property| property.id).collect()
+    }
+
+    #[test]
+    fn location_matches_word_prefixes_and_pages_through_the_sort_index() {
+        insert_property(listed(1, "San Francisco, CA"));
+        insert_property(listed(2, "Franklin, TN"));
+        insert_property(listed(3, "Los Angeles, CA"));
+        insert_property(listed(4, "Sacramento, CA"));
+
+        assert_eq!(ids(&in_location("Franc", 10, None)), vec![1]);
+        assert_eq!(ids(&in_location("fran", 10, None)), vec![1, 2]);
+        assert!(in_location("cisco", 10, None).items.is_empty());
+
+        let first = in_location("ca", 2, None);
+        assert_eq!(ids(&first), vec![1, 3]);
+        let second = in_location("ca", 2, first.next_cursor);
+        assert_eq!(ids(&second), vec![4]);
+        assert!(second.next_cursor.is_none());
+    }
 }
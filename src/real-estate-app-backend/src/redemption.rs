@@ -0,0 +1,118 @@
+use ic_cdk_macros::*;
+use crate::analytics::{decrement_metric, Metric};
+use crate::cash::cash_balance;
+use crate::dividend::run_distribution;
+use crate::exchange::to_platform_currency;
+use crate::holdings::{holders_of, record_disposal, release_locks};
+use crate::marketplace::cancel_property_orders;
+use crate::offering::open_offering_round;
+use crate::portfolio::refresh_portfolio;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::{get_current_time, is_admin, is_authenticated};
+
+/// Settles the property's sale on the owner's or an admin's authority. Holders can
+/// approve a sale through a `PropertySale` proposal instead.
+#[update]
+pub fn sell_property(property_id: u64, sale_price: Amount) -> Result<Redemption, String> {
+    let caller = is_authenticated()?;
+    let property = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().get(&property_id)
+            .ok_or_else(|| "Property not found".to_string())
+    })?;
+
+    if caller != property.owner {
+        is_admin().map_err(|_| "Only the property owner or an admin can sell the property".to_string())?;
+    }
+    if sale_price.is_zero() {
+        return Err("Sale price must be greater than zero".to_string());
+    }
+
+    redeem_property(property, sale_price, None)
+}
+
+#[query]
+pub fn get_redemption(property_id: u64) -> Option<Redemption> {
+    REDEMPTION_STORAGE.with(|storage| storage.borrow().get(&property_id))
+}
+
+/// Winds a property down after its sale. Trading halts and open orders are
+/// cancelled with their escrow refunded, and each holder is paid their tokens'
+/// share of `sale_price` out of the owner's cash balance, measured against every
+/// token issued; the unsold tokens' share stays with the owner. Each holder's
+/// tokens are burned against their payout, realizing their final gain or loss.
+/// The owner has to deposit the proceeds before the sale can settle.
+pub fn redeem_property(mut property: Property, sale_price: Amount, proposal_id: Option<u64>) -> Result<Redemption, String> {
+    if matches!(property.status, PropertyStatus::Sold) {
+        return Err("Property is already sold".to_string());
+    }
+    if open_offering_round(&mut property).is_some() {
+        return Err("Cancel the open offering round before selling the property".to_string());
+    }
+    if cash_balance(property.owner, property.settlement_asset).available < sale_price {
+        return Err("The owner must deposit the sale proceeds before the sale can settle".to_string());
+    }
+    // What the sold tokens count for in the platform's total value locked.
+    let value_locked = property.price_per_token
+        .times(property.total_tokens - property.available_tokens)
+        .and_then(|raised| to_platform_currency(property.settlement_asset, raised))?;
+
+    // Everything from here on writes, so a failure traps to undo the whole sale
+    // rather than committing half of it.
+    let orders_cancelled = cancel_property_orders(property.id)
+        .unwrap_or_else(|error| ic_cdk::trap(&error));
+    // Proposal deposits and order escrow can't outlive the tokens.
+    release_locks(property.id);
+
+    let holders = holders_of(property.id);
+    let distribution = if holders.is_empty() {
+        None
+    } else {
+        Some(
+            run_distribution(property.id, sale_price, DistributionKind::SaleProceeds, proposal_id)
+                .unwrap_or_else(|error| ic_cdk::trap(&error)),
+        )
+    };
+
+    let mut tokens_redeemed = 0;
+    let mut total_realized_gain: i64 = 0;
+    if let Some(distribution) = &distribution {
+        for recipient in &distribution.recipients {
+            let gain = record_disposal(
+                recipient.investor,
+                property.id,
+                distribution.id,
+                recipient.token_amount,
+                recipient.dividend_amount,
+            )
+            .unwrap_or_else(|error| ic_cdk::trap(&error));
+            tokens_redeemed += recipient.token_amount;
+            total_realized_gain = total_realized_gain.saturating_add(gain.gain);
+            refresh_portfolio(recipient.investor);
+        }
+    }
+
+    let now = get_current_time();
+    let redemption = Redemption {
+        property_id: property.id,
+        proposal_id,
+        sale_price,
+        distribution_id: distribution.map(|distribution| distribution.id),
+        tokens_redeemed,
+        holders: holders.len() as u32,
+        orders_cancelled,
+        total_realized_gain,
+        redeemed_at: now,
+    };
+    REDEMPTION_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(property.id, redemption.clone())
+    });
+
+    property.status = PropertyStatus::Sold;
+    property.available_tokens = 0;
+    property.updated_at = now;
+    insert_property(property);
+    decrement_metric(Metric::ValueLocked, value_locked.minor_units());
+
+    Ok(redemption)
+}
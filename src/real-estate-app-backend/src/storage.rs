@@ -1,11 +1,16 @@
 use crate::investment::Investment;
 use crate::types::*;
-use candid::{Decode, Encode, Principal};
+use crate::utils::{is_descending, page_limit, paginate, signed_rate_key, tokenize, yield_sort_key, Paginated};
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::de::DeserializeOwned;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
 use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, Storable};
 use ic_stable_structures::storable::Bound;
 use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ops::Bound::{Excluded, Included};
+use std::thread::LocalKey;
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 type IdStore = StableBTreeMap<u64, u64, Memory>;
@@ -17,10 +22,103 @@ type DividendStore = StableBTreeMap<u64, DividendDistribution, Memory>;
 type PortfolioStore = StableBTreeMap<Principal, Portfolio, Memory>;
 type ProposalStore = StableBTreeMap<u64, GovernanceProposal, Memory>;
 type MarketDataStore = StableBTreeMap<u64, MarketData, Memory>;
+type LotStore = StableBTreeMap<(Principal, u64, u64), TaxLot, Memory>; // (owner, property_id, lot_id)
+type HoldingStore = StableBTreeMap<(u64, Principal), u64, Memory>; // (property_id, owner) -> balance
+type ProposalThresholdStore = StableBTreeMap<u8, ProposalThresholds, Memory>; // keyed by proposal_type_key
+type BalanceCheckpointStore = StableBTreeMap<(u64, Principal, u64), u64, Memory>; // (property_id, owner, changed_at) -> balance
+type RealizedGainStore = StableBTreeMap<(Principal, u64, u64), RealizedGain, Memory>; // (owner, disposed_at, id)
+type CostBasisMethodStore = StableBTreeMap<Principal, CostBasisMethod, Memory>;
+type VoteStore = StableBTreeMap<(u64, Principal), Vote, Memory>; // (proposal_id, voter)
+type TokenLockStore = StableBTreeMap<(u64, Principal), u64, Memory>; // (property_id, owner) -> locked tokens
+type GovernanceConfigStore = StableBTreeMap<u8, GovernanceConfig, Memory>; // single entry at key 0
+type TradeStore = StableBTreeMap<(u64, u64, u64), Trade, Memory>; // (property_id, timestamp, trade_id)
+type CandleStore = StableBTreeMap<(u64, u8, u64), Candle, Memory>; // (property_id, interval, open_time)
+type FeeConfigStore = StableBTreeMap<u64, FeeConfig, Memory>; // 0 = platform default, otherwise property override
+type FeeLedgerStore = StableBTreeMap<u64, FeeEntry, Memory>;
+type FeeTotalStore = StableBTreeMap<(Principal, u8), Amount, Memory>; // (asset ledger, fee_source_key) -> collected
+type TreasuryWithdrawalStore = StableBTreeMap<u64, TreasuryWithdrawal, Memory>;
+type PlatformMetricStore = StableBTreeMap<u8, u64, Memory>; // running platform counters
+type PlatformSnapshotStore = StableBTreeMap<u64, PlatformSnapshot, Memory>; // keyed by day
+type AppraisalStore = StableBTreeMap<(u64, u64), Appraisal, Memory>; // (property_id, appraisal_id)
+type DelegationStore = StableBTreeMap<(Principal, u64), Delegation, Memory>; // (delegator, scope)
+type SettlementAssetStore = StableBTreeMap<Principal, SettlementAsset, Memory>; // keyed by ledger canister
+type CurrencyStore = StableBTreeMap<String, Currency, Memory>; // display currencies by code
+type ExchangeRateStore = StableBTreeMap<String, ExchangeRate, Memory>; // asset symbol or currency code
+type CashBalanceStore = StableBTreeMap<(Principal, Principal), CashBalance, Memory>; // (owner, asset ledger)
+type CashTransactionStore = StableBTreeMap<(Principal, u64), CashTransaction, Memory>; // (owner, transaction_id)
+type OfferingRoundStore = StableBTreeMap<(u64, u64), OfferingRound, Memory>; // (property_id, round_id)
+type SubscriptionStore = StableBTreeMap<(u64, Principal), Subscription, Memory>; // (round_id, investor)
+type OfferingAccessStore = StableBTreeMap<u64, OfferingAccess, Memory>; // keyed by property_id
+type AllowlistStore = StableBTreeMap<(u64, Principal), AllowlistEntry, Memory>; // (property_id, investor)
+type PrimaryPurchaseStore = StableBTreeMap<(u64, Principal), Amount, Memory>; // (property_id, investor) -> bought at issuance
+type RedemptionStore = StableBTreeMap<u64, Redemption, Memory>; // keyed by property_id
+type CorporateActionStore = StableBTreeMap<(u64, u64), CorporateAction, Memory>; // (property_id, action_id)
+type SchemaVersionStore = StableBTreeMap<u8, u32, Memory>; // single entry at key 0
+type TreasuryWithdrawnStore = StableBTreeMap<Principal, Amount, Memory>; // asset ledger -> withdrawn or in flight
+type AppraiserStore = StableBTreeMap<Principal, Appraiser, Memory>;
+type PendingWithdrawalStore = StableBTreeMap<u64, PendingWithdrawal, Memory>; // keyed by withdrawal id
+type PendingDepositStore = StableBTreeMap<u64, PendingDeposit, Memory>; // keyed by deposit id
+
+// Secondary indexes. Values are unit; the key carries everything needed for a range scan.
+type InvestmentUserIndex = StableBTreeMap<(Principal, u64, u64), (), Memory>; // (investor, property_id, investment_id)
+type OrderUserIndex = StableBTreeMap<(Principal, u64), (), Memory>; // (seller or buyer, order_id)
+type OrderPropertyIndex = StableBTreeMap<(u64, u8, u64), (), Memory>; // (property_id, status, order_id)
+type ProposalPropertyIndex = StableBTreeMap<(u64, u64), (), Memory>; // (property_id, proposal_id)
+type VoterIndex = StableBTreeMap<(Principal, u64), (), Memory>; // (voter, proposal_id)
+type DelegateIndex = StableBTreeMap<(Principal, u64, Principal), (), Memory>; // (delegate, scope, delegator)
+type DividendPropertyIndex = StableBTreeMap<(u64, u64), (), Memory>; // (property_id, distribution_id)
+type DividendRecipientIndex = StableBTreeMap<(Principal, u64), (), Memory>; // (investor, distribution_id)
+type PropertySortIndex = StableBTreeMap<(u8, u64, u64), (), Memory>; // (numeric field, key, property_id)
+type PropertyAttributeIndex = StableBTreeMap<(u8, u8, u64), (), Memory>; // (attribute, variant, property_id)
+type PropertyTermIndex = StableBTreeMap<TermKey, (), Memory>;
+type FeeEntryIndex = StableBTreeMap<(u64, u8, u64), (), Memory>; // (property_id or ANY_PROPERTY, source or ANY_FEE_SOURCE, entry_id)
+type TreasuryWithdrawalIndex = StableBTreeMap<(Principal, u64), (), Memory>; // (asset ledger, withdrawal_id)
+
+/// Key of the full-text indexes: a lowercased search term and the property it occurs in.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TermKey {
+    pub term: String,
+    pub property_id: u64,
+}
+
+pub const MAX_TERM_CHARS: usize = 32;
+
+impl Storable for TermKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(self.term.len() + 8);
+        bytes.extend_from_slice(self.term.as_bytes());
+        bytes.extend_from_slice(&self.property_id.to_be_bytes());
+        Cow::Owned(bytes)
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (term, id) = bytes.split_at(bytes.len() - 8);
+        TermKey {
+            term: String::from_utf8(term.to_vec()).unwrap(),
+            property_id: u64::from_be_bytes(id.try_into().unwrap()),
+        }
+    }
+    const BOUND: Bound = Bound::Bounded {
+        max_size: (MAX_TERM_CHARS * 4 + 8) as u32,
+        is_fixed_size: false,
+    };
+}
 
 // Implement Storable for all types
+impl Storable for Amount {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.minor_units().to_be_bytes().to_vec())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Amount::from_minor(u64::from_be_bytes(bytes.as_ref().try_into().unwrap()))
+    }
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 8,
+        is_fixed_size: true,
+    };
+}
+
 impl Storable for Property {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -30,7 +128,7 @@ impl Storable for Property {
 }
 
 impl Storable for Investment {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -40,7 +138,7 @@ impl Storable for Investment {
 }
 
 impl Storable for UserProfile {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -50,7 +148,7 @@ impl Storable for UserProfile {
 }
 
 impl Storable for TokenOrder {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -60,7 +158,7 @@ impl Storable for TokenOrder {
 }
 
 impl Storable for DividendDistribution {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -70,7 +168,7 @@ impl Storable for DividendDistribution {
 }
 
 impl Storable for Portfolio {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -80,7 +178,7 @@ impl Storable for Portfolio {
 }
 
 impl Storable for GovernanceProposal {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -90,7 +188,287 @@ impl Storable for GovernanceProposal {
 }
 
 impl Storable for MarketData {
-    fn to_bytes(&self) -> Cow<[u8]> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for TaxLot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for RealizedGain {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Vote {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for GovernanceConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Trade {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Candle {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for FeeConfig {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for FeeEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for TreasuryWithdrawal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for PlatformSnapshot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Appraisal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Appraiser {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Delegation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for ProposalThresholds {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for SettlementAsset {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Currency {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for ExchangeRate {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for CashBalance {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for CashTransaction {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for PendingWithdrawal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for PendingDeposit {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for OfferingRound {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Subscription {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for OfferingAccess {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for AllowlistEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for Redemption {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for CorporateAction {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for CostBasisMethod {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
         Cow::Owned(Encode!(self).unwrap())
     }
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
@@ -139,6 +517,242 @@ thread_local! {
     pub static MARKET_DATA_STORAGE: RefCell<MarketDataStore> = RefCell::new(
         MarketDataStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))))
     );
+
+    static INVESTMENT_USER_INDEX: RefCell<InvestmentUserIndex> = RefCell::new(
+        InvestmentUserIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))))
+    );
+
+    static ORDER_USER_INDEX: RefCell<OrderUserIndex> = RefCell::new(
+        OrderUserIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))))
+    );
+
+    static ORDER_PROPERTY_INDEX: RefCell<OrderPropertyIndex> = RefCell::new(
+        OrderPropertyIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))))
+    );
+
+    static PROPOSAL_PROPERTY_INDEX: RefCell<ProposalPropertyIndex> = RefCell::new(
+        ProposalPropertyIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))))
+    );
+
+    static PROPERTY_SORT_INDEX: RefCell<PropertySortIndex> = RefCell::new(
+        PropertySortIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))))
+    );
+
+    static PROPERTY_ATTRIBUTE_INDEX: RefCell<PropertyAttributeIndex> = RefCell::new(
+        PropertyAttributeIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))))
+    );
+
+    static PROPERTY_LOCATION_INDEX: RefCell<PropertyTermIndex> = RefCell::new(
+        PropertyTermIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))))
+    );
+
+    static PROPERTY_TEXT_INDEX: RefCell<PropertyTermIndex> = RefCell::new(
+        PropertyTermIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))))
+    );
+
+    pub static LOT_STORAGE: RefCell<LotStore> = RefCell::new(
+        LotStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))))
+    );
+
+    pub static HOLDING_STORAGE: RefCell<HoldingStore> = RefCell::new(
+        HoldingStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))))
+    );
+
+    pub static REALIZED_GAIN_STORAGE: RefCell<RealizedGainStore> = RefCell::new(
+        RealizedGainStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))))
+    );
+
+    pub static COST_BASIS_METHOD_STORAGE: RefCell<CostBasisMethodStore> = RefCell::new(
+        CostBasisMethodStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))))
+    );
+
+    pub static VOTE_STORAGE: RefCell<VoteStore> = RefCell::new(
+        VoteStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))))
+    );
+
+    static VOTER_INDEX: RefCell<VoterIndex> = RefCell::new(
+        VoterIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))))
+    );
+
+    pub static BALANCE_CHECKPOINT_STORAGE: RefCell<BalanceCheckpointStore> = RefCell::new(
+        BalanceCheckpointStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23))))
+    );
+
+    pub static PROPOSAL_THRESHOLD_STORAGE: RefCell<ProposalThresholdStore> = RefCell::new(
+        ProposalThresholdStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))))
+    );
+
+    static DIVIDEND_PROPERTY_INDEX: RefCell<DividendPropertyIndex> = RefCell::new(
+        DividendPropertyIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))))
+    );
+
+    static DIVIDEND_RECIPIENT_INDEX: RefCell<DividendRecipientIndex> = RefCell::new(
+        DividendRecipientIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))))
+    );
+
+    pub static DELEGATION_STORAGE: RefCell<DelegationStore> = RefCell::new(
+        DelegationStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))))
+    );
+
+    static DELEGATE_INDEX: RefCell<DelegateIndex> = RefCell::new(
+        DelegateIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))))
+    );
+
+    pub static TOKEN_LOCK_STORAGE: RefCell<TokenLockStore> = RefCell::new(
+        TokenLockStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))))
+    );
+
+    pub static GOVERNANCE_CONFIG_STORAGE: RefCell<GovernanceConfigStore> = RefCell::new(
+        GovernanceConfigStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))))
+    );
+
+    pub static TRADE_STORAGE: RefCell<TradeStore> = RefCell::new(
+        TradeStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31))))
+    );
+
+    pub static CANDLE_STORAGE: RefCell<CandleStore> = RefCell::new(
+        CandleStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32))))
+    );
+
+    pub static FEE_CONFIG_STORAGE: RefCell<FeeConfigStore> = RefCell::new(
+        FeeConfigStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33))))
+    );
+
+    pub static FEE_LEDGER_STORAGE: RefCell<FeeLedgerStore> = RefCell::new(
+        FeeLedgerStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34))))
+    );
+
+    pub static FEE_TOTAL_STORAGE: RefCell<FeeTotalStore> = RefCell::new(
+        FeeTotalStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35))))
+    );
+
+    pub static TREASURY_WITHDRAWAL_STORAGE: RefCell<TreasuryWithdrawalStore> = RefCell::new(
+        TreasuryWithdrawalStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36))))
+    );
+
+    pub static PLATFORM_METRIC_STORAGE: RefCell<PlatformMetricStore> = RefCell::new(
+        PlatformMetricStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37))))
+    );
+
+    pub static PLATFORM_SNAPSHOT_STORAGE: RefCell<PlatformSnapshotStore> = RefCell::new(
+        PlatformSnapshotStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38))))
+    );
+
+    pub static APPRAISAL_STORAGE: RefCell<AppraisalStore> = RefCell::new(
+        AppraisalStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39))))
+    );
+
+    pub static SETTLEMENT_ASSET_STORAGE: RefCell<SettlementAssetStore> = RefCell::new(
+        SettlementAssetStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40))))
+    );
+
+    pub static CURRENCY_STORAGE: RefCell<CurrencyStore> = RefCell::new(
+        CurrencyStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41))))
+    );
+
+    pub static EXCHANGE_RATE_STORAGE: RefCell<ExchangeRateStore> = RefCell::new(
+        ExchangeRateStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42))))
+    );
+
+    pub static CASH_BALANCE_STORAGE: RefCell<CashBalanceStore> = RefCell::new(
+        CashBalanceStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43))))
+    );
+
+    pub static CASH_TRANSACTION_STORAGE: RefCell<CashTransactionStore> = RefCell::new(
+        CashTransactionStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44))))
+    );
+
+    pub static OFFERING_ROUND_STORAGE: RefCell<OfferingRoundStore> = RefCell::new(
+        OfferingRoundStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45))))
+    );
+
+    pub static SUBSCRIPTION_STORAGE: RefCell<SubscriptionStore> = RefCell::new(
+        SubscriptionStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46))))
+    );
+
+    pub static OFFERING_ACCESS_STORAGE: RefCell<OfferingAccessStore> = RefCell::new(
+        OfferingAccessStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47))))
+    );
+
+    pub static ALLOWLIST_STORAGE: RefCell<AllowlistStore> = RefCell::new(
+        AllowlistStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48))))
+    );
+
+    pub static PRIMARY_PURCHASE_STORAGE: RefCell<PrimaryPurchaseStore> = RefCell::new(
+        PrimaryPurchaseStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49))))
+    );
+
+    pub static REDEMPTION_STORAGE: RefCell<RedemptionStore> = RefCell::new(
+        RedemptionStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50))))
+    );
+
+    pub static CORPORATE_ACTION_STORAGE: RefCell<CorporateActionStore> = RefCell::new(
+        CorporateActionStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51))))
+    );
+
+    static SCHEMA_VERSION_STORAGE: RefCell<SchemaVersionStore> = RefCell::new(
+        SchemaVersionStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52))))
+    );
+
+    static FEE_ENTRY_INDEX: RefCell<FeeEntryIndex> = RefCell::new(
+        FeeEntryIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53))))
+    );
+
+    static TREASURY_WITHDRAWAL_INDEX: RefCell<TreasuryWithdrawalIndex> = RefCell::new(
+        TreasuryWithdrawalIndex::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54))))
+    );
+
+    static TREASURY_WITHDRAWN_STORAGE: RefCell<TreasuryWithdrawnStore> = RefCell::new(
+        TreasuryWithdrawnStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55))))
+    );
+
+    pub static APPRAISER_STORAGE: RefCell<AppraiserStore> = RefCell::new(
+        AppraiserStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56))))
+    );
+
+    pub static PENDING_WITHDRAWAL_STORAGE: RefCell<PendingWithdrawalStore> = RefCell::new(
+        PendingWithdrawalStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(57))))
+    );
+
+    pub static PENDING_DEPOSIT_STORAGE: RefCell<PendingDepositStore> = RefCell::new(
+        PendingDepositStore::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58))))
+    );
+}
+
+/// Layout of the stored records and indexes. Bump it whenever either changes so
+/// the next upgrade migrates and reindexes; upgrades that leave it alone skip
+/// that work entirely.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// Rewrites the records of the store on `memory_id` that no longer decode as `T`,
+/// passing their raw bytes to `upgrade`; `None` drops the record. Works on the
+/// raw bytes so an old layout can't trap the typed store, which therefore must not
+/// have been touched yet in this message.
+pub fn migrate_store<K, T>(memory_id: u8, mut upgrade: impl FnMut(&[u8]) -> Option<T>)
+where
+    K: Storable + Ord + Clone,
+    T: CandidType + DeserializeOwned,
+{
+    let memory = MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(memory_id)));
+    let mut raw: StableBTreeMap<K, Vec<u8>, Memory> = StableBTreeMap::init(memory);
+    let stale: Vec<(K, Vec<u8>)> = raw.iter()
+        .filter(|(_, bytes)| Decode!(bytes.as_slice(), T).is_err())
+        .collect();
+    for (key, bytes) in stale {
+        match upgrade(&bytes) {
+            Some(record) => raw.insert(key, Encode!(&record).unwrap()),
+            None => raw.remove(&key),
+        };
+    }
+}
+
+/// The layout the stored data was written in; 0 for data that predates versioning.
+pub fn schema_version() -> u32 {
+    SCHEMA_VERSION_STORAGE.with(|storage| storage.borrow().get(&0)).unwrap_or(0)
+}
+
+pub fn set_schema_version(version: u32) {
+    SCHEMA_VERSION_STORAGE.with(|storage| storage.borrow_mut().insert(0, version));
 }
 
 pub fn get_next_id() -> u64 {
@@ -149,3 +763,664 @@ pub fn get_next_id() -> u64 {
         next_value
     })
 }
+
+fn order_status_key(status: &OrderStatus) -> u8 {
+    match status {
+        OrderStatus::Active => 0,
+        OrderStatus::Filled => 1,
+        OrderStatus::Cancelled => 2,
+        OrderStatus::Expired => 3,
+    }
+}
+
+// Numeric fields kept in PROPERTY_SORT_INDEX. The first four double as sort orders;
+// all of them back range filters in property search.
+pub const CREATED_AT_FIELD: u8 = 0;
+pub const PRICE_FIELD: u8 = 1;
+pub const YIELD_FIELD: u8 = 2;
+pub const VALUE_FIELD: u8 = 3;
+pub const APPRECIATION_FIELD: u8 = 4;
+pub const AVAILABLE_TOKENS_FIELD: u8 = 5;
+
+const TYPE_ATTRIBUTE: u8 = 0;
+const STATUS_ATTRIBUTE: u8 = 1;
+
+fn sort_field_key(field: &SortField) -> u8 {
+    match field {
+        SortField::CreatedAt => CREATED_AT_FIELD,
+        SortField::Price => PRICE_FIELD,
+        SortField::Yield => YIELD_FIELD,
+        SortField::Value => VALUE_FIELD,
+    }
+}
+
+fn property_numeric_keys(property: &Property) -> [(u8, u64); 6] {
+    [
+        (CREATED_AT_FIELD, property.created_at),
+        (PRICE_FIELD, property.price_per_token.minor_units()),
+        (YIELD_FIELD, yield_sort_key(property.rental_yield)),
+        (VALUE_FIELD, property.total_value.minor_units()),
+        (APPRECIATION_FIELD, signed_rate_key(property.appreciation_rate)),
+        (AVAILABLE_TOKENS_FIELD, property.available_tokens),
+    ]
+}
+
+fn property_type_key(property_type: &PropertyType) -> u8 {
+    match property_type {
+        PropertyType::Residential => 0,
+        PropertyType::Commercial => 1,
+        PropertyType::Industrial => 2,
+        PropertyType::Land => 3,
+        PropertyType::Trophy => 4,
+    }
+}
+
+fn property_status_key(status: &PropertyStatus) -> u8 {
+    match status {
+        PropertyStatus::Active => 0,
+        PropertyStatus::Sold => 1,
+        PropertyStatus::Pending => 2,
+        PropertyStatus::Inactive => 3,
+        PropertyStatus::UnderMaintenance => 4,
+    }
+}
+
+pub fn proposal_type_key(proposal_type: &ProposalType) -> u8 {
+    match proposal_type {
+        ProposalType::PropertyMaintenance => 0,
+        ProposalType::PropertySale => 1,
+        ProposalType::ManagementChange => 2,
+        ProposalType::DividendDistribution => 3,
+        ProposalType::Other => 4,
+        ProposalType::CorporateAction => 5,
+    }
+}
+
+fn property_attribute_keys(property: &Property) -> [(u8, u8); 2] {
+    [
+        (TYPE_ATTRIBUTE, property_type_key(&property.property_type)),
+        (STATUS_ATTRIBUTE, property_status_key(&property.status)),
+    ]
+}
+
+fn property_location_terms(property: &Property) -> BTreeSet<String> {
+    tokenize(&property.location)
+}
+
+fn property_text_terms(property: &Property) -> BTreeSet<String> {
+    let mut terms = tokenize(&property.title);
+    terms.extend(tokenize(&property.description));
+    for highlight in property.property_highlights.iter() {
+        terms.extend(tokenize(highlight));
+    }
+    terms
+}
+
+fn reindex_terms(index: &'static LocalKey<RefCell<PropertyTermIndex>>, property_id: u64, old: BTreeSet<String>, new: BTreeSet<String>) {
+    index.with(|index| {
+        let mut index = index.borrow_mut();
+        for term in old.difference(&new) {
+            index.remove(&TermKey { term: term.clone(), property_id });
+        }
+        for term in new.difference(&old) {
+            index.insert(TermKey { term: term.clone(), property_id }, ());
+        }
+    });
+}
+
+// Writes below go through these helpers so the secondary indexes never drift
+// from the primary stores.
+
+pub fn insert_property(property: Property) {
+    let previous = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(property.id, property.clone())
+    });
+
+    PROPERTY_SORT_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous.as_ref() {
+            for (field, key) in property_numeric_keys(previous) {
+                index.remove(&(field, key, property.id));
+            }
+        }
+        for (field, key) in property_numeric_keys(&property) {
+            index.insert((field, key, property.id), ());
+        }
+    });
+
+    PROPERTY_ATTRIBUTE_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous.as_ref() {
+            for (attribute, value) in property_attribute_keys(previous) {
+                index.remove(&(attribute, value, property.id));
+            }
+        }
+        for (attribute, value) in property_attribute_keys(&property) {
+            index.insert((attribute, value, property.id), ());
+        }
+    });
+
+    let (old_location, old_text) = previous.as_ref()
+        .map(|previous| (property_location_terms(previous), property_text_terms(previous)))
+        .unwrap_or_default();
+    reindex_terms(&PROPERTY_LOCATION_INDEX, property.id, old_location, property_location_terms(&property));
+    reindex_terms(&PROPERTY_TEXT_INDEX, property.id, old_text, property_text_terms(&property));
+}
+
+pub fn insert_investment(investment: Investment) {
+    INVESTMENT_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(investment.id, investment.clone())
+    });
+    INVESTMENT_USER_INDEX.with(|index| {
+        index.borrow_mut().insert((investment.investor, investment.property_id, investment.id), ())
+    });
+}
+
+pub fn insert_order(order: TokenOrder) {
+    let previous = ORDER_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(order.id, order.clone())
+    });
+
+    ORDER_USER_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(Some(buyer)) = previous.as_ref().map(|p| p.buyer) {
+            index.remove(&(buyer, order.id));
+        }
+        index.insert((order.seller, order.id), ());
+        if let Some(buyer) = order.buyer {
+            index.insert((buyer, order.id), ());
+        }
+    });
+
+    ORDER_PROPERTY_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        if let Some(previous) = previous.as_ref() {
+            index.remove(&(previous.property_id, order_status_key(&previous.status), order.id));
+        }
+        index.insert((order.property_id, order_status_key(&order.status), order.id), ());
+    });
+}
+
+pub fn insert_proposal(proposal: GovernanceProposal) {
+    PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(proposal.id, proposal.clone())
+    });
+    PROPOSAL_PROPERTY_INDEX.with(|index| {
+        index.borrow_mut().insert((proposal.property_id, proposal.id), ())
+    });
+}
+
+/// Delegation scope covering every property; property ids start at 1.
+pub const GLOBAL_DELEGATION_SCOPE: u64 = 0;
+
+pub fn delegation_scope(property_id: Option<u64>) -> u64 {
+    property_id.unwrap_or(GLOBAL_DELEGATION_SCOPE)
+}
+
+/// Stores the delegation, replacing any previous one the delegator had for the same scope.
+pub fn insert_delegation(delegation: Delegation) {
+    let scope = delegation_scope(delegation.property_id);
+    remove_delegation(delegation.delegator, scope);
+    DELEGATION_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((delegation.delegator, scope), delegation.clone())
+    });
+    DELEGATE_INDEX.with(|index| {
+        index.borrow_mut().insert((delegation.delegate, scope, delegation.delegator), ())
+    });
+}
+
+pub fn remove_delegation(delegator: Principal, scope: u64) -> Option<Delegation> {
+    let removed = DELEGATION_STORAGE.with(|storage| storage.borrow_mut().remove(&(delegator, scope)));
+    if let Some(delegation) = removed.as_ref() {
+        DELEGATE_INDEX.with(|index| {
+            index.borrow_mut().remove(&(delegation.delegate, scope, delegator))
+        });
+    }
+    removed
+}
+
+pub fn get_delegation(delegator: Principal, scope: u64) -> Option<Delegation> {
+    DELEGATION_STORAGE.with(|storage| storage.borrow().get(&(delegator, scope)))
+}
+
+pub fn delegations_by_delegator(delegator: Principal) -> Vec<Delegation> {
+    DELEGATION_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((delegator, 0)..=(delegator, u64::MAX))
+            .map(|(_, delegation)| delegation)
+            .collect()
+    })
+}
+
+/// Principals that delegated to `delegate` within exactly this scope.
+pub fn delegators_of(delegate: Principal, scope: u64) -> Vec<Principal> {
+    DELEGATE_INDEX.with(|index| {
+        index.borrow()
+            .range((delegate, scope, Principal::from_slice(&[]))..)
+            .take_while(|((d, s, _), _)| *d == delegate && *s == scope)
+            .map(|((_, _, delegator), _)| delegator)
+            .collect()
+    })
+}
+
+pub fn insert_distribution(distribution: DividendDistribution) {
+    DIVIDEND_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(distribution.id, distribution.clone())
+    });
+    DIVIDEND_PROPERTY_INDEX.with(|index| {
+        index.borrow_mut().insert((distribution.property_id, distribution.id), ())
+    });
+    DIVIDEND_RECIPIENT_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for recipient in distribution.recipients.iter() {
+            index.insert((recipient.investor, distribution.id), ());
+        }
+    });
+}
+
+pub fn distributions_by_property(property_id: u64) -> Vec<DividendDistribution> {
+    let ids: Vec<u64> = DIVIDEND_PROPERTY_INDEX.with(|index| {
+        index.borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    load_distributions(ids)
+}
+
+pub fn distributions_by_recipient(investor: Principal) -> Vec<DividendDistribution> {
+    let ids: Vec<u64> = DIVIDEND_RECIPIENT_INDEX.with(|index| {
+        index.borrow()
+            .range((investor, 0)..=(investor, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    load_distributions(ids)
+}
+
+fn load_distributions(ids: Vec<u64>) -> Vec<DividendDistribution> {
+    DIVIDEND_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter().filter_map(|id| storage.get(&id)).collect()
+    })
+}
+
+pub fn insert_vote(vote: Vote) {
+    VOTE_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((vote.proposal_id, vote.voter), vote.clone())
+    });
+    VOTER_INDEX.with(|index| {
+        index.borrow_mut().insert((vote.voter, vote.proposal_id), ())
+    });
+}
+
+pub fn votes_by_proposal(proposal_id: u64) -> Vec<Vote> {
+    VOTE_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((proposal_id, Principal::from_slice(&[]))..)
+            .take_while(|((id, _), _)| *id == proposal_id)
+            .map(|(_, vote)| vote)
+            .collect()
+    })
+}
+
+pub fn votes_by_voter(voter: Principal) -> Vec<Vote> {
+    let proposal_ids: Vec<u64> = VOTER_INDEX.with(|index| {
+        index.borrow()
+            .range((voter, 0)..=(voter, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    VOTE_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        proposal_ids.into_iter().filter_map(|id| storage.get(&(id, voter))).collect()
+    })
+}
+
+pub fn investments_by_user(user: Principal) -> Vec<Investment> {
+    let ids: Vec<u64> = INVESTMENT_USER_INDEX.with(|index| {
+        index.borrow()
+            .range((user, 0, 0)..=(user, u64::MAX, u64::MAX))
+            .map(|((_, _, id), _)| id)
+            .collect()
+    });
+    load_investments(ids)
+}
+
+pub fn orders_by_user(user: Principal) -> Vec<TokenOrder> {
+    let ids: Vec<u64> = ORDER_USER_INDEX.with(|index| {
+        index.borrow()
+            .range((user, 0)..=(user, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    load_orders(ids)
+}
+
+pub fn orders_by_property_and_status(property_id: u64, status: &OrderStatus) -> Vec<TokenOrder> {
+    let status = order_status_key(status);
+    let ids: Vec<u64> = ORDER_PROPERTY_INDEX.with(|index| {
+        index.borrow()
+            .range((property_id, status, 0)..=(property_id, status, u64::MAX))
+            .map(|((_, _, id), _)| id)
+            .collect()
+    });
+    load_orders(ids)
+}
+
+pub fn proposals_by_property(property_id: u64) -> Vec<GovernanceProposal> {
+    let ids: Vec<u64> = PROPOSAL_PROPERTY_INDEX.with(|index| {
+        index.borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|((_, id), _)| id)
+            .collect()
+    });
+    PROPOSAL_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter().filter_map(|id| storage.get(&id)).collect()
+    })
+}
+
+/// Walks the property sort index from the cursor, so a page costs `limit` reads
+/// rather than a scan of every listing. With `matches`, index entries for other
+/// properties are skipped; only the page's own records are loaded either way.
+pub fn properties_page(request: &PageRequest, matches: Option<&BTreeSet<u64>>) -> Page<Property> {
+    let field = sort_field_key(&request.sort_by.clone().unwrap_or(SortField::CreatedAt));
+    let cursor = request.cursor.as_ref().map(|cursor| (field, cursor.sort_key, cursor.id));
+    let limit = page_limit(request);
+    let matching = |(_, _, id): &(u8, u64, u64)| matches.is_none_or(|matches| matches.contains(id));
+
+    let keys: Vec<(u8, u64, u64)> = PROPERTY_SORT_INDEX.with(|index| {
+        let index = index.borrow();
+        if is_descending(request) {
+            let upper = cursor.map_or(Included((field, u64::MAX, u64::MAX)), Excluded);
+            index.range((Included((field, 0, 0)), upper))
+                .rev()
+                .map(|(key, _)| key)
+                .filter(matching)
+                .take(limit + 1)
+                .collect()
+        } else {
+            let lower = cursor.map_or(Included((field, 0, 0)), Excluded);
+            index.range((lower, Included((field, u64::MAX, u64::MAX))))
+                .map(|(key, _)| key)
+                .filter(matching)
+                .take(limit + 1)
+                .collect()
+        }
+    });
+
+    let next_cursor = if keys.len() > limit {
+        keys.get(limit - 1).map(|(_, sort_key, id)| PageCursor { sort_key: *sort_key, id: *id })
+    } else {
+        None
+    };
+
+    let items = PROPERTY_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        keys.iter().take(limit).filter_map(|(_, _, id)| storage.get(id)).collect()
+    });
+
+    Page { items, next_cursor }
+}
+
+pub fn property_ids_in_range(field: u8, min: u64, max: u64) -> BTreeSet<u64> {
+    PROPERTY_SORT_INDEX.with(|index| {
+        index.borrow()
+            .range((field, min, 0)..=(field, max, u64::MAX))
+            .map(|((_, _, id), _)| id)
+            .collect()
+    })
+}
+
+pub fn property_ids_by_type(property_type: &PropertyType) -> BTreeSet<u64> {
+    property_ids_with_attribute(TYPE_ATTRIBUTE, property_type_key(property_type))
+}
+
+pub fn property_ids_by_status(status: &PropertyStatus) -> BTreeSet<u64> {
+    property_ids_with_attribute(STATUS_ATTRIBUTE, property_status_key(status))
+}
+
+fn property_ids_with_attribute(attribute: u8, value: u8) -> BTreeSet<u64> {
+    PROPERTY_ATTRIBUTE_INDEX.with(|index| {
+        index.borrow()
+            .range((attribute, value, 0)..=(attribute, value, u64::MAX))
+            .map(|((_, _, id), _)| id)
+            .collect()
+    })
+}
+
+/// Properties whose location has a word starting with `prefix` (e.g. "calif" matches "California").
+pub fn property_ids_by_location_term(prefix: &str) -> BTreeSet<u64> {
+    property_ids_with_term_prefix(&PROPERTY_LOCATION_INDEX, prefix)
+}
+
+/// Properties whose title, description or highlights have a word starting with `prefix`.
+pub fn property_ids_by_text_term(prefix: &str) -> BTreeSet<u64> {
+    property_ids_with_term_prefix(&PROPERTY_TEXT_INDEX, prefix)
+}
+
+fn property_ids_with_term_prefix(index: &'static LocalKey<RefCell<PropertyTermIndex>>, prefix: &str) -> BTreeSet<u64> {
+    index.with(|index| {
+        index.borrow()
+            .range(TermKey { term: prefix.to_string(), property_id: 0 }..)
+            .take_while(|(key, _)| key.term.starts_with(prefix))
+            .map(|(key, _)| key.property_id)
+            .collect()
+    })
+}
+
+fn load_investments(ids: Vec<u64>) -> Vec<Investment> {
+    INVESTMENT_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter().filter_map(|id| storage.get(&id)).collect()
+    })
+}
+
+fn load_orders(ids: Vec<u64>) -> Vec<TokenOrder> {
+    ORDER_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter().filter_map(|id| storage.get(&id)).collect()
+    })
+}
+
+/// Wildcards in `FeeEntryIndex`. Every entry is indexed under its property and
+/// source and under the wildcard for each, so any combination of filters is one
+/// range walk.
+const ANY_PROPERTY: u64 = u64::MAX;
+const ANY_FEE_SOURCE: u8 = u8::MAX;
+
+pub fn insert_fee_entry(entry: FeeEntry) {
+    let source = fee_source_key(&entry.source);
+    FEE_ENTRY_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for property in [entry.property_id, ANY_PROPERTY] {
+            for source in [source, ANY_FEE_SOURCE] {
+                index.insert((property, source, entry.id), ());
+            }
+        }
+    });
+    FEE_LEDGER_STORAGE.with(|storage| storage.borrow_mut().insert(entry.id, entry));
+}
+
+/// Ledger entries for a property and/or source. In creation order (the default)
+/// the index is walked from the cursor, so a page costs `limit` reads; other sort
+/// fields sort the narrowed set in memory.
+pub fn fee_entries_page(property_id: Option<u64>, source: Option<&FeeSource>, request: &PageRequest) -> Page<FeeEntry> {
+    let property = property_id.unwrap_or(ANY_PROPERTY);
+    let source = source.map_or(ANY_FEE_SOURCE, fee_source_key);
+    let chronological = matches!(request.sort_by, None | Some(SortField::CreatedAt));
+
+    let ids: Vec<u64> = FEE_ENTRY_INDEX.with(|index| {
+        let index = index.borrow();
+        let ids = index.range((property, source, 0)..=(property, source, u64::MAX)).map(|((_, _, id), _)| id);
+        if chronological {
+            ids_after(ids, request)
+        } else {
+            ids.collect()
+        }
+    });
+    let entries = FEE_LEDGER_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter().filter_map(|id| storage.get(&id)).collect()
+    });
+
+    if chronological {
+        chronological_page(entries, request)
+    } else {
+        paginate(entries, request)
+    }
+}
+
+/// Records a treasury withdrawal, or updates one already recorded, and keeps the
+/// asset's withdrawn total in step.
+pub fn insert_treasury_withdrawal(withdrawal: TreasuryWithdrawal) {
+    let previous = TREASURY_WITHDRAWAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(withdrawal.id, withdrawal.clone())
+    });
+    let total = treasury_withdrawn(withdrawal.asset)
+        .saturating_sub(previous.map_or(Amount::ZERO, |previous| previous.amount))
+        .checked_add(withdrawal.amount)
+        .expect("Treasury withdrawn total overflow");
+    TREASURY_WITHDRAWN_STORAGE.with(|storage| storage.borrow_mut().insert(withdrawal.asset, total));
+    TREASURY_WITHDRAWAL_INDEX.with(|index| index.borrow_mut().insert((withdrawal.asset, withdrawal.id), ()));
+}
+
+pub fn remove_treasury_withdrawal(id: u64) -> Option<TreasuryWithdrawal> {
+    let removed = TREASURY_WITHDRAWAL_STORAGE.with(|storage| storage.borrow_mut().remove(&id));
+    if let Some(withdrawal) = removed.as_ref() {
+        let total = treasury_withdrawn(withdrawal.asset).saturating_sub(withdrawal.amount);
+        TREASURY_WITHDRAWN_STORAGE.with(|storage| storage.borrow_mut().insert(withdrawal.asset, total));
+        TREASURY_WITHDRAWAL_INDEX.with(|index| index.borrow_mut().remove(&(withdrawal.asset, id)));
+    }
+    removed
+}
+
+/// Withdrawn from the asset's treasury, including transfers still in flight.
+pub fn treasury_withdrawn(asset: Principal) -> Amount {
+    TREASURY_WITHDRAWN_STORAGE.with(|storage| storage.borrow().get(&asset)).unwrap_or(Amount::ZERO)
+}
+
+/// Treasury withdrawals, optionally of one asset, paged the same way as the fee ledger.
+pub fn treasury_withdrawals_page(asset: Option<Principal>, request: &PageRequest) -> Page<TreasuryWithdrawal> {
+    let chronological = matches!(request.sort_by, None | Some(SortField::CreatedAt));
+
+    let ids: Vec<u64> = match asset {
+        Some(asset) => TREASURY_WITHDRAWAL_INDEX.with(|index| {
+            let index = index.borrow();
+            let ids = index.range((asset, 0)..=(asset, u64::MAX)).map(|((_, id), _)| id);
+            if chronological {
+                ids_after(ids, request)
+            } else {
+                ids.collect()
+            }
+        }),
+        None => TREASURY_WITHDRAWAL_STORAGE.with(|storage| {
+            let storage = storage.borrow();
+            let ids = storage.iter().map(|(id, _)| id);
+            if chronological {
+                ids_after(ids, request)
+            } else {
+                ids.collect()
+            }
+        }),
+    };
+    let withdrawals = TREASURY_WITHDRAWAL_STORAGE.with(|storage| {
+        let storage = storage.borrow();
+        ids.into_iter().filter_map(|id| storage.get(&id)).collect()
+    });
+
+    if chronological {
+        chronological_page(withdrawals, request)
+    } else {
+        paginate(withdrawals, request)
+    }
+}
+
+/// Up to `limit + 1` ids past the cursor from an ascending id walk, in the
+/// requested direction. Ids are allocated in creation order, so this is also
+/// chronological order.
+fn ids_after(ids: impl DoubleEndedIterator<Item = u64>, request: &PageRequest) -> Vec<u64> {
+    let after = request.cursor.as_ref().map(|cursor| cursor.id);
+    let limit = page_limit(request) + 1;
+    if is_descending(request) {
+        ids.rev().filter(|id| after.is_none_or(|after| *id < after)).take(limit).collect()
+    } else {
+        ids.filter(|id| after.is_none_or(|after| *id > after)).take(limit).collect()
+    }
+}
+
+/// Turns the `limit + 1` records read by `ids_after` into a page.
+fn chronological_page<T: Paginated>(mut items: Vec<T>, request: &PageRequest) -> Page<T> {
+    let limit = page_limit(request);
+    let next_cursor = if items.len() > limit {
+        items.truncate(limit);
+        items.last().map(|item| PageCursor { sort_key: item.sort_key(&SortField::CreatedAt), id: item.id() })
+    } else {
+        None
+    };
+    Page { items, next_cursor }
+}
+
+pub fn fee_source_key(source: &FeeSource) -> u8 {
+    match source {
+        FeeSource::Issuance => 0,
+        FeeSource::Maker => 1,
+        FeeSource::Taker => 2,
+        FeeSource::DividendManagement => 3,
+        FeeSource::Listing => 4,
+    }
+}
+
+/// Rebuilds every secondary index from the primary stores. The write helpers keep
+/// the indexes current, so this only runs when `SCHEMA_VERSION` moves, to backfill
+/// data written before an index existed. Inserts are idempotent.
+pub fn rebuild_indexes() {
+    let properties: Vec<Property> = PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, property)| property).collect()
+    });
+    properties.into_iter().for_each(insert_property);
+
+    let investments: Vec<Investment> = INVESTMENT_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, investment)| investment).collect()
+    });
+    investments.into_iter().for_each(insert_investment);
+
+    let orders: Vec<TokenOrder> = ORDER_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, order)| order).collect()
+    });
+    orders.into_iter().for_each(insert_order);
+
+    let proposals: Vec<GovernanceProposal> = PROPOSAL_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, proposal)| proposal).collect()
+    });
+    proposals.into_iter().for_each(insert_proposal);
+
+    let distributions: Vec<DividendDistribution> = DIVIDEND_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, distribution)| distribution).collect()
+    });
+    distributions.into_iter().for_each(insert_distribution);
+
+    let delegations: Vec<Delegation> = DELEGATION_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, delegation)| delegation).collect()
+    });
+    delegations.into_iter().for_each(insert_delegation);
+
+    let fee_entries: Vec<FeeEntry> = FEE_LEDGER_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, entry)| entry).collect()
+    });
+    fee_entries.into_iter().for_each(insert_fee_entry);
+
+    // The withdrawn totals are running sums, so they're recounted rather than re-added.
+    TREASURY_WITHDRAWN_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let assets: Vec<Principal> = storage.iter().map(|(asset, _)| asset).collect();
+        for asset in assets {
+            storage.remove(&asset);
+        }
+    });
+    let withdrawals: Vec<TreasuryWithdrawal> = TREASURY_WITHDRAWAL_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, withdrawal)| withdrawal).collect()
+    });
+    withdrawals.into_iter().for_each(insert_treasury_withdrawal);
+}
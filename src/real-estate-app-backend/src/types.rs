@@ -1,29 +1,37 @@
 use candid::{CandidType, Deserialize, Principal};
 use serde::Serialize;
 
+pub use crate::money::{Amount, Currency, Money, Rate, Rounding};
+
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub struct Property {
     pub id: u64,
     pub title: String,
     pub description: String,
     pub location: String,
-    pub total_value: u64,
+    pub total_value: Amount,
     pub total_tokens: u64,
     pub available_tokens: u64,
-    pub price_per_token: u64,
+    pub price_per_token: Amount,
     pub owner: Principal,
+    pub manager: Principal,
     pub created_at: u64,
     pub updated_at: u64,
     pub property_type: PropertyType,
     pub status: PropertyStatus,
     pub images: Vec<String>,
     pub documents: Vec<String>,
-    pub rental_yield: f64,
-    pub appreciation_rate: f64,
+    pub rental_yield: Rate,
+    /// Fitted from the appraisal history in floating point and rounded once to
+    /// basis points, so unlike the other figures it isn't exact fixed-point; see
+    /// `Rate::from_growth_factor`.
+    pub appreciation_rate: Rate,
     pub property_highlights: Vec<String>,
     pub legal_structure: String,
     pub valuation_date: u64,
     pub next_dividend_date: u64,
+    /// Ledger of the asset the property is priced in and pays out in.
+    pub settlement_asset: Principal,
 }
 
 #[derive(CandidType, Clone, Serialize, Deserialize)]
@@ -51,12 +59,21 @@ pub struct TokenOrder {
     pub seller: Principal,
     pub buyer: Option<Principal>,
     pub token_amount: u64,
-    pub price_per_token: u64,
-    pub total_price: u64,
+    pub price_per_token: Amount,
+    pub total_price: Amount,
     pub order_type: OrderType,
     pub status: OrderStatus,
     pub created_at: u64,
     pub expires_at: u64,
+    /// Fixed when the order is placed. A buy order escrows `total_price` plus this
+    /// fee; a sell order escrows its tokens.
+    pub maker_fee: Amount,
+    pub taker_fee: Amount,
+    /// Ledger of the asset prices and fees are quoted in.
+    pub quote_asset: Principal,
+    /// When the order was filled. The seller's gain and the buyer's lot are dated
+    /// to this, not to `created_at`.
+    pub filled_at: Option<u64>,
 }
 
 #[derive(CandidType, Clone, Serialize, Deserialize)]
@@ -77,18 +94,27 @@ pub enum OrderStatus {
 pub struct DividendDistribution {
     pub id: u64,
     pub property_id: u64,
-    pub total_amount: u64,
-    pub per_token_amount: u64,
+    pub kind: DistributionKind,
+    pub proposal_id: Option<u64>,
+    pub total_amount: Amount,
+    pub management_fee: Amount,
+    pub per_token_amount: Amount,
     pub distribution_date: u64,
     pub payment_status: PaymentStatus,
     pub recipients: Vec<DividendRecipient>,
 }
 
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum DistributionKind {
+    Dividend,
+    SaleProceeds,
+}
+
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub struct DividendRecipient {
     pub investor: Principal,
     pub token_amount: u64,
-    pub dividend_amount: u64,
+    pub dividend_amount: Amount,
     pub paid: bool,
 }
 
@@ -100,32 +126,46 @@ pub enum PaymentStatus {
     Failed,
 }
 
+/// Totals and performance are in `display_currency`, converted at current exchange
+/// rates. Per-property figures stay in each property's settlement asset.
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub struct Portfolio {
     pub owner: Principal,
-    pub total_value: u64,
+    /// The currency of the signed figures in `performance_metrics`.
+    pub display_currency: Currency,
+    pub total_value: Money,
     pub total_tokens: u64,
     pub properties: Vec<PortfolioProperty>,
-    pub total_dividends_received: u64,
+    pub total_dividends_received: Money,
     pub performance_metrics: PerformanceMetrics,
+    pub last_updated: u64,
 }
 
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub struct PortfolioProperty {
     pub property_id: u64,
+    pub settlement_asset: Principal,
     pub token_amount: u64,
-    pub initial_investment: u64,
-    pub current_value: u64,
-    pub dividends_received: u64,
+    pub initial_investment: Amount,
+    pub cost_basis: Amount,
+    pub current_value: Amount,
+    pub current_value_display: Money,
+    pub realized_pnl: i64,
+    pub unrealized_pnl: i64,
+    pub dividends_received: Amount,
     pub purchase_date: u64,
 }
 
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub struct PerformanceMetrics {
-    pub total_return: f64,
-    pub annual_yield: f64,
-    pub roi_percentage: f64,
-    pub diversification_score: f64,
+    pub total_return: i64,
+    pub annual_yield: Rate,
+    pub roi_percentage: Rate,
+    pub diversification_score: Rate,
+    pub realized_pnl: i64,
+    pub unrealized_pnl: i64,
+    pub dividend_yield: Rate,
+    pub time_weighted_return: Rate,
 }
 
 #[derive(CandidType, Clone, Serialize, Deserialize)]
@@ -136,12 +176,46 @@ pub struct GovernanceProposal {
     pub title: String,
     pub description: String,
     pub proposal_type: ProposalType,
+    pub action: ProposalAction,
     pub voting_power_required: u64,
+    pub approval_threshold_bps: u32,
+    pub eligible_supply: u64,
     pub votes_for: u64,
     pub votes_against: u64,
     pub status: ProposalStatus,
+    pub outcome: Option<ProposalOutcome>,
     pub created_at: u64,
+    pub snapshot_at: u64,
     pub voting_ends_at: u64,
+    pub finalized_at: Option<u64>,
+    pub executable_at: Option<u64>,
+    pub executed_at: Option<u64>,
+    pub deposit: u64,
+    pub deposit_status: DepositStatus,
+    pub discussion_links: Vec<String>,
+    pub document_hashes: Vec<String>,
+    pub amendments: Vec<ProposalAmendment>,
+    pub voting_starts_at: u64,
+}
+
+/// Tokens the proposer locks while a proposal is open. Returned at finalization
+/// unless turnout missed quorum.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum DepositStatus {
+    Locked,
+    Refunded,
+    Slashed,
+}
+
+/// The content a proposal had before an amendment replaced it.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct ProposalAmendment {
+    pub title: String,
+    pub description: String,
+    pub action: ProposalAction,
+    pub discussion_links: Vec<String>,
+    pub document_hashes: Vec<String>,
+    pub amended_at: u64,
 }
 
 #[derive(CandidType, Clone, Serialize, Deserialize)]
@@ -150,6 +224,7 @@ pub enum ProposalType {
     PropertySale,
     ManagementChange,
     DividendDistribution,
+    CorporateAction,
     Other,
 }
 
@@ -161,6 +236,233 @@ pub enum ProposalStatus {
     Executed,
 }
 
+/// What a passed proposal does when executed; the variant determines the proposal type.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum ProposalAction {
+    PropertyMaintenance { estimated_cost: Amount },
+    PropertySale { sale_price: Amount },
+    ManagementChange { new_manager: Principal },
+    DividendDistribution { total_amount: Amount },
+    CorporateAction(CorporateActionKind),
+    Other,
+}
+
+/// Why a finalized proposal ended up `Passed` or `Rejected`.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum ProposalOutcome {
+    Approved,
+    QuorumNotMet,
+    Tied,
+    ApprovalNotMet,
+}
+
+/// Turnout and approval a proposal type needs, in basis points. Quorum is measured
+/// against the circulating supply; approval against the votes cast.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct ProposalThresholds {
+    pub quorum_bps: u32,
+    pub approval_bps: u32,
+}
+
+/// Platform fees. Rates apply to the settled amount; the listing fee is a flat
+/// charge per property, set in the platform currency and charged in the
+/// property's settlement asset.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct FeeConfig {
+    pub issuance_fee: Rate,
+    pub maker_fee: Rate,
+    pub taker_fee: Rate,
+    pub dividend_management_fee: Rate,
+    pub listing_fee: Amount,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum FeeSource {
+    Issuance,
+    Maker,
+    Taker,
+    DividendManagement,
+    Listing,
+}
+
+/// One fee collected into the treasury. `reference_id` is the investment, order,
+/// distribution or property the fee was charged on.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct FeeEntry {
+    pub id: u64,
+    pub source: FeeSource,
+    pub property_id: u64,
+    pub asset: Principal,
+    pub payer: Principal,
+    pub base_amount: Amount,
+    pub amount: Amount,
+    pub reference_id: u64,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct TreasuryWithdrawal {
+    pub id: u64,
+    pub asset: Principal,
+    pub amount: Amount,
+    pub recipient: Principal,
+    pub withdrawn_by: Principal,
+    pub timestamp: u64,
+    /// Set once the ledger has accepted the transfer.
+    pub block_index: Option<u64>,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct FeeSourceTotal {
+    pub source: FeeSource,
+    pub amount: Amount,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct FeeSummary {
+    pub asset: Principal,
+    pub total_collected: Amount,
+    pub total_withdrawn: Amount,
+    pub treasury_balance: Amount,
+    pub by_source: Vec<FeeSourceTotal>,
+}
+
+/// An investor's funds in one settlement asset. `locked` is escrowed for open buy
+/// orders and offering subscriptions and can't be spent or withdrawn.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct CashBalance {
+    pub owner: Principal,
+    pub asset: Principal,
+    pub available: Amount,
+    pub locked: Amount,
+}
+
+/// One movement on an investor's cash balance. Amounts are always positive; the
+/// kind says which way the money went.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct CashTransaction {
+    pub id: u64,
+    pub owner: Principal,
+    pub asset: Principal,
+    pub kind: CashTransactionKind,
+    pub amount: Amount,
+    /// The investment, order, offering round, distribution or property the movement belongs to,
+    /// or the ledger block index for deposits and withdrawals.
+    pub reference_id: u64,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum CashTransactionKind {
+    Deposit,
+    Withdrawal,
+    /// A withdrawal the ledger rejected, credited back.
+    WithdrawalRefund,
+    /// Tokens bought at issuance, fee included.
+    Investment,
+    /// The issuer's share of an issuance sale.
+    IssuanceProceeds,
+    EscrowLocked,
+    EscrowReleased,
+    TradePurchase,
+    TradeSale,
+    DistributionFunding,
+    DistributionReceived,
+    ListingFee,
+}
+
+/// An ICRC-1 ledger account.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+/// A payout's ledger arguments, fixed on the first attempt so that a retry is
+/// deduplicated by the ledger instead of paying twice. `amount` is what `to`
+/// receives; `fee` is the ledger fee on top.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct OutgoingTransfer {
+    pub ledger: Principal,
+    pub to: Account,
+    pub amount: Amount,
+    pub fee: Amount,
+    pub memo: u64,
+    pub created_at_time: u64,
+}
+
+/// A cash or treasury withdrawal whose ledger call ended without a definite
+/// answer. The funds stay debited until `reconcile_withdrawal` learns the outcome,
+/// or an admin settles it with `resolve_pending_withdrawal`.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct PendingWithdrawal {
+    /// The withdrawal's cash transaction, or its treasury withdrawal.
+    pub id: u64,
+    /// `None` for a treasury withdrawal.
+    pub owner: Option<Principal>,
+    /// Debited from the balance: the transfer amount plus the ledger fee.
+    pub debited: Amount,
+    pub transfer: OutgoingTransfer,
+    pub last_error: String,
+    pub last_attempt_at: u64,
+}
+
+/// A deposit recorded before its ledger pull, so that a pull with no definite
+/// answer can be retried with the same memo and `created_at_time` and credited
+/// once the ledger confirms it.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct PendingDeposit {
+    /// Also the pull's memo.
+    pub id: u64,
+    pub owner: Principal,
+    pub asset: Principal,
+    pub amount: Amount,
+    pub created_at_time: u64,
+    pub last_error: String,
+    pub last_attempt_at: u64,
+}
+
+/// A token properties can be priced in and orders settled in, identified by its
+/// ledger canister.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct SettlementAsset {
+    pub ledger: Principal,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl SettlementAsset {
+    pub fn currency(&self) -> Currency {
+        Currency {
+            code: self.symbol.clone(),
+            decimals: self.decimals,
+        }
+    }
+}
+
+/// Price of one whole unit of `code` (an asset symbol or display currency) in
+/// the platform currency, scaled by 10^8.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct ExchangeRate {
+    pub code: String,
+    pub rate: u64,
+    pub updated_by: Principal,
+    pub updated_at: u64,
+}
+
+/// Limits on who may propose and how much. Token thresholds are basis points of
+/// the property's circulating supply.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct GovernanceConfig {
+    pub min_proposer_bps: u32,
+    pub deposit_bps: u32,
+    pub max_active_proposals: u32,
+    pub max_title_chars: u32,
+    pub max_description_chars: u32,
+    pub max_attachments: u32,
+    pub review_period_hours: u64,
+}
+
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub struct KycVerification {
     pub user: Principal,
@@ -172,7 +474,8 @@ pub struct KycVerification {
     pub compliance_score: u32,
 }
 
-#[derive(CandidType, Clone, Serialize, Deserialize)]
+/// Ordered from least to most thoroughly verified.
+#[derive(CandidType, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum KycLevel {
     Basic,
     Standard,
@@ -188,11 +491,13 @@ pub struct UserProfile {
     pub kyc_status: KycStatus,
     pub kyc_verification: Option<KycVerification>,
     pub created_at: u64,
-    pub total_investments: u64,
-    pub investment_limit: u64,
+    pub total_investments: Amount,
+    pub investment_limit: Amount,
     pub accredited_investor: bool,
     pub jurisdiction: String,
     pub risk_profile: RiskProfile,
+    /// Currency code portfolios are valued in.
+    pub display_currency: String,
 }
 
 #[derive(CandidType, Clone, Serialize, Deserialize)]
@@ -203,56 +508,556 @@ pub enum KycStatus {
     Expired,
 }
 
-#[derive(CandidType, Clone, Serialize, Deserialize)]
+#[derive(CandidType, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RiskProfile {
     Conservative,
     Moderate,
     Aggressive,
 }
 
+/// Prices are in the property's settlement asset; trades quoted in other assets
+/// don't move them.
 #[derive(CandidType, Clone, Serialize, Deserialize)]
 pub struct MarketData {
     pub property_id: u64,
-    pub current_price: u64,
-    pub price_change_24h: f64,
+    pub current_price: Amount,
+    pub price_change_24h: Rate,
     pub trading_volume_24h: u64,
-    pub market_cap: u64,
-    pub liquidity_score: f64,
+    pub market_cap: Amount,
+    pub liquidity_score: Rate,
+    pub nav_per_token: Amount,
     pub last_updated: u64,
 }
 
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum AppraisalMethod {
+    SalesComparison,
+    IncomeCapitalization,
+    CostApproach,
+    Other,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum AppraisalStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// An independent valuation. It only changes the property's value once the
+/// property manager approves it.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Appraisal {
+    pub id: u64,
+    pub property_id: u64,
+    pub appraiser: Principal,
+    pub value: Amount,
+    pub method: AppraisalMethod,
+    pub report_hash: String,
+    pub appraised_at: u64,
+    pub submitted_at: u64,
+    pub status: AppraisalStatus,
+    pub reviewed_by: Option<Principal>,
+    pub reviewed_at: Option<u64>,
+}
+
+/// A valuer vetted by the platform. Only registered appraisers can submit appraisals.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Appraiser {
+    pub principal: Principal,
+    pub name: String,
+    pub license_number: String,
+    pub registered_by: Principal,
+    pub registered_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct AppraisalPayload {
+    pub property_id: u64,
+    pub value: Amount,
+    pub method: AppraisalMethod,
+    pub report_hash: String,
+    pub appraised_at: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum OfferingStatus {
+    Open,
+    /// The soft cap was met and tokens were issued.
+    Succeeded,
+    /// The soft cap was missed and every subscriber was refunded.
+    Failed,
+    Cancelled,
+}
+
+/// A time-boxed primary issuance at the property's token price. Caps are in the
+/// property's settlement asset; commitments beyond the hard cap are allocated
+/// pro rata when the round is finalized.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct OfferingRound {
+    pub id: u64,
+    pub property_id: u64,
+    pub price_per_token: Amount,
+    pub soft_cap: Amount,
+    pub hard_cap: Amount,
+    pub min_ticket: Amount,
+    pub starts_at: u64,
+    pub ends_at: u64,
+    pub status: OfferingStatus,
+    pub total_committed: Amount,
+    pub tokens_issued: u64,
+    pub created_by: Principal,
+    pub created_at: u64,
+    pub finalized_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct OfferingRoundPayload {
+    pub property_id: u64,
+    pub soft_cap: Amount,
+    pub hard_cap: Amount,
+    pub min_ticket: Amount,
+    pub starts_at: u64,
+    pub ends_at: u64,
+}
+
+/// A pre-sale window for one class of investor. An investor qualifies once their
+/// verified KYC level reaches `min_kyc_level` and, when `risk_profiles` isn't
+/// empty, their risk profile is one of those listed.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct AccessTier {
+    pub name: String,
+    pub min_kyc_level: KycLevel,
+    pub risk_profiles: Vec<RiskProfile>,
+    pub opens_at: u64,
+    /// Most one investor may buy at issuance during the pre-sale. `None` is uncapped.
+    pub allocation_cap: Option<Amount>,
+}
+
+/// Who may buy a property's tokens at issuance, directly or through an offering
+/// round. Until `public_sale_at` only allowlisted investors and members of an
+/// open tier can buy; after it the sale is open to every verified investor.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct OfferingAccess {
+    pub property_id: u64,
+    pub tiers: Vec<AccessTier>,
+    pub public_sale_at: u64,
+    pub updated_by: Principal,
+    pub updated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct OfferingAccessPayload {
+    pub tiers: Vec<AccessTier>,
+    pub public_sale_at: u64,
+}
+
+/// An investor admitted to a property's pre-sale from the start, whatever their tier.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct AllowlistEntry {
+    pub property_id: u64,
+    pub investor: Principal,
+    pub allocation_cap: Option<Amount>,
+    pub added_by: Principal,
+    pub added_at: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum CorporateActionKind {
+    /// Scales every balance and open order by `numerator / denominator` and the
+    /// token price by the inverse; above one is a split, below one a consolidation.
+    Split { numerator: u64, denominator: u64 },
+    /// Adds tokens to the unsold supply at the current token price. With a rights
+    /// period, only existing holders can buy until it ends, each up to their pro
+    /// rata share.
+    Issuance { token_amount: u64, rights_period_hours: Option<u64> },
+}
+
+/// Audit record of a corporate action, with the property's supply and price on
+/// either side of it.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct CorporateAction {
+    pub id: u64,
+    pub property_id: u64,
+    pub kind: CorporateActionKind,
+    /// The proposal that approved it, or `None` when the owner applied it directly.
+    pub proposal_id: Option<u64>,
+    pub applied_by: Principal,
+    pub total_tokens_before: u64,
+    pub total_tokens_after: u64,
+    pub price_per_token_before: Amount,
+    pub price_per_token_after: Amount,
+    pub holders_affected: u32,
+    pub orders_restated: u32,
+    pub applied_at: u64,
+}
+
+/// How a sold property was closed out: holders' tokens were burned in exchange for
+/// their share of the sale price, paid as a `SaleProceeds` distribution.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Redemption {
+    pub property_id: u64,
+    pub proposal_id: Option<u64>,
+    pub sale_price: Amount,
+    /// `None` when nobody held tokens and the owner kept the proceeds.
+    pub distribution_id: Option<u64>,
+    pub tokens_redeemed: u64,
+    pub holders: u32,
+    pub orders_cancelled: u32,
+    /// Sum of the holders' realized gains, in minor units of the settlement asset.
+    pub total_realized_gain: i64,
+    pub redeemed_at: u64,
+}
+
+/// One investor's commitment to a round. `committed` and `fee_escrowed` are held
+/// in escrow until the round is finalized.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub round_id: u64,
+    pub investor: Principal,
+    pub committed: Amount,
+    /// The issuance fee on `committed`, at the rate when the investor subscribed.
+    pub fee_escrowed: Amount,
+    pub tokens_allocated: u64,
+    pub refunded: Amount,
+    pub investment_id: Option<u64>,
+    pub subscribed_at: u64,
+}
+
+/// Open orders aggregated at one price.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct PriceLevel {
+    pub price: Amount,
+    pub token_amount: u64,
+    pub order_count: u32,
+}
+
+/// Bids best (highest) first, asks best (lowest) first. Spread and mid are only
+/// set when both sides have orders.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct OrderBook {
+    pub property_id: u64,
+    pub quote_asset: Principal,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+    pub best_bid: Option<Amount>,
+    pub best_ask: Option<Amount>,
+    pub spread: Option<Amount>,
+    pub mid_price: Option<Amount>,
+    pub liquidity_score: Rate,
+    pub timestamp: u64,
+}
+
+/// A filled order, kept for chart data and rolling market statistics.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    pub id: u64,
+    pub property_id: u64,
+    pub order_id: u64,
+    pub buyer: Principal,
+    pub seller: Principal,
+    pub price_per_token: Amount,
+    pub token_amount: u64,
+    pub total_price: Amount,
+    pub quote_asset: Principal,
+    pub timestamp: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum CandleInterval {
+    Hour,
+    Day,
+    Week,
+}
+
+/// OHLCV for one interval. `volume` is in tokens, `quote_volume` in money.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Candle {
+    pub property_id: u64,
+    pub interval: CandleInterval,
+    pub open_time: u64,
+    pub open: Amount,
+    pub high: Amount,
+    pub low: Amount,
+    pub close: Amount,
+    pub volume: u64,
+    pub quote_volume: Amount,
+    pub trade_count: u64,
+}
+
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct CreatePropertyPayload {
     pub title: String,
     pub description: String,
     pub location: String,
-    pub total_value: u64,
+    pub total_value: Amount,
     pub total_tokens: u64,
     pub property_type: PropertyType,
     pub images: Vec<String>,
     pub documents: Vec<String>,
-    pub rental_yield: f64,
+    pub rental_yield: Rate,
     pub property_highlights: Vec<String>,
     pub legal_structure: String,
+    pub settlement_asset: Principal,
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct CreateOrderPayload {
     pub property_id: u64,
     pub token_amount: u64,
-    pub price_per_token: u64,
+    pub price_per_token: Amount,
     pub order_type: OrderType,
     pub expires_in_hours: u64,
+    /// Defaults to the property's settlement asset.
+    pub quote_asset: Option<Principal>,
 }
 
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct CreateProposalPayload {
+    pub property_id: u64,
+    pub title: String,
+    pub description: String,
+    pub action: ProposalAction,
+    pub voting_duration_hours: u64,
+    pub discussion_links: Vec<String>,
+    pub document_hashes: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct AmendProposalPayload {
+    pub title: String,
+    pub description: String,
+    pub action: ProposalAction,
+    pub discussion_links: Vec<String>,
+    pub document_hashes: Vec<String>,
+}
+
+/// Money totals are in the platform currency. Running totals are converted at
+/// the rate in effect when each amount was recorded; fees at current rates.
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct PlatformStats {
     pub total_properties: u64,
     pub total_investments: u64,
     pub total_users: u64,
-    pub total_value_locked: u64,
-    pub total_trading_volume: u64,
+    pub total_value_locked: Money,
+    pub total_trading_volume: Money,
     pub active_orders: u64,
-    pub total_dividends_paid: u64,
-    pub platform_fee_collected: u64,
+    pub total_dividends_paid: Money,
+    pub platform_fee_collected: Money,
+}
+
+/// Cap-table and performance figures for one property. Concentration is measured
+/// over current balances: HHI on the 0-10000 scale, Gini from 0 (equal) to 10000
+/// basis points. Trailing figures cover the last 365 days.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct PropertyAnalytics {
+    pub property_id: u64,
+    pub holder_count: u64,
+    pub circulating_supply: u64,
+    pub largest_holder_share: Rate,
+    pub top_10_holder_share: Rate,
+    pub herfindahl_index: u64,
+    pub gini_coefficient: Rate,
+    pub tokens_traded_trailing: u64,
+    pub annual_turnover: Rate,
+    pub average_holding_period_days: u64,
+    pub advertised_yield: Rate,
+    pub realized_yield: Rate,
+    pub dividend_history: Vec<DividendPoint>,
+    pub price_history: Vec<Candle>,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct DividendPoint {
+    pub distribution_id: u64,
+    pub distribution_date: u64,
+    pub total_amount: Amount,
+    pub per_token_amount: Amount,
+}
+
+/// Platform totals as of the end of `day` (days since the Unix epoch, UTC).
+/// `trading_volume` is the volume traded during that day alone.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct PlatformSnapshot {
+    pub day: u64,
+    pub total_properties: u64,
+    pub total_investments: u64,
+    pub total_users: u64,
+    pub total_value_locked: Money,
+    pub total_trading_volume: Money,
+    pub trading_volume: Money,
+    pub active_orders: u64,
+    pub total_dividends_paid: Money,
+    pub platform_fee_collected: Money,
+    pub recorded_at: u64,
+}
+
+/// Sort order for list queries. Fields that don't apply to a record type
+/// (e.g. `Price` on proposals) fall back to `CreatedAt`.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum SortField {
+    CreatedAt,
+    Price,
+    Yield,
+    Value,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+/// Position of the last item returned; pass it back to continue after that item.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct PageCursor {
+    pub sort_key: u64,
+    pub id: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize, Default)]
+pub struct PageRequest {
+    pub limit: Option<u32>,
+    pub cursor: Option<PageCursor>,
+    pub sort_by: Option<SortField>,
+    pub direction: Option<SortDirection>,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<PageCursor>,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct AmountRange {
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+/// Rate range in basis points, e.g. `min: Some(500)` for a yield of at least 5%.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct RateRange {
+    pub min: Option<Rate>,
+    pub max: Option<Rate>,
+}
+
+/// All set filters must match. `location` and `text` are split into words, and
+/// each word must begin a word of the property's, ignoring case: "Franc" or
+/// "san fran" finds "San Francisco, CA" and "ca" matches the state, but "cisco"
+/// finds nothing, since matching doesn't reach into the middle of a word.
+#[derive(CandidType, Clone, Serialize, Deserialize, Default)]
+pub struct PropertySearchFilter {
+    pub property_type: Option<PropertyType>,
+    pub status: Option<PropertyStatus>,
+    pub location: Option<String>,
+    pub total_value: Option<AmountRange>,
+    pub price_per_token: Option<AmountRange>,
+    pub rental_yield: Option<RateRange>,
+    pub appreciation_rate: Option<RateRange>,
+    pub available_tokens: Option<AmountRange>,
+    pub text: Option<String>,
+}
+
+/// Tokens acquired in one purchase, tracked separately so disposals can be
+/// matched against a specific acquisition price and date.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct TaxLot {
+    pub id: u64,
+    pub owner: Principal,
+    pub property_id: u64,
+    pub investment_id: u64,
+    pub quantity: u64,
+    pub remaining_quantity: u64,
+    pub cost_basis: Amount,
+    pub remaining_cost_basis: Amount,
+    pub acquired_at: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub enum CostBasisMethod {
+    Fifo,
+    Lifo,
+    AverageCost,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct LotDisposal {
+    pub lot_id: u64,
+    pub quantity: u64,
+    pub cost_basis: Amount,
+    pub acquired_at: u64,
+}
+
+/// Gain or loss on one trade, split by the lots it consumed.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct RealizedGain {
+    pub id: u64,
+    pub owner: Principal,
+    pub property_id: u64,
+    pub order_id: u64,
+    pub quantity: u64,
+    pub proceeds: Amount,
+    pub cost_basis: Amount,
+    pub gain: i64,
+    pub method: CostBasisMethod,
+    pub lots: Vec<LotDisposal>,
+    pub disposed_at: u64,
+}
+
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct TaxStatement {
+    pub owner: Principal,
+    pub year: u32,
+    pub proceeds: Amount,
+    pub cost_basis: Amount,
+    pub realized_gain: i64,
+    pub short_term_gain: i64,
+    pub long_term_gain: i64,
+    pub dividends_received: Amount,
+    pub disposals: Vec<RealizedGain>,
+}
+
+/// A holder's recorded vote. `weight` is what was added to the tally, including
+/// the `delegated` weight, and is reversed if the vote is changed.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Vote {
+    pub proposal_id: u64,
+    pub voter: Principal,
+    pub weight: u64,
+    pub delegated: Vec<DelegatedWeight>,
+    pub vote_for: bool,
+    pub timestamp: u64,
+}
+
+/// Weight a delegate cast on behalf of one delegator.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct DelegatedWeight {
+    pub delegator: Principal,
+    pub weight: u64,
+}
+
+/// Hands a holder's voting power to `delegate`, either for one property or, when
+/// `property_id` is `None`, for every property without a more specific delegation.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct Delegation {
+    pub delegator: Principal,
+    pub delegate: Principal,
+    pub property_id: Option<u64>,
+    pub created_at: u64,
+}
+
+/// Install and upgrade argument. Only read when upgrading data written before
+/// stored records were versioned, which names no settlement asset of its own.
+#[derive(CandidType, Clone, Serialize, Deserialize, Default)]
+pub struct UpgradeArgs {
+    pub legacy_settlement_asset: Option<LegacySettlementAsset>,
+}
+
+/// The asset unversioned properties, orders and amounts are denominated in, and
+/// its price in the platform currency.
+#[derive(CandidType, Clone, Serialize, Deserialize)]
+pub struct LegacySettlementAsset {
+    pub asset: SettlementAsset,
+    pub rate: u64,
 }
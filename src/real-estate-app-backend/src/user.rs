@@ -1,9 +1,16 @@
 use ic_cdk_macros::*;
 use candid::Principal;
+use crate::analytics::record_snapshot;
+use crate::exchange::display_currency;
+use crate::money::PLATFORM_CURRENCY_CODE;
+use crate::portfolio::refresh_portfolio;
 use crate::storage::USER_STORAGE;
 use crate::types::*;
 use crate::utils::{get_current_time, is_authenticated};
 
+/// $100,000 in minor units.
+const DEFAULT_INVESTMENT_LIMIT: Amount = Amount::from_minor(10_000_000);
+
 #[update]
 pub fn create_user_profile(name: String, email: String) -> Result<UserProfile, String> {
     let caller = is_authenticated()?;
@@ -19,16 +26,18 @@ pub fn create_user_profile(name: String, email: String) -> Result<UserProfile, S
         kyc_status: KycStatus::Pending,
         kyc_verification: None,
         created_at: get_current_time(),
-        total_investments: 0,
-        investment_limit: 100_000,
+        total_investments: Amount::ZERO,
+        investment_limit: DEFAULT_INVESTMENT_LIMIT,
         accredited_investor: false,
         jurisdiction: "Unknown".to_string(),
         risk_profile: RiskProfile::Conservative,
+        display_currency: PLATFORM_CURRENCY_CODE.to_string(),
     };
 
     USER_STORAGE.with(|storage| {
         storage.borrow_mut().insert(caller, user_profile.clone())
     });
+    record_snapshot();
 
     Ok(user_profile)
 }
@@ -40,3 +49,19 @@ pub fn get_user_profile(user: Principal) -> Result<UserProfile, String> {
             .ok_or_else(|| "User profile not found".to_string())
     })
 }
+
+/// Sets the currency the caller's portfolio is valued in.
+#[update]
+pub fn set_display_currency(code: String) -> Result<UserProfile, String> {
+    let caller = is_authenticated()?;
+    display_currency(&code)?;
+
+    let mut profile = get_user_profile(caller)?;
+    profile.display_currency = code;
+    USER_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(caller, profile.clone())
+    });
+    refresh_portfolio(caller);
+
+    Ok(profile)
+}
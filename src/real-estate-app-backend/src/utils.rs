@@ -1,7 +1,9 @@
 use candid::Principal;
 use crate::storage::USER_STORAGE;
-use crate::types::KycStatus;
+use crate::types::{KycStatus, Page, PageCursor, PageRequest, Rate, SortDirection, SortField};
+use crate::storage::MAX_TERM_CHARS;
 use ic_cdk::api::time;
+use std::collections::BTreeSet;
 
 pub fn is_authenticated() -> Result<Principal, String> {
     let caller = ic_cdk::caller();
@@ -12,6 +14,16 @@ pub fn is_authenticated() -> Result<Principal, String> {
     }
 }
 
+/// Canister controllers administer platform-wide settings.
+pub fn is_admin() -> Result<Principal, String> {
+    let caller = is_authenticated()?;
+    if ic_cdk::api::is_controller(&caller) {
+        Ok(caller)
+    } else {
+        Err("Admin access required".to_string())
+    }
+}
+
 pub fn validate_kyc(user_principal: Principal) -> Result<(), String> {
     USER_STORAGE.with(|storage| {
         match storage.borrow().get(&user_principal) {
@@ -29,3 +41,199 @@ pub fn validate_kyc(user_principal: Principal) -> Result<(), String> {
 pub fn get_current_time() -> u64 {
     time()
 }
+
+pub const BASIS_POINTS: u64 = 10_000;
+
+pub const DEFAULT_PAGE_LIMIT: u32 = 50;
+pub const MAX_PAGE_LIMIT: u32 = 200;
+
+/// Records that can be returned through a `Page`.
+pub trait Paginated {
+    fn id(&self) -> u64;
+    fn sort_key(&self, field: &SortField) -> u64;
+}
+
+pub fn page_limit(request: &PageRequest) -> usize {
+    request.limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT) as usize
+}
+
+pub fn is_descending(request: &PageRequest) -> bool {
+    matches!(request.direction, Some(SortDirection::Descending))
+}
+
+/// Yields can't be negative; sort on their basis points.
+pub fn yield_sort_key(rental_yield: Rate) -> u64 {
+    rental_yield.bps().max(0) as u64
+}
+
+/// Signed rates (e.g. appreciation) shifted so negative values still order
+/// before positive ones in an unsigned index key.
+pub fn signed_rate_key(rate: Rate) -> u64 {
+    (rate.bps() as u64) ^ (1 << 63)
+}
+
+/// Splits free text into lowercase alphanumeric search terms.
+pub fn tokenize(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase().chars().take(MAX_TERM_CHARS).collect())
+        .collect()
+}
+
+/// A hex-encoded SHA-256 digest, as used for document hashes.
+pub fn is_sha256_hex(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Sorts an already-narrowed result set in memory and cuts one page out of it.
+pub fn paginate<T: Paginated>(items: Vec<T>, request: &PageRequest) -> Page<T> {
+    let field = request.sort_by.clone().unwrap_or(SortField::CreatedAt);
+    let descending = is_descending(request);
+
+    let mut keyed: Vec<((u64, u64), T)> = items
+        .into_iter()
+        .map(|item| ((item.sort_key(&field), item.id()), item))
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    if descending {
+        keyed.reverse();
+    }
+
+    let after = request.cursor.as_ref().map(|cursor| (cursor.sort_key, cursor.id));
+    let mut remaining = keyed.into_iter().filter(|(key, _)| match after {
+        Some(after) if descending => *key < after,
+        Some(after) => *key > after,
+        None => true,
+    });
+
+    let limit = page_limit(request);
+    let page: Vec<((u64, u64), T)> = remaining.by_ref().take(limit).collect();
+    let next_cursor = match (page.last(), remaining.next()) {
+        (Some(((sort_key, id), _)), Some(_)) => Some(PageCursor { sort_key: *sort_key, id: *id }),
+        _ => None,
+    };
+
+    Page {
+        items: page.into_iter().map(|(_, item)| item).collect(),
+        next_cursor,
+    }
+}
+
+/// `value * numerator / denominator` without intermediate overflow, rounding down.
+pub fn mul_div(value: u64, numerator: u64, denominator: u64) -> u64 {
+    if denominator == 0 {
+        return 0;
+    }
+    (value as u128 * numerator as u128 / denominator as u128) as u64
+}
+
+/// `value * numerator / denominator` when it divides evenly and fits in a `u64`.
+pub fn mul_div_exact(value: u64, numerator: u64, denominator: u64) -> Option<u64> {
+    let product = value as u128 * numerator as u128;
+    if denominator == 0 || !product.is_multiple_of(denominator as u128) {
+        return None;
+    }
+    u64::try_from(product / denominator as u128).ok()
+}
+
+pub const NANOS_PER_DAY: u64 = 24 * 3600 * 1_000_000_000;
+
+/// Start (inclusive) and end (exclusive) of a UTC calendar year in nanoseconds.
+pub fn year_bounds(year: u32) -> (u64, u64) {
+    (
+        days_since_epoch(year as i64) as u64 * NANOS_PER_DAY,
+        days_since_epoch(year as i64 + 1) as u64 * NANOS_PER_DAY,
+    )
+}
+
+/// Days from 1970-01-01 to January 1st of `year` in the proleptic Gregorian calendar,
+/// clamped to zero for earlier years.
+fn days_since_epoch(year: i64) -> i64 {
+    if year <= 1970 {
+        return 0;
+    }
+    let leap_days = |y: i64| y / 4 - y / 100 + y / 400;
+    (year - 1970) * 365 + leap_days(year - 1) - leap_days(1969)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Item {
+        id: u64,
+        key: u64,
+    }
+
+    impl Paginated for Item {
+        fn id(&self) -> u64 {
+            self.id
+        }
+
+        fn sort_key(&self, _field: &SortField) -> u64 {
+            self.key
+        }
+    }
+
+    fn items(keys: &[(u64, u64)]) -> Vec<Item> {
+        keys.iter().map(|(id, key)| Item { id: *id, key: *key }).collect()
+    }
+
+    fn request(limit: u32, cursor: Option<PageCursor>, direction: SortDirection) -> PageRequest {
+        PageRequest {
+            limit: Some(limit),
+            cursor,
+            sort_by: None,
+            direction: Some(direction),
+        }
+    }
+
+    /// Follows `next_cursor` until it runs out and returns the ids of every page.
+    fn walk(keys: &[(u64, u64)], limit: u32, direction: SortDirection) -> Vec<Vec<u64>> {
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = paginate(items(keys), &request(limit, cursor, direction.clone()));
+            pages.push(page.items.iter().map(|item| item.id).collect());
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    const KEYS: [(u64, u64); 5] = [(1, 30), (2, 10), (3, 20), (4, 10), (5, 30)];
+
+    #[test]
+    fn cursors_walk_every_item_once_in_key_then_id_order() {
+        assert_eq!(walk(&KEYS, 2, SortDirection::Ascending), vec![vec![2, 4], vec![3, 1], vec![5]]);
+        assert_eq!(walk(&KEYS, 2, SortDirection::Descending), vec![vec![5, 1], vec![3, 4], vec![2]]);
+    }
+
+    #[test]
+    fn a_page_that_ends_the_results_has_no_cursor() {
+        assert_eq!(walk(&KEYS, 5, SortDirection::Ascending), vec![vec![2, 4, 3, 1, 5]]);
+        assert_eq!(walk(&[], 5, SortDirection::Ascending), vec![Vec::<u64>::new()]);
+    }
+
+    #[test]
+    fn a_cursor_past_a_removed_item_still_continues_after_it() {
+        let remaining = [(1, 30), (2, 10), (3, 20), (5, 30)];
+        let cursor = PageCursor { sort_key: 10, id: 4 };
+        let page = paginate(items(&remaining), &request(10, Some(cursor), SortDirection::Ascending));
+        assert_eq!(page.items.iter().map(|item| item.id).collect::<Vec<u64>>(), vec![3, 1, 5]);
+    }
+
+    #[test]
+    fn limits_are_clamped() {
+        assert_eq!(page_limit(&PageRequest::default()), DEFAULT_PAGE_LIMIT as usize);
+        assert_eq!(page_limit(&request(0, None, SortDirection::Ascending)), 1);
+        assert_eq!(page_limit(&request(u32::MAX, None, SortDirection::Ascending)), MAX_PAGE_LIMIT as usize);
+    }
+
+    #[test]
+    fn extreme_sort_keys_page_without_overflow() {
+        let keys = [(u64::MAX, u64::MAX), (0, 0), (1, u64::MAX)];
+        assert_eq!(walk(&keys, 1, SortDirection::Ascending), vec![vec![0], vec![1], vec![u64::MAX]]);
+    }
+}
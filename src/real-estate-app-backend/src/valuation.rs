@@ -0,0 +1,254 @@
+use candid::Principal;
+use ic_cdk_macros::*;
+use crate::market_data::refresh_nav;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::{get_current_time, is_admin, is_authenticated, is_sha256_hex};
+
+const NANOS_PER_YEAR: f64 = 365.0 * 24.0 * 3600.0 * 1_000_000_000.0;
+const MAX_APPRAISER_FIELD_CHARS: usize = 100;
+
+/// Adds an appraiser to the registry, or updates their details.
+#[update]
+pub fn register_appraiser(appraiser: Principal, name: String, license_number: String) -> Result<Appraiser, String> {
+    let caller = is_admin()?;
+
+    if appraiser == Principal::anonymous() {
+        return Err("Cannot register the anonymous principal".to_string());
+    }
+    for field in [&name, &license_number] {
+        if field.trim().is_empty() || field.chars().count() > MAX_APPRAISER_FIELD_CHARS {
+            return Err(format!("Name and license number must be 1-{} characters", MAX_APPRAISER_FIELD_CHARS));
+        }
+    }
+
+    let appraiser = Appraiser {
+        principal: appraiser,
+        name,
+        license_number,
+        registered_by: caller,
+        registered_at: get_current_time(),
+    };
+    APPRAISER_STORAGE.with(|storage| {
+        storage.borrow_mut().insert(appraiser.principal, appraiser.clone())
+    });
+
+    Ok(appraiser)
+}
+
+/// Takes an appraiser off the registry. Their pending appraisals can no longer
+/// be approved; reviewed ones stay in the history.
+#[update]
+pub fn remove_appraiser(appraiser: Principal) -> Result<Appraiser, String> {
+    is_admin()?;
+    APPRAISER_STORAGE.with(|storage| storage.borrow_mut().remove(&appraiser))
+        .ok_or_else(|| "Appraiser not found".to_string())
+}
+
+#[query]
+pub fn get_appraisers() -> Vec<Appraiser> {
+    APPRAISER_STORAGE.with(|storage| {
+        storage.borrow().iter().map(|(_, appraiser)| appraiser).collect()
+    })
+}
+
+#[update]
+pub fn submit_appraisal(payload: AppraisalPayload) -> Result<Appraisal, String> {
+    let caller = is_authenticated()?;
+    if !is_registered_appraiser(caller) {
+        return Err("Only registered appraisers can submit appraisals".to_string());
+    }
+
+    let property = load_property(payload.property_id)?;
+
+    if payload.value.is_zero() {
+        return Err("Appraised value must be greater than zero".to_string());
+    }
+    if !is_sha256_hex(&payload.report_hash) {
+        return Err("Report hash must be a hex-encoded SHA-256 digest".to_string());
+    }
+    let now = get_current_time();
+    if payload.appraised_at > now || payload.appraised_at < property.created_at {
+        return Err("Appraisal date must be between listing and now".to_string());
+    }
+
+    let appraisal = Appraisal {
+        id: get_next_id(),
+        property_id: payload.property_id,
+        appraiser: caller,
+        value: payload.value,
+        method: payload.method,
+        report_hash: payload.report_hash,
+        appraised_at: payload.appraised_at,
+        submitted_at: now,
+        status: AppraisalStatus::Pending,
+        reviewed_by: None,
+        reviewed_at: None,
+    };
+
+    APPRAISAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((appraisal.property_id, appraisal.id), appraisal.clone())
+    });
+
+    Ok(appraisal)
+}
+
+/// Approving the most recent appraisal revalues the property: `total_value`,
+/// `valuation_date`, `appreciation_rate` and the published NAV per token all follow
+/// it. Older appraisals are kept in the history and feed the appreciation rate.
+#[update]
+pub fn approve_appraisal(property_id: u64, appraisal_id: u64) -> Result<Appraisal, String> {
+    let appraisal = review_appraisal(property_id, appraisal_id, AppraisalStatus::Approved)?;
+
+    let mut property = load_property(property_id)?;
+    if appraisal.appraised_at >= property.valuation_date {
+        property.total_value = appraisal.value;
+        property.valuation_date = appraisal.appraised_at;
+    }
+    property.appreciation_rate = appreciation_rate(&property);
+    property.updated_at = get_current_time();
+    insert_property(property);
+    refresh_nav(property_id);
+
+    Ok(appraisal)
+}
+
+#[update]
+pub fn reject_appraisal(property_id: u64, appraisal_id: u64) -> Result<Appraisal, String> {
+    review_appraisal(property_id, appraisal_id, AppraisalStatus::Rejected)
+}
+
+/// All appraisals for the property, oldest submission first.
+#[query]
+pub fn get_appraisals(property_id: u64) -> Vec<Appraisal> {
+    APPRAISAL_STORAGE.with(|storage| {
+        storage.borrow()
+            .range((property_id, 0)..=(property_id, u64::MAX))
+            .map(|(_, appraisal)| appraisal)
+            .collect()
+    })
+}
+
+fn review_appraisal(property_id: u64, appraisal_id: u64, status: AppraisalStatus) -> Result<Appraisal, String> {
+    let caller = is_authenticated()?;
+
+    let property = load_property(property_id)?;
+    if caller != property.manager {
+        return Err("Only the property manager can review appraisals".to_string());
+    }
+
+    let mut appraisal = APPRAISAL_STORAGE.with(|storage| storage.borrow().get(&(property_id, appraisal_id)))
+        .ok_or_else(|| "Appraisal not found".to_string())?;
+    if !matches!(appraisal.status, AppraisalStatus::Pending) {
+        return Err("Appraisal has already been reviewed".to_string());
+    }
+    if appraisal.appraiser == caller {
+        return Err("Appraisers cannot review their own appraisals".to_string());
+    }
+    if matches!(status, AppraisalStatus::Approved) && !is_registered_appraiser(appraisal.appraiser) {
+        return Err("The appraiser is no longer registered".to_string());
+    }
+
+    appraisal.status = status;
+    appraisal.reviewed_by = Some(caller);
+    appraisal.reviewed_at = Some(get_current_time());
+
+    APPRAISAL_STORAGE.with(|storage| {
+        storage.borrow_mut().insert((property_id, appraisal_id), appraisal.clone())
+    });
+
+    Ok(appraisal)
+}
+
+/// Annual growth fitted across the listing value (issue price times supply, at
+/// listing) and every approved appraisal, so one outlier can't set the rate on its
+/// own. Histories spanning less than a year report the fitted growth over the span
+/// rather than extrapolating it. The fit needs `ln` and `exp`, so this is the one
+/// stored figure computed in `f64` rather than fixed point.
+fn appreciation_rate(property: &Property) -> Rate {
+    let baseline = property.price_per_token.times(property.total_tokens).unwrap_or(Amount::ZERO);
+    if baseline.is_zero() {
+        return Rate::ZERO;
+    }
+
+    let mut points = vec![(property.created_at, baseline)];
+    points.extend(
+        get_appraisals(property.id)
+            .into_iter()
+            .filter(|appraisal| matches!(appraisal.status, AppraisalStatus::Approved))
+            .map(|appraisal| (appraisal.appraised_at, appraisal.value)),
+    );
+    fitted_growth(&points).map_or(Rate::ZERO, Rate::from_growth_factor)
+}
+
+/// Least-squares fit of log value against time over `(timestamp, value)` points,
+/// returned as a growth factor per year, or over the whole span when it's shorter.
+/// `None` without two distinct instants to fit.
+fn fitted_growth(points: &[(u64, Amount)]) -> Option<f64> {
+    let points: Vec<(f64, f64)> = points
+        .iter()
+        .filter(|(_, value)| !value.is_zero())
+        .map(|(at, value)| (*at as f64 / NANOS_PER_YEAR, (value.minor_units() as f64).ln()))
+        .collect();
+    let first = points.iter().map(|(years, _)| *years).fold(f64::INFINITY, f64::min);
+    let last = points.iter().map(|(years, _)| *years).fold(f64::NEG_INFINITY, f64::max);
+    let span = last - first;
+    if points.len() < 2 || span <= 0.0 {
+        return None;
+    }
+
+    let count = points.len() as f64;
+    let mean_years = points.iter().map(|(years, _)| years).sum::<f64>() / count;
+    let mean_log = points.iter().map(|(_, log)| log).sum::<f64>() / count;
+    let covariance: f64 = points.iter().map(|(years, log)| (years - mean_years) * (log - mean_log)).sum();
+    let variance: f64 = points.iter().map(|(years, _)| (years - mean_years).powi(2)).sum();
+    let slope = covariance / variance;
+
+    Some((slope * span.min(1.0)).exp())
+}
+
+fn load_property(property_id: u64) -> Result<Property, String> {
+    PROPERTY_STORAGE.with(|storage| {
+        storage.borrow().get(&property_id)
+            .ok_or_else(|| "Property not found".to_string())
+    })
+}
+
+fn is_registered_appraiser(principal: Principal) -> bool {
+    APPRAISER_STORAGE.with(|storage| storage.borrow().contains_key(&principal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YEAR: u64 = 365 * 24 * 3600 * 1_000_000_000;
+
+    fn growth_bps(points: &[(u64, u64)]) -> Option<i64> {
+        let points: Vec<(u64, Amount)> = points.iter().map(|(at, value)| (*at, Amount::from_minor(*value))).collect();
+        fitted_growth(&points).map(|factor| Rate::from_growth_factor(factor).bps())
+    }
+
+    #[test]
+    fn steady_growth_is_annualized() {
+        assert_eq!(growth_bps(&[(0, 1_000_000), (YEAR, 1_100_000), (2 * YEAR, 1_210_000)]), Some(1_000));
+    }
+
+    #[test]
+    fn earlier_appraisals_temper_an_outlier() {
+        let latest_only = growth_bps(&[(0, 1_000_000), (3 * YEAR, 2_000_000)]).unwrap();
+        let history = growth_bps(&[(0, 1_000_000), (YEAR, 1_100_000), (2 * YEAR, 1_210_000), (3 * YEAR, 2_000_000)]).unwrap();
+        assert!(history < latest_only);
+    }
+
+    #[test]
+    fn short_histories_are_not_extrapolated() {
+        assert_eq!(growth_bps(&[(0, 1_000_000), (YEAR / 2, 1_050_000)]), Some(500));
+    }
+
+    #[test]
+    fn needs_two_instants() {
+        assert_eq!(growth_bps(&[(YEAR, 1_000_000)]), None);
+        assert_eq!(growth_bps(&[(YEAR, 1_000_000), (YEAR, 1_200_000)]), None);
+    }
+}